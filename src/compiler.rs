@@ -1,38 +1,84 @@
+use crate::diagnostic::{self, Diagnostic};
 use crate::evaluator;
+use crate::location::Location;
 use crate::typer;
 use std::collections::HashMap;
 
 type IRIter = <typer::TypedChunk as IntoIterator>::IntoIter;
 
-pub fn compile(ir_chunks: typer::TypedChunks) -> Result<evaluator::Program, String> {
+pub fn compile(ir_chunks: typer::TypedChunks) -> Result<evaluator::Program, Diagnostic> {
     // @NOTE:
     // We're assumming that we typecheck!
     //
 
     let mut compiler = Compiler::new();
 
+    // functions get their ids up front so they can be called before the
+    // function itself has been compiled
+    for chunk in &ir_chunks {
+        if let Some(typer::TypedIR {
+            kind: typer::TypedIRKind::Def(name),
+            ..
+        }) = chunk.first()
+        {
+            compiler.declare_function(name.clone());
+        }
+    }
+
     for chunk in ir_chunks {
         let mut ir = chunk.into_iter();
         match ir.next().expect("We filter out empty chunks in the parser") {
             typer::TypedIR {
                 kind: typer::TypedIRKind::Def(ident),
+                ..
             } => compiler.compile_function(ident.clone(), &mut ir)?,
             typer::TypedIR {
                 kind: typer::TypedIRKind::Var,
+                ..
             } => compiler.compile_variable(&mut ir)?,
             _ => unreachable!(),
         }
     }
 
-    println!("{:#?}\n---------", compiler.program);
+    Ok(compiler.program)
+}
+
+pub fn compile_constant(ir: typer::TypedChunk) -> Result<evaluator::Program, Diagnostic> {
+    // The body of a constant is compiled into the global function so that
+    // evaluating it leaves its value on the data stack.
+    let mut compiler = Compiler::new();
+
+    let mut ir = ir.into_iter();
+    while let Some(i) = ir.next() {
+        compiler.compile_expression(i, &mut ir)?;
+    }
 
     Ok(compiler.program)
 }
 
+// Where `continue` jumps to if it's already known and the jumps to patch
+// once the loop is compiled
+struct Loop {
+    start: Option<usize>,
+    continues: Vec<usize>,
+    breaks: Vec<usize>,
+}
+
+impl Loop {
+    fn new(start: Option<usize>) -> Self {
+        Self {
+            start,
+            continues: Vec::new(),
+            breaks: Vec::new(),
+        }
+    }
+}
+
 struct Compiler {
     program: evaluator::Program,
     function_map: HashMap<String, usize>,
     function_stack: Vec<usize>,
+    loops: Vec<Loop>,
 }
 
 impl Compiler {
@@ -41,9 +87,12 @@ impl Compiler {
             program: evaluator::Program::new(),
             function_map: HashMap::new(),
             function_stack: Vec::new(),
+            loops: Vec::new(),
         };
 
-        s.program.functions.push(evaluator::Function::new());
+        s.program
+            .functions
+            .push(evaluator::Function::new("<global>".to_string()));
         s.function_stack.push(0);
 
         s
@@ -56,20 +105,30 @@ impl Compiler {
             .expect("We should have at least one function on the stack!")
     }
 
-    fn add_function(&mut self, name: String) {
+    fn declare_function(&mut self, name: String) -> usize {
         let function_id = self.program.functions.len();
-        self.program.functions.push(evaluator::Function::new());
+        self.program
+            .functions
+            .push(evaluator::Function::new(name.clone()));
 
         if name == "main" {
             self.program.set_entry_index(function_id);
         }
 
         self.function_map.insert(name, function_id);
+        function_id
+    }
+
+    fn add_function(&mut self, name: String) {
+        let function_id = match self.get_function_id(&name) {
+            Some(function_id) => function_id,
+            None => self.declare_function(name),
+        };
         self.function_stack.push(function_id);
     }
 
     fn get_function_id(&self, name: &String) -> Option<usize> {
-        self.function_map.get(name).map(|id| *id)
+        self.function_map.get(name).copied()
     }
 }
 
@@ -93,12 +152,10 @@ impl Compiler {
             .code
             .push(evaluator::Instruction::PushInt as u64);
 
-        current_function
-            .code
-            .push(unsafe { std::mem::transmute::<i64, u64>(value) });
+        current_function.code.push(value as u64);
     }
 
-    fn emit_push_str(&mut self, value: &str) -> Result<(), String> {
+    fn emit_push_str(&mut self, value: &str) -> Result<(), Diagnostic> {
         let index = self.program.add_string_constant(value)?;
 
         let current_function_id = self.current_function_id();
@@ -112,6 +169,16 @@ impl Compiler {
         Ok(())
     }
 
+    // Remembers where the code emitted from here on came from so runtime
+    // errors can point back at the source
+    fn mark_location(&mut self, location: Location) {
+        let current_function_id = self.current_function_id();
+        let current_function = &mut self.program.functions[current_function_id];
+
+        let offset = current_function.code.len();
+        current_function.locations.push((offset, location));
+    }
+
     fn emit_instruction(&mut self, instruction: evaluator::Instruction) {
         let current_function_id = self.current_function_id();
         let current_function = &mut self.program.functions[current_function_id];
@@ -129,30 +196,113 @@ impl Compiler {
         current_function.code.push(function_id as u64);
     }
 
-    fn emit_jump(&mut self, jump: i64) {
+    fn emit_push_function(&mut self, function_id: usize) {
         let current_function_id = self.current_function_id();
         let current_function = &mut self.program.functions[current_function_id];
 
         current_function
             .code
-            .push(evaluator::Instruction::Jump as u64);
+            .push(evaluator::Instruction::PushFunction as u64);
+
+        current_function.code.push(function_id as u64);
+    }
+
+    fn emit_make_closure(&mut self, function_id: usize, ncaptures: usize) {
+        let current_function_id = self.current_function_id();
+        let current_function = &mut self.program.functions[current_function_id];
 
         current_function
             .code
-            .push(unsafe { std::mem::transmute::<i64, u64>(jump) });
+            .push(evaluator::Instruction::MakeClosure as u64);
+
+        current_function.code.push(function_id as u64);
+        current_function.code.push(ncaptures as u64);
     }
 
-    fn emit_jump_true(&mut self, jump: i64) {
+    fn emit_push_capture(&mut self, index: usize) {
         let current_function_id = self.current_function_id();
         let current_function = &mut self.program.functions[current_function_id];
 
         current_function
             .code
-            .push(evaluator::Instruction::JumpTrue as u64);
+            .push(evaluator::Instruction::PushCapture as u64);
+
+        current_function.code.push(index as u64);
+    }
+
+    fn emit_for(&mut self, instruction: evaluator::Instruction, id: usize, jump: i64) {
+        let current_function_id = self.current_function_id();
+        let current_function = &mut self.program.functions[current_function_id];
+
+        current_function.code.push(instruction as u64);
+
+        current_function.code.push(id as u64);
+        current_function.code.push(jump as u64);
+    }
+
+    fn emit_wide(&mut self, instruction: evaluator::Instruction, width: usize) {
+        let current_function_id = self.current_function_id();
+        let current_function = &mut self.program.functions[current_function_id];
+
+        current_function.code.push(instruction as u64);
+
+        current_function.code.push(width as u64);
+    }
+
+    fn emit_array(&mut self, instruction: evaluator::Instruction, len: usize, width: usize) {
+        let current_function_id = self.current_function_id();
+        let current_function = &mut self.program.functions[current_function_id];
+
+        current_function.code.push(instruction as u64);
+
+        current_function.code.push(len as u64);
+        current_function.code.push(width as u64);
+    }
+
+    fn emit_shuffle(&mut self, inputs: usize, outputs: Vec<usize>) {
+        let index = self
+            .program
+            .add_shuffle(evaluator::Shuffle { inputs, outputs });
+
+        let current_function_id = self.current_function_id();
+        let current_function = &mut self.program.functions[current_function_id];
+
+        current_function
+            .code
+            .push(evaluator::Instruction::Shuffle as u64);
+
+        current_function.code.push(index as u64);
+    }
+
+    fn emit_stack_index(&mut self, instruction: evaluator::Instruction, index: usize) {
+        let current_function_id = self.current_function_id();
+        let current_function = &mut self.program.functions[current_function_id];
+
+        current_function.code.push(instruction as u64);
+
+        current_function.code.push(index as u64);
+    }
+
+    fn emit_jump(&mut self, jump: i64) {
+        let current_function_id = self.current_function_id();
+        let current_function = &mut self.program.functions[current_function_id];
 
         current_function
             .code
-            .push(unsafe { std::mem::transmute::<i64, u64>(jump) });
+            .push(evaluator::Instruction::Jump as u64);
+
+        current_function.code.push(jump as u64);
+    }
+
+    fn emit_jump_true(&mut self, jump: i64) {
+        let current_function_id = self.current_function_id();
+        let current_function = &mut self.program.functions[current_function_id];
+
+        current_function
+            .code
+            .push(evaluator::Instruction::JumpTrue as u64);
+
+        current_function.code.push(jump as u64);
     }
 
     fn emit_jump_false(&mut self, jump: i64) {
@@ -163,16 +313,59 @@ impl Compiler {
             .code
             .push(evaluator::Instruction::JumpFalse as u64);
 
+        current_function.code.push(jump as u64);
+    }
+
+    fn patch_jump(&mut self, jump_index: usize) {
+        let target = self.current_offset();
+        self.patch_jump_to(jump_index, target);
+    }
+
+    fn patch_jump_to(&mut self, jump_index: usize, target: usize) {
+        let current_function_id = self.current_function_id();
+        let current_function = &mut self.program.functions[current_function_id];
+
+        current_function.code[jump_index] = (target as i64 - jump_index as i64 - 1) as u64;
+    }
+
+    fn current_offset(&self) -> usize {
+        self.program.functions[self.current_function_id()]
+            .code
+            .len()
+    }
+
+    fn emit_jump_table(&mut self) {
+        let current_function_id = self.current_function_id();
+        let current_function = &mut self.program.functions[current_function_id];
+
         current_function
             .code
-            .push(unsafe { std::mem::transmute::<i64, u64>(jump) });
+            .push(evaluator::Instruction::JumpTable as u64);
+
+        // the table is only added once the arms have been compiled
+        current_function.code.push(0);
     }
 
-    fn patch_jump(&mut self, jump_index: usize) {
+    fn emit_field(&mut self, instruction: evaluator::Instruction, layout: typer::FieldLayout) {
+        let current_function_id = self.current_function_id();
+        let current_function = &mut self.program.functions[current_function_id];
+
+        current_function.code.push(instruction as u64);
+
+        current_function.code.push(layout.width as u64);
+        current_function.code.push(layout.offset as u64);
+        current_function.code.push(layout.field_width as u64);
+    }
+
+    fn emit_check_enum(&mut self, variant_count: usize) {
         let current_function_id = self.current_function_id();
         let current_function = &mut self.program.functions[current_function_id];
 
-        current_function.code[jump_index] = (current_function.code.len() - jump_index - 1) as u64;
+        current_function
+            .code
+            .push(evaluator::Instruction::CheckEnum as u64);
+
+        current_function.code.push(variant_count as u64);
     }
 
     fn emit_bind(&mut self, nbinds: usize) {
@@ -183,9 +376,7 @@ impl Compiler {
             .code
             .push(evaluator::Instruction::Bind as u64);
 
-        current_function
-            .code
-            .push(unsafe { std::mem::transmute(nbinds) });
+        current_function.code.push(nbinds as u64);
     }
 
     fn emit_unbind(&mut self, nbinds: usize) {
@@ -196,9 +387,7 @@ impl Compiler {
             .code
             .push(evaluator::Instruction::Unbind as u64);
 
-        current_function
-            .code
-            .push(unsafe { std::mem::transmute(nbinds) });
+        current_function.code.push(nbinds as u64);
     }
 
     fn emit_push_bind(&mut self, index: usize) {
@@ -209,9 +398,7 @@ impl Compiler {
             .code
             .push(evaluator::Instruction::PushBind as u64);
 
-        current_function
-            .code
-            .push(unsafe { std::mem::transmute(index) });
+        current_function.code.push(index as u64);
     }
 
     fn emit_push_var(&mut self, index: usize) {
@@ -222,9 +409,7 @@ impl Compiler {
             .code
             .push(evaluator::Instruction::PushVar as u64);
 
-        current_function
-            .code
-            .push(unsafe { std::mem::transmute(index) });
+        current_function.code.push(index as u64);
     }
 
     fn emit_make_var(&mut self, index: usize) {
@@ -235,33 +420,52 @@ impl Compiler {
             .code
             .push(evaluator::Instruction::MakeVar as u64);
 
-        current_function
-            .code
-            .push(unsafe { std::mem::transmute(index) });
+        current_function.code.push(index as u64);
     }
 }
 
 impl Compiler {
     fn compile_expression(
         &mut self,
-        ir: typer::TypedIRKind,
+        ir: typer::TypedIR,
         rest: &mut IRIter,
-    ) -> Result<(), String> {
+    ) -> Result<(), Diagnostic> {
+        let typer::TypedIR { kind, location } = ir;
+        self.mark_location(location.clone());
+
         use typer::TypedIRKind::*;
-        match ir {
+        match kind {
             // Literals
             PushBool(value) => self.emit_push_bool(value),
             PushInt(value) => self.emit_push_int(value),
+            // floats live bit-cast in the stack cells
+            PushFloat(value) => self.emit_push_int(value.to_bits() as i64),
             PushStr(value) => self.emit_push_str(&value)?,
 
             // Keywords
-            End => return Err("Unexpected `end`!".to_string()),
+            End => {
+                return Err(location.error(diagnostic::Code::MisplacedKeyword, "Unexpected `end`!"))
+            }
             If => self.compile_if(rest)?,
-            Elif => return Err("Unexpected `elif`!".to_string()),
-            Else => return Err("Unexpected `else`!".to_string()),
+            Elif => {
+                return Err(location.error(diagnostic::Code::MisplacedKeyword, "Unexpected `elif`!"))
+            }
+            Else => {
+                return Err(location.error(diagnostic::Code::MisplacedKeyword, "Unexpected `else`!"))
+            }
             While => self.compile_while(rest)?,
-            Then => return Err("Unexpected `then`!".to_string()),
-            Do => return Err("Unexpected `do`!".to_string()),
+            For(id) => self.compile_for(id, rest)?,
+            Loop => self.compile_loop(rest)?,
+            Match(arms) => self.compile_match(arms, location, rest)?,
+            Case | CaseDefault => {
+                return Err(location.error(diagnostic::Code::MisplacedKeyword, "Unexpected `case`!"))
+            }
+            Then => {
+                return Err(location.error(diagnostic::Code::MisplacedKeyword, "Unexpected `then`!"))
+            }
+            Do => {
+                return Err(location.error(diagnostic::Code::MisplacedKeyword, "Unexpected `do`!"))
+            }
             Def(name) => self.compile_function(name, rest)?,
             Var => self.compile_variable(rest)?,
 
@@ -270,10 +474,21 @@ impl Compiler {
             Over => self.emit_instruction(evaluator::Instruction::Over),
             Drop => self.emit_instruction(evaluator::Instruction::Drop),
             Swap => self.emit_instruction(evaluator::Instruction::Swap),
+            Rot => self.emit_instruction(evaluator::Instruction::Rot),
+            Unrot => self.emit_instruction(evaluator::Instruction::Unrot),
+            Nip => self.emit_instruction(evaluator::Instruction::Nip),
+            Tuck => self.emit_instruction(evaluator::Instruction::Tuck),
+            TwoDup => self.emit_instruction(evaluator::Instruction::TwoDup),
+            TwoDrop => self.emit_instruction(evaluator::Instruction::TwoDrop),
+            TwoSwap => self.emit_instruction(evaluator::Instruction::TwoSwap),
+            TwoOver => self.emit_instruction(evaluator::Instruction::TwoOver),
+            Pick(index) => self.emit_stack_index(evaluator::Instruction::Pick, index),
+            Roll(index) => self.emit_stack_index(evaluator::Instruction::Roll, index),
             PrintBool => self.emit_instruction(evaluator::Instruction::PrintBool),
             PrintInt => self.emit_instruction(evaluator::Instruction::PrintInt),
+            PrintFloat => self.emit_instruction(evaluator::Instruction::PrintFloat),
             PrintStr => self.emit_instruction(evaluator::Instruction::PrintStr),
-            PrintPtr => todo!(),
+            PrintPtr => self.emit_instruction(evaluator::Instruction::PrintPtr),
             And => self.emit_instruction(evaluator::Instruction::And),
             Or => self.emit_instruction(evaluator::Instruction::Or),
             Not => self.emit_instruction(evaluator::Instruction::Not),
@@ -283,33 +498,152 @@ impl Compiler {
             Divide => self.emit_instruction(evaluator::Instruction::Divide),
             Eq => self.emit_instruction(evaluator::Instruction::Eq),
             Neq => self.emit_instruction(evaluator::Instruction::Neq),
+            StrEq => self.emit_instruction(evaluator::Instruction::StrEq),
+            StrNeq => self.emit_instruction(evaluator::Instruction::StrNeq),
             Lt => self.emit_instruction(evaluator::Instruction::Lt),
             Gt => self.emit_instruction(evaluator::Instruction::Gt),
-            Assign => self.emit_instruction(evaluator::Instruction::Assign),
-            Load => self.emit_instruction(evaluator::Instruction::Load),
+            Modulo => self.emit_instruction(evaluator::Instruction::Modulo),
+            Le => self.emit_instruction(evaluator::Instruction::Le),
+            Ge => self.emit_instruction(evaluator::Instruction::Ge),
+            Negate => self.emit_instruction(evaluator::Instruction::Negate),
+            BitAnd => self.emit_instruction(evaluator::Instruction::BitAnd),
+            BitOr => self.emit_instruction(evaluator::Instruction::BitOr),
+            BitXor => self.emit_instruction(evaluator::Instruction::BitXor),
+            BitNot => self.emit_instruction(evaluator::Instruction::BitNot),
+            Shl => self.emit_instruction(evaluator::Instruction::Shl),
+            Shr => self.emit_instruction(evaluator::Instruction::Shr),
+            DivMod => self.emit_instruction(evaluator::Instruction::DivMod),
+            FloatAdd => self.emit_instruction(evaluator::Instruction::FloatAdd),
+            FloatSubtract => self.emit_instruction(evaluator::Instruction::FloatSubtract),
+            FloatMultiply => self.emit_instruction(evaluator::Instruction::FloatMultiply),
+            FloatDivide => self.emit_instruction(evaluator::Instruction::FloatDivide),
+            FloatEq => self.emit_instruction(evaluator::Instruction::FloatEq),
+            FloatNeq => self.emit_instruction(evaluator::Instruction::FloatNeq),
+            FloatLt => self.emit_instruction(evaluator::Instruction::FloatLt),
+            FloatGt => self.emit_instruction(evaluator::Instruction::FloatGt),
+            FloatLe => self.emit_instruction(evaluator::Instruction::FloatLe),
+            FloatGe => self.emit_instruction(evaluator::Instruction::FloatGe),
+            FloatNegate => self.emit_instruction(evaluator::Instruction::FloatNegate),
+            IntToFloat => self.emit_instruction(evaluator::Instruction::IntToFloat),
+            FloatToInt => self.emit_instruction(evaluator::Instruction::FloatToInt),
+            StrLen => self.emit_instruction(evaluator::Instruction::StrLen),
+            StrConcat => self.emit_instruction(evaluator::Instruction::StrConcat),
+            StrSlice => self.emit_instruction(evaluator::Instruction::StrSlice),
+            StrFind => self.emit_instruction(evaluator::Instruction::StrFind),
+            StrStartsWith => self.emit_instruction(evaluator::Instruction::StrStartsWith),
+            StrEndsWith => self.emit_instruction(evaluator::Instruction::StrEndsWith),
+            StrCompare => self.emit_instruction(evaluator::Instruction::StrCompare),
+            StrSplit => self.emit_instruction(evaluator::Instruction::StrSplit),
+            StrTrim => self.emit_instruction(evaluator::Instruction::StrTrim),
+            StrToInt => self.emit_instruction(evaluator::Instruction::StrToInt),
+            IntToStr => self.emit_instruction(evaluator::Instruction::IntToStr),
+            Alloc(width) => self.emit_wide(evaluator::Instruction::Alloc, width),
+            Free => self.emit_instruction(evaluator::Instruction::Free),
+            Offset(width) => self.emit_wide(evaluator::Instruction::Offset, width),
+            Zero(width) => self.emit_wide(evaluator::Instruction::Zero, width),
+            ArrayGet(len, width) => self.emit_array(evaluator::Instruction::ArrayGet, len, width),
+            ArraySet(len, width) => self.emit_array(evaluator::Instruction::ArraySet, len, width),
+            CheckIndex(len) => self.emit_wide(evaluator::Instruction::CheckIndex, len),
+            VecNew => self.emit_instruction(evaluator::Instruction::VecNew),
+            VecPush(width) => self.emit_wide(evaluator::Instruction::VecPush, width),
+            VecPop(width) => self.emit_wide(evaluator::Instruction::VecPop, width),
+            VecGet(width) => self.emit_wide(evaluator::Instruction::VecGet, width),
+            VecSet(width) => self.emit_wide(evaluator::Instruction::VecSet, width),
+            VecLen => self.emit_instruction(evaluator::Instruction::VecLen),
+            Assign(1) => self.emit_instruction(evaluator::Instruction::Assign),
+            Assign(width) => self.emit_wide(evaluator::Instruction::AssignWide, width),
+            Load(1) => self.emit_instruction(evaluator::Instruction::Load),
+            Load(width) => self.emit_wide(evaluator::Instruction::LoadWide, width),
             Call(name) => {
                 let function_id = self
                     .get_function_id(&name)
-                    .expect(format!("No function named `{}` in function map!", name).as_str());
+                    .unwrap_or_else(|| panic!("No function named `{}` in function map!", name));
                 self.emit_call(function_id);
             }
+            Break(nbinds) => {
+                if nbinds > 0 {
+                    self.emit_unbind(nbinds);
+                }
+                self.emit_jump(-1);
+                let jump_index = self.current_offset() - 1;
+                self.loops
+                    .last_mut()
+                    .expect("The parser only allows `break` in loops")
+                    .breaks
+                    .push(jump_index);
+            }
+            Continue(nbinds) => {
+                if nbinds > 0 {
+                    self.emit_unbind(nbinds);
+                }
+                let start = self
+                    .loops
+                    .last()
+                    .expect("The parser only allows `continue` in loops")
+                    .start;
+                match start {
+                    // plus 2 because of the jump instruction itself
+                    Some(start) => {
+                        self.emit_jump(start as i64 - (self.current_offset() + 2) as i64)
+                    }
+                    None => {
+                        self.emit_jump(-1);
+                        let jump_index = self.current_offset() - 1;
+                        self.loops
+                            .last_mut()
+                            .expect("We just read it")
+                            .continues
+                            .push(jump_index);
+                    }
+                }
+            }
+            Return => self.emit_instruction(evaluator::Instruction::Return),
+            CallDynamic => self.emit_instruction(evaluator::Instruction::CallDynamic),
+            PushFunction(name) => {
+                let function_id = self
+                    .get_function_id(&name)
+                    .unwrap_or_else(|| panic!("No function named `{}` in function map!", name));
+                self.emit_push_function(function_id);
+            }
+            MakeClosure(name, ncaptures) => {
+                let function_id = self
+                    .get_function_id(&name)
+                    .unwrap_or_else(|| panic!("No quotation named `{}` in function map!", name));
+                self.emit_make_closure(function_id, ncaptures);
+            }
+            PushCapture(index, width) => {
+                for cell in index..index + width {
+                    self.emit_push_capture(cell);
+                }
+            }
+            Field(layout) => self.emit_field(evaluator::Instruction::Field, layout),
+            FieldUpdate(layout) => self.emit_field(evaluator::Instruction::FieldUpdate, layout),
+            CheckEnum(variant_count) => self.emit_check_enum(variant_count),
             Bind(nbinds) => self.emit_bind(nbinds),
             Unbind(nbinds) => self.emit_unbind(nbinds),
-            PushBind(id) => self.emit_push_bind(id),
+            PushBind(id, width) => {
+                for cell in id..id + width {
+                    self.emit_push_bind(cell);
+                }
+            }
             PushVar(index) => self.emit_push_var(index),
-            MakeVar(_) => unreachable!(),
+            MakeVar(..) => unreachable!(),
+            Shuffle(inputs, outputs) => self.emit_shuffle(inputs, outputs),
         }
         Ok(())
     }
 
-    fn compile_function(&mut self, name: String, ir: &mut IRIter) -> Result<(), String> {
+    fn compile_function(&mut self, name: String, ir: &mut IRIter) -> Result<(), Diagnostic> {
         self.add_function(name);
 
         while let Some(i) = ir.next() {
             use typer::TypedIRKind::*;
             match i.kind {
-                End => break,
-                _ => self.compile_expression(i.kind, ir)?,
+                End => {
+                    self.mark_location(i.location);
+                    break;
+                }
+                _ => self.compile_expression(i, ir)?,
             }
         }
 
@@ -322,24 +656,145 @@ impl Compiler {
         Ok(())
     }
 
-    fn compile_variable(&mut self, ir: &mut IRIter) -> Result<(), String> {
+    fn compile_variable(&mut self, ir: &mut IRIter) -> Result<(), Diagnostic> {
         while let Some(i) = ir.next() {
             use typer::TypedIRKind::*;
             match i.kind {
-                MakeVar(index) => {
-                    if index >= self.program.variable_size {
-                        self.program.variable_size = index + 1;
+                MakeVar(index, width) => {
+                    if index + width > self.program.variable_size {
+                        self.program.variable_size = index + width;
+                    }
+                    // the last cell is on top of the stack
+                    for cell in (index..index + width).rev() {
+                        self.emit_make_var(cell);
+                    }
+                }
+                _ => self.compile_expression(i, ir)?,
+            }
+        }
+
+        Ok(())
+    }
+
+    // Values close together dispatch through a jump table and anything
+    // sparser compares against each value in turn.
+    fn compile_match(
+        &mut self,
+        arms: Vec<Vec<i64>>,
+        location: Location,
+        ir: &mut IRIter,
+    ) -> Result<(), Diagnostic> {
+        let mut next = loop {
+            let i = ir
+                .next()
+                .expect("The typer checks every `match` has an `end`");
+            match i.kind {
+                typer::TypedIRKind::Case
+                | typer::TypedIRKind::CaseDefault
+                | typer::TypedIRKind::End => break i,
+                _ => self.compile_expression(i, ir)?,
+            }
+        };
+
+        let values = arms
+            .iter()
+            .enumerate()
+            .flat_map(|(arm, values)| values.iter().map(move |value| (*value, arm)))
+            .collect::<Vec<_>>();
+        let min = values.iter().map(|(value, _)| *value).min();
+        let max = values.iter().map(|(value, _)| *value).max();
+        let dense = match (min, max) {
+            (Some(min), Some(max)) => (max as i128 - min as i128) < 2 * values.len() as i128,
+            _ => false,
+        };
+
+        self.mark_location(location);
+        let mut checks = Vec::new();
+        let mut default_jump = None;
+        if dense {
+            self.emit_jump_table();
+        } else {
+            for (value, arm) in &values {
+                self.emit_instruction(evaluator::Instruction::Dup);
+                self.emit_push_int(*value);
+                self.emit_instruction(evaluator::Instruction::Eq);
+                self.emit_jump_true(-1);
+                checks.push((self.current_offset() - 1, *arm));
+            }
+            self.emit_instruction(evaluator::Instruction::Drop);
+            self.emit_jump(-1);
+            default_jump = Some(self.current_offset() - 1);
+        }
+        let table_operand = self.current_offset() - 1;
+
+        let mut arm_starts = Vec::new();
+        let mut default_start = None;
+        let mut exits = Vec::new();
+        loop {
+            self.mark_location(next.location.clone());
+            match next.kind {
+                typer::TypedIRKind::Case => {
+                    arm_starts.push(self.current_offset());
+                    // the compared value is still on the stack
+                    if !dense {
+                        self.emit_instruction(evaluator::Instruction::Drop);
                     }
-                    self.emit_make_var(index);
                 }
-                _ => self.compile_expression(i.kind, ir)?,
+                typer::TypedIRKind::CaseDefault => default_start = Some(self.current_offset()),
+                typer::TypedIRKind::End => break,
+                _ => unreachable!("The typer only allows arms in a `match`"),
+            }
+
+            while let Some(i) = ir.next() {
+                match i.kind {
+                    typer::TypedIRKind::End => {
+                        self.mark_location(i.location);
+                        break;
+                    }
+                    _ => self.compile_expression(i, ir)?,
+                }
+            }
+            self.emit_jump(-1);
+            exits.push(self.current_offset() - 1);
+
+            next = ir
+                .next()
+                .expect("The typer checks every `match` has an `end`");
+        }
+
+        let end = self.current_offset();
+        exits.into_iter().for_each(|index| self.patch_jump(index));
+        let default = default_start.unwrap_or(end);
+
+        match (min, max) {
+            (Some(min), Some(max)) if dense => {
+                let mut targets = vec![default; (max - min) as usize + 1];
+                for (value, arm) in values {
+                    targets[(value - min) as usize] = arm_starts[arm];
+                }
+
+                let index = self.program.add_jump_table(evaluator::JumpTable {
+                    min,
+                    targets,
+                    default,
+                });
+                let current_function_id = self.current_function_id();
+                self.program.functions[current_function_id].code[table_operand] = index as u64;
+            }
+            _ => {
+                for (jump_index, arm) in checks {
+                    self.patch_jump_to(jump_index, arm_starts[arm]);
+                }
+                if let Some(jump_index) = default_jump {
+                    self.patch_jump_to(jump_index, default);
+                }
             }
         }
 
         Ok(())
     }
 
-    fn compile_if(&mut self, ir: &mut IRIter) -> Result<(), String> {
+    fn compile_if(&mut self, ir: &mut IRIter) -> Result<(), Diagnostic> {
         let mut jump_index = Some(0);
         let mut exits = Vec::new();
 
@@ -347,6 +802,7 @@ impl Compiler {
             use typer::TypedIRKind::*;
             match i.kind {
                 End => {
+                    self.mark_location(i.location.clone());
                     if let Some(jump_index) = jump_index {
                         self.patch_jump(jump_index);
                     }
@@ -354,6 +810,7 @@ impl Compiler {
                     break;
                 }
                 Elif => {
+                    self.mark_location(i.location.clone());
                     self.emit_jump(-1);
                     exits.push(
                         self.program.functions[self.current_function_id()]
@@ -364,6 +821,7 @@ impl Compiler {
                     self.patch_jump(jump_index.expect("We should have a jump index!"));
                 }
                 Else => {
+                    self.mark_location(i.location.clone());
                     self.emit_jump(-1);
                     exits.push(
                         self.program.functions[self.current_function_id()]
@@ -375,6 +833,7 @@ impl Compiler {
                     jump_index = None;
                 }
                 Then => {
+                    self.mark_location(i.location.clone());
                     self.emit_jump_false(-1);
                     jump_index = Some(
                         self.program.functions[self.current_function_id()]
@@ -383,23 +842,25 @@ impl Compiler {
                             - 1,
                     );
                 }
-                _ => self.compile_expression(i.kind, ir)?,
+                _ => self.compile_expression(i, ir)?,
             }
         }
 
         Ok(())
     }
 
-    fn compile_while(&mut self, ir: &mut IRIter) -> Result<(), String> {
+    fn compile_while(&mut self, ir: &mut IRIter) -> Result<(), Diagnostic> {
         let while_index = self.program.functions[self.current_function_id()]
             .code
             .len();
         let mut do_index = 0;
+        self.loops.push(Loop::new(Some(while_index)));
 
         while let Some(i) = ir.next() {
             use typer::TypedIRKind::*;
             match i.kind {
                 End => {
+                    self.mark_location(i.location.clone());
                     self.emit_jump(
                         (while_index.wrapping_sub(
                             self.program.functions[self.current_function_id()]
@@ -409,16 +870,82 @@ impl Compiler {
                         )) as i64,
                     );
                     self.patch_jump(do_index);
+                    let current_loop = self.loops.pop().expect("We pushed it before the loop");
+                    for jump_index in current_loop.breaks {
+                        self.patch_jump(jump_index);
+                    }
                     break;
                 }
                 Do => {
+                    self.mark_location(i.location.clone());
                     self.emit_jump_false(-1);
                     do_index = self.program.functions[self.current_function_id()]
                         .code
                         .len()
                         - 1;
                 }
-                _ => self.compile_expression(i.kind, ir)?,
+                _ => self.compile_expression(i, ir)?,
+            }
+        }
+
+        Ok(())
+    }
+
+    // The range is bound below the body and checked once on entry and then
+    // only when the index is stepped at the end of each iteration
+    fn compile_for(&mut self, id: usize, ir: &mut IRIter) -> Result<(), Diagnostic> {
+        self.emit_bind(2);
+        self.emit_for(evaluator::Instruction::ForStart, id, -1);
+        let start_jump_index = self.current_offset() - 1;
+        let body_index = self.current_offset();
+        self.loops.push(Loop::new(None));
+
+        while let Some(i) = ir.next() {
+            use typer::TypedIRKind::*;
+            match i.kind {
+                End => {
+                    self.mark_location(i.location.clone());
+                    let current_loop = self.loops.pop().expect("We pushed it before the loop");
+                    for jump_index in current_loop.continues {
+                        self.patch_jump(jump_index);
+                    }
+
+                    // plus 3 because of the instruction and its operands
+                    let jump = body_index as i64 - (self.current_offset() + 3) as i64;
+                    self.emit_for(evaluator::Instruction::ForNext, id, jump);
+
+                    self.patch_jump(start_jump_index);
+                    for jump_index in current_loop.breaks {
+                        self.patch_jump(jump_index);
+                    }
+                    self.emit_unbind(2);
+                    break;
+                }
+                _ => self.compile_expression(i, ir)?,
+            }
+        }
+
+        Ok(())
+    }
+
+    fn compile_loop(&mut self, ir: &mut IRIter) -> Result<(), Diagnostic> {
+        let loop_index = self.current_offset();
+        self.loops.push(Loop::new(Some(loop_index)));
+
+        while let Some(i) = ir.next() {
+            use typer::TypedIRKind::*;
+            match i.kind {
+                End => {
+                    self.mark_location(i.location.clone());
+                    // plus 2 because of the jump instruction itself
+                    self.emit_jump(loop_index as i64 - (self.current_offset() + 2) as i64);
+                    let current_loop = self.loops.pop().expect("We pushed it before the loop");
+                    for jump_index in current_loop.breaks {
+                        self.patch_jump(jump_index);
+                    }
+                    break;
+                }
+                _ => self.compile_expression(i, ir)?,
             }
         }
 
@@ -0,0 +1,345 @@
+use crate::location::Location;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    Lexer,
+    Parser,
+    Typer,
+    Compiler,
+    Runtime,
+}
+
+impl Phase {
+    fn prefix(self) -> char {
+        match self {
+            Phase::Lexer => 'L',
+            Phase::Parser => 'P',
+            Phase::Typer => 'T',
+            Phase::Compiler => 'C',
+            Phase::Runtime => 'R',
+        }
+    }
+}
+
+impl fmt::Display for Phase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Phase::Lexer => write!(f, "lexer"),
+            Phase::Parser => write!(f, "parser"),
+            Phase::Typer => write!(f, "typer"),
+            Phase::Compiler => write!(f, "compiler"),
+            Phase::Runtime => write!(f, "runtime"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+// @NOTE:
+// The numbers of these codes are part of the public interface of the
+// compiler. Never renumber an existing code, only append new ones.
+//
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Code {
+    // Lexer
+    UnterminatedString, // L0001
+    InvalidEscape,      // L0002
+    MalformedNumber,    // L0003
+    IntegerOverflow,    // L0004
+    MalformedChar,      // L0005
+
+    // Parser
+    UnexpectedToken,      // P0001
+    UnexpectedEof,        // P0002
+    ExpectedIdentifier,   // P0003
+    UnknownIdentifier,    // P0004
+    RedeclaredIdentifier, // P0005
+    IncludeFailed,        // P0006
+    InvalidConstant,      // P0007
+    InvalidTypeSignature, // P0008
+
+    // Typer
+    StackUnderflow,        // T0001
+    TypeMismatch,          // T0002
+    BranchMismatch,        // T0003
+    LoopAltersStack,       // T0004
+    ReturnMismatch,        // T0005
+    RecursiveStruct,       // T0006
+    UnknownType,           // T0007
+    UninitializedVariable, // T0008
+    InvalidBody,           // T0009
+    UnexpectedKeyword,     // T0010
+    DuplicateCase,         // T0011
+    NonExhaustiveMatch,    // T0012
+    UninferredQuotation,   // T0013
+    Unreachable,           // T0014
+
+    // Compiler
+    MisplacedKeyword, // C0001
+    StringConstant,   // C0002
+
+    // Runtime
+    DataStackUnderflow, // R0001
+    DivisionByZero,     // R0002
+    InvalidString,      // R0003
+    InvalidEnum,        // R0004
+    OutOfBounds,        // R0005
+    NullDereference,    // R0006
+    UseAfterFree,       // R0007
+    DoubleFree,         // R0008
+    InvalidPointer,     // R0009
+    AllocationFailed,   // R0010
+}
+
+impl Code {
+    pub fn phase(self) -> Phase {
+        use Code::*;
+        match self {
+            UnterminatedString | InvalidEscape | MalformedNumber | IntegerOverflow
+            | MalformedChar => Phase::Lexer,
+            UnexpectedToken | UnexpectedEof | ExpectedIdentifier | UnknownIdentifier
+            | RedeclaredIdentifier | IncludeFailed | InvalidConstant | InvalidTypeSignature => {
+                Phase::Parser
+            }
+            StackUnderflow
+            | TypeMismatch
+            | BranchMismatch
+            | LoopAltersStack
+            | ReturnMismatch
+            | RecursiveStruct
+            | UnknownType
+            | UninitializedVariable
+            | InvalidBody
+            | UnexpectedKeyword
+            | DuplicateCase
+            | NonExhaustiveMatch
+            | UninferredQuotation
+            | Unreachable => Phase::Typer,
+            MisplacedKeyword | StringConstant => Phase::Compiler,
+            DataStackUnderflow | DivisionByZero | InvalidString | InvalidEnum | OutOfBounds
+            | NullDereference | UseAfterFree | DoubleFree | InvalidPointer | AllocationFailed => {
+                Phase::Runtime
+            }
+        }
+    }
+
+    pub fn severity(self) -> Severity {
+        match self {
+            Code::NonExhaustiveMatch => Severity::Warning,
+            _ => Severity::Error,
+        }
+    }
+
+    fn number(self) -> u32 {
+        use Code::*;
+        match self {
+            UnterminatedString => 1,
+            InvalidEscape => 2,
+            MalformedNumber => 3,
+            IntegerOverflow => 4,
+            MalformedChar => 5,
+
+            UnexpectedToken => 1,
+            UnexpectedEof => 2,
+            ExpectedIdentifier => 3,
+            UnknownIdentifier => 4,
+            RedeclaredIdentifier => 5,
+            IncludeFailed => 6,
+            InvalidConstant => 7,
+            InvalidTypeSignature => 8,
+
+            StackUnderflow => 1,
+            TypeMismatch => 2,
+            BranchMismatch => 3,
+            LoopAltersStack => 4,
+            ReturnMismatch => 5,
+            RecursiveStruct => 6,
+            UnknownType => 7,
+            UninitializedVariable => 8,
+            InvalidBody => 9,
+            UnexpectedKeyword => 10,
+            DuplicateCase => 11,
+            NonExhaustiveMatch => 12,
+            UninferredQuotation => 13,
+            Unreachable => 14,
+
+            MisplacedKeyword => 1,
+            StringConstant => 2,
+
+            DataStackUnderflow => 1,
+            DivisionByZero => 2,
+            InvalidString => 3,
+            InvalidEnum => 4,
+            OutOfBounds => 5,
+            NullDereference => 6,
+            UseAfterFree => 7,
+            DoubleFree => 8,
+            InvalidPointer => 9,
+            AllocationFailed => 10,
+        }
+    }
+}
+
+impl fmt::Display for Code {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{:04}", self.phase().prefix(), self.number())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Note {
+    pub message: String,
+    pub location: Option<Location>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub phase: Phase,
+    pub severity: Severity,
+    pub code: Code,
+    pub message: String,
+    pub location: Option<Location>,
+    pub notes: Vec<Note>,
+    pub help: Option<String>,
+}
+
+impl Diagnostic {
+    pub fn new(code: Code, message: impl fmt::Display) -> Self {
+        Self {
+            phase: code.phase(),
+            severity: code.severity(),
+            code,
+            message: message.to_string(),
+            location: None,
+            notes: Vec::new(),
+            help: None,
+        }
+    }
+
+    pub fn at(mut self, location: Location) -> Self {
+        self.location = Some(location);
+        self
+    }
+
+    pub fn with_note(mut self, message: impl fmt::Display) -> Self {
+        self.notes.push(Note {
+            message: message.to_string(),
+            location: None,
+        });
+        self
+    }
+
+    pub fn with_note_at(mut self, location: Location, message: impl fmt::Display) -> Self {
+        self.notes.push(Note {
+            message: message.to_string(),
+            location: Some(location),
+        });
+        self
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+
+    pub fn with_help(mut self, message: impl fmt::Display) -> Self {
+        self.help = Some(message.to_string());
+        self
+    }
+}
+
+// Renders as e.g.
+//   typer error[T0002]: Cannot add something of type `str`!
+//    --> examples/dev.reko:3:11
+//     |
+//   3 |     1 "a" +
+//     |           ^
+//   note: ...
+//   help: ...
+//
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {}[{}]: {}",
+            self.phase, self.severity, self.code, self.message
+        )?;
+        if let Some(location) = &self.location {
+            write!(f, "\n --> {}\n{}", location, location.underline())?;
+        }
+
+        for note in &self.notes {
+            write!(f, "\nnote: {}", note.message)?;
+            if let Some(location) = &note.location {
+                write!(f, "\n --> {}\n{}", location, location.underline())?;
+            }
+        }
+
+        if let Some(help) = &self.help {
+            write!(f, "\nhelp: {}", help)?;
+        }
+
+        Ok(())
+    }
+}
+
+// Suggests the closest of `candidates` to a misspelled `name`
+pub fn did_you_mean<'a>(name: &str, candidates: impl Iterator<Item = &'a str>) -> Option<&'a str> {
+    let max_distance = (name.chars().count() / 3).max(1);
+    candidates
+        .filter(|candidate| *candidate != name)
+        .map(|candidate| (edit_distance(name, candidate), candidate))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .min_by_key(|(distance, candidate)| (*distance, *candidate))
+        .map(|(_, candidate)| candidate)
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut previous = (0..=b.len()).collect::<Vec<_>>();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + (ca != *cb) as usize;
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        previous = current;
+    }
+
+    previous[b.len()]
+}
+
+// Errors inside the parser and typer. Anything depending on a declaration
+// that already failed is poisoned instead of being reported again so one
+// mistake doesn't cascade into a wall of errors.
+#[derive(Debug)]
+pub enum Error {
+    Diagnostic(Diagnostic),
+    Poisoned,
+}
+
+impl From<Diagnostic> for Error {
+    fn from(diagnostic: Diagnostic) -> Self {
+        Error::Diagnostic(diagnostic)
+    }
+}
+
+pub fn report(diagnostics: &mut Vec<Diagnostic>, error: Error) {
+    if let Error::Diagnostic(diagnostic) = error {
+        diagnostics.push(diagnostic);
+    }
+}
@@ -0,0 +1,156 @@
+use crate::evaluator;
+use crate::location::Location;
+use crate::parser;
+use crate::typer;
+use std::fmt::{self, Write};
+use std::path::PathBuf;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    Tokens,
+    Ir,
+    TypedIr,
+    Bytecode,
+}
+
+impl Stage {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "tokens" => Some(Stage::Tokens),
+            "ir" => Some(Stage::Ir),
+            "typed-ir" => Some(Stage::TypedIr),
+            "bytecode" => Some(Stage::Bytecode),
+            _ => None,
+        }
+    }
+}
+
+// A request to write out an intermediate representation, parsed from
+// `--emit=<stage>` or `--emit=<stage>=<file>`
+#[derive(Debug)]
+pub struct Emit {
+    pub stage: Stage,
+    pub path: Option<PathBuf>,
+}
+
+impl Emit {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let (name, path) = match value.split_once('=') {
+            Some((name, path)) => (name, Some(PathBuf::from(path))),
+            None => (value, None),
+        };
+
+        let stage = Stage::from_name(name).ok_or_else(|| {
+            format!(
+                "Unknown stage `{}` for `--emit`! Expected one of tokens, ir, typed-ir or bytecode",
+                name
+            )
+        })?;
+
+        Ok(Self { stage, path })
+    }
+}
+
+pub fn wants(emits: &[Emit], stage: Stage) -> bool {
+    emits.iter().any(|emit| emit.stage == stage)
+}
+
+pub fn writes_to_stdout(emits: &[Emit]) -> bool {
+    emits.iter().any(|emit| emit.path.is_none())
+}
+
+// Writes `text` to stdout or the file of every request for `stage`
+pub fn emit(emits: &[Emit], stage: Stage, text: &str) -> Result<(), String> {
+    for emit in emits.iter().filter(|emit| emit.stage == stage) {
+        match &emit.path {
+            Some(path) => std::fs::write(path, text)
+                .map_err(|err| format!("Failed to write `{}`: {}", path.display(), err))?,
+            None => print!("{}", text),
+        }
+    }
+    Ok(())
+}
+
+pub fn format_tokens(tokens: &[(Location, String)]) -> String {
+    let mut out = String::new();
+    for (location, token) in tokens {
+        writeln!(out, "{:<24} {}", location.to_string(), token).expect("Writing to a string");
+    }
+    out
+}
+
+pub fn format_ir(chunks: &parser::IRChunks) -> String {
+    use parser::IRKind::*;
+    format_chunks(chunks, |i| {
+        let block = match i.kind {
+            If | While | For(_) | Loop | Match | Case(_) | CaseDefault | Def(_) | Var(..)
+            | Struct(_) | Enum(_) | Quotation(_) | Bind(_) => Block::Open,
+            End | Unbind(_) => Block::Close,
+            Elif | Else | Then | Do | DashDash => Block::Middle,
+            _ => Block::Inside,
+        };
+        (block, &i.location, &i.kind)
+    })
+}
+
+pub fn format_typed_ir(chunks: &typer::TypedChunks) -> String {
+    use typer::TypedIRKind::*;
+    format_chunks(chunks, |i| {
+        let block = match i.kind {
+            If | While | For(_) | Loop | Match(_) | Case | CaseDefault | Def(_) | Var | Bind(_) => {
+                Block::Open
+            }
+            End | Unbind(_) | MakeVar(..) => Block::Close,
+            Elif | Else | Then | Do => Block::Middle,
+            _ => Block::Inside,
+        };
+        (block, &i.location, &i.kind)
+    })
+}
+
+pub fn format_bytecode(program: &evaluator::Program) -> String {
+    program.to_string()
+}
+
+enum Block {
+    Open,
+    Middle,
+    Close,
+    Inside,
+}
+
+// Writes one instruction per line, indented by the blocks it's nested in
+fn format_chunks<T, K: fmt::Debug>(
+    chunks: &[Vec<T>],
+    describe: impl Fn(&T) -> (Block, &Location, &K),
+) -> String {
+    let mut out = String::new();
+    for chunk in chunks {
+        let mut depth = 0usize;
+        for i in chunk {
+            let (block, location, kind) = describe(i);
+            let indent = match block {
+                Block::Open | Block::Inside => depth,
+                Block::Middle => depth.saturating_sub(1),
+                Block::Close => {
+                    depth = depth.saturating_sub(1);
+                    depth
+                }
+            };
+            if let Block::Open = block {
+                depth += 1;
+            }
+
+            writeln!(
+                out,
+                "{:<24} {}{:?}",
+                location.to_string(),
+                "    ".repeat(indent),
+                kind
+            )
+            .expect("Writing to a string");
+        }
+        out.push('\n');
+    }
+    out
+}
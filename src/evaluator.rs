@@ -1,17 +1,51 @@
 use crate::compiler;
+use crate::diagnostic::{Code, Diagnostic};
+use crate::location::Location;
+use crate::parser;
 use crate::string;
+use std::fmt::{self, Write};
+use std::str;
 
 #[derive(Debug)]
 pub struct Function {
+    pub name: String,
     pub code: compiler::Code,
+    // (code offset, location) pairs in increasing offset order
+    pub locations: Vec<(usize, Location)>,
 }
 
 impl Function {
-    pub fn new() -> Self {
+    pub fn new(name: String) -> Self {
         Self {
+            name,
             code: compiler::Code::new(),
+            locations: Vec::new(),
         }
     }
+
+    pub fn location_at(&self, offset: usize) -> Option<&Location> {
+        let index = self
+            .locations
+            .partition_point(|(start, _)| *start <= offset);
+        index.checked_sub(1).map(|index| &self.locations[index].1)
+    }
+}
+
+// Code offsets for the values `min..min + targets.len()` and for any other
+// value
+#[derive(Debug)]
+pub struct JumpTable {
+    pub min: i64,
+    pub targets: Vec<usize>,
+    pub default: usize,
+}
+
+// Replaces the top `inputs` cells with the cells at the indices of
+// `outputs`, counted from the deepest input
+#[derive(Debug)]
+pub struct Shuffle {
+    pub inputs: usize,
+    pub outputs: Vec<usize>,
 }
 
 #[derive(Debug)]
@@ -20,6 +54,8 @@ pub struct Program {
     pub variable_size: usize,
     pub functions: Vec<Function>,
     strings: Vec<Box<[u8]>>,
+    jump_tables: Vec<JumpTable>,
+    shuffles: Vec<Shuffle>,
 }
 
 impl Program {
@@ -29,6 +65,8 @@ impl Program {
             variable_size: 0,
             functions: Vec::new(),
             strings: Vec::new(),
+            jump_tables: Vec::new(),
+            shuffles: Vec::new(),
         }
     }
 
@@ -36,7 +74,17 @@ impl Program {
         self.entry_index = entry_index;
     }
 
-    pub fn add_string_constant(&mut self, string: &str) -> Result<usize, String> {
+    pub fn add_jump_table(&mut self, table: JumpTable) -> usize {
+        self.jump_tables.push(table);
+        self.jump_tables.len() - 1
+    }
+
+    pub fn add_shuffle(&mut self, shuffle: Shuffle) -> usize {
+        self.shuffles.push(shuffle);
+        self.shuffles.len() - 1
+    }
+
+    pub fn add_string_constant(&mut self, string: &str) -> Result<usize, Diagnostic> {
         if let Some(index) = self
             .strings
             .iter()
@@ -44,8 +92,12 @@ impl Program {
         {
             Ok(index)
         } else {
-            let zstring = string::make_from_str(string)
-                .map_err(|err| format!("Failed to allocate string constant: {err}"))?;
+            let zstring = string::make_from_str(string).map_err(|err| {
+                Diagnostic::new(
+                    Code::StringConstant,
+                    format!("Failed to allocate string constant: {err}"),
+                )
+            })?;
             self.strings.push(zstring);
             Ok(self.strings.len() - 1)
         }
@@ -58,13 +110,14 @@ impl Program {
 // {} = arguments on the bind stack
 // -a = peek argument (doesn't pop)
 //
+#[allow(dead_code)] // variants are decoded from the bytecode with a transmute
 #[derive(Debug)]
 pub enum Instruction {
     _NoOp, // 0. Just to reserve 0
 
     PushBool, // 1. (a) -> [a]
     PushInt,  // 2. (a) -> [a]
-    PushStr,  // 3. (index in string table) -> [size, ptr]
+    PushStr,  // 3. (index in string table) -> [ptr, len]
 
     Dup,  // 4. [-a] -> [a, a]
     Over, // 5. [-a, -b] -> [a, b, a]
@@ -73,7 +126,7 @@ pub enum Instruction {
 
     PrintBool, // 8. [a] -> []
     PrintInt,  // 9. [a] -> []
-    PrintStr,  // 10. [size, ptr] -> []
+    PrintStr,  // 10. [ptr, len] -> []
 
     Call,   // 11. (fid) -> [return values]
     Return, // 12. [] -> []
@@ -102,32 +155,308 @@ pub enum Instruction {
     PushBind, // 31. (id) {aID} [] -> {aID} [aID]
     PushVar,  // 32. (id) [] -> [a]
     MakeVar,  // 33. (id) [a] -> []
+
+    Field, // 34. (W = struct width, O = field offset, K = field width) [s0, ... sW] -> [s0, ... sW, sO, ... sO+K]
+    FieldUpdate, // 35. (W = struct width, O = field offset, K = field width) [s0, ... sW, a0, ... aK] -> [s0, ... a0, ... aK, ... sW]
+    CheckEnum,   // 36. (K = no. variants) [a] -> [a]
+    JumpTable,   // 37. (index in jump table list) [a] -> []
+
+    CallDynamic,  // 38. [closure] -> [return values]
+    PushFunction, // 39. (fid) -> [closure]
+    MakeClosure,  // 40. (fid, K = no. captures) [a0, a1, ... aK] -> [closure]
+    PushCapture,  // 41. (index) -> [a]
+
+    ForStart, // 42. (id, relative jump) {aID, aID+1} -> {aID, aID+1}
+    ForNext,  // 43. (id, relative jump) {aID, aID+1} -> {aID + 1, aID+1}
+
+    Rot,     // 44. [a, b, c] -> [b, c, a]
+    Unrot,   // 45. [a, b, c] -> [c, a, b]
+    Nip,     // 46. [a, b] -> [b]
+    Tuck,    // 47. [a, b] -> [b, a, b]
+    TwoDup,  // 48. [-a, -b] -> [a, b, a, b]
+    TwoDrop, // 49. [a, b] -> []
+    TwoSwap, // 50. [a, b, c, d] -> [c, d, a, b]
+    TwoOver, // 51. [-a, -b, -c, -d] -> [a, b, c, d, a, b]
+    Pick,    // 52. (N) [-aN, ... -a0] -> [aN, ... a0, aN]
+    Roll,    // 53. (N) [aN, ... a0] -> [aN-1, ... a0, aN]
+
+    Modulo, // 54. [a, b] -> [c]
+    Le,     // 55. [a, b] -> [c]
+    Ge,     // 56. [a, b] -> [c]
+    Negate, // 57. [a] -> [a]
+    BitAnd, // 58. [a, b] -> [c]
+    BitOr,  // 59. [a, b] -> [c]
+    BitXor, // 60. [a, b] -> [c]
+    BitNot, // 61. [a] -> [a]
+    Shl,    // 62. [a, b] -> [c]
+    Shr,    // 63. [a, b] -> [c]
+    DivMod, // 64. [a, b] -> [quotient, remainder]
+
+    FloatAdd,      // 65. [a, b] -> [c]
+    FloatSubtract, // 66. [a, b] -> [c]
+    FloatMultiply, // 67. [a, b] -> [c]
+    FloatDivide,   // 68. [a, b] -> [c]
+    FloatEq,       // 69. [a, b] -> [c]
+    FloatNeq,      // 70. [a, b] -> [c]
+    FloatLt,       // 71. [a, b] -> [c]
+    FloatGt,       // 72. [a, b] -> [c]
+    FloatLe,       // 73. [a, b] -> [c]
+    FloatGe,       // 74. [a, b] -> [c]
+    FloatNegate,   // 75. [a] -> [a]
+    PrintFloat,    // 76. [a] -> []
+    IntToFloat,    // 77. [a] -> [a]
+    FloatToInt,    // 78. [a] -> [a]
+
+    StrEq,      // 79. [ptr, len, ptr, len] -> [c]
+    StrNeq,     // 80. [ptr, len, ptr, len] -> [c]
+    LoadWide,   // 81. (K = width) [ptr] -> [a0, ... aK]
+    AssignWide, // 82. (K = width) [a0, ... aK, ptr] -> []
+    Shuffle,    // 83. (index in shuffle list) [a0, ... aK] -> [shuffled]
+
+    StrLen,        // 84. [ptr, len] -> [len]
+    StrConcat,     // 85. [a, b] -> [ab]
+    StrSlice,      // 86. [s, start, end] -> [s[start..end]]
+    StrFind,       // 87. [s, needle] -> [index or -1]
+    StrStartsWith, // 88. [s, prefix] -> [c]
+    StrEndsWith,   // 89. [s, suffix] -> [c]
+    StrCompare,    // 90. [a, b] -> [-1, 0 or 1]
+    StrSplit,      // 91. [s, delimiter] -> [before, after, found]
+    StrTrim,       // 92. [s] -> [trimmed]
+    StrToInt,      // 93. [s] -> [a, parsed]
+    IntToStr,      // 94. [a] -> [s]
+
+    Alloc,  // 95. (K = element width) [count] -> [ptr]
+    Free,   // 96. [ptr] -> []
+    Offset, // 97. (K = element width) [ptr, n] -> [ptr + n * K]
+
+    Zero,       // 98. (K = width) [] -> [0, ... 0K]
+    ArrayGet, // 99. (N = length, K = element width) [a0, ... aN*K, i] -> [a0, ... aN*K, ai*K, ... ai*K+K]
+    ArraySet, // 100. (N = length, K = element width) [e0, ... eK, a0, ... aN*K, i] -> [a0, ... e0, ... eK, ... aN*K]
+    CheckIndex, // 101. (N = length) [i] -> [i]
+
+    VecNew,  // 102. [] -> [vec]
+    VecPush, // 103. (K = element width) [e0, ... eK, vec] -> []
+    VecPop,  // 104. (K = element width) [vec] -> [e0, ... eK]
+    VecGet,  // 105. (K = element width) [vec, i] -> [e0, ... eK]
+    VecSet,  // 106. (K = element width) [e0, ... eK, vec, i] -> []
+    VecLen,  // 107. [vec] -> [len]
+
+    PrintPtr, // 108. [ptr] -> []
+}
+
+// Where `print` writes to. Constants print to stderr while an emitted stage
+// is being written to stdout so the two don't get mixed.
+#[derive(Debug, Clone, Copy)]
+pub enum Output {
+    Stdout,
+    Stderr,
+}
+
+fn stack_underflow() -> Diagnostic {
+    Diagnostic::new(Code::DataStackUnderflow, "Stack underflow!")
+}
+
+fn allocation_failed(cells: usize) -> Diagnostic {
+    Diagnostic::new(
+        Code::AllocationFailed,
+        format!("Failed to allocate {} cells!", cells),
+    )
+}
+
+// Values of large types are reserved up front so that running out of
+// memory is an error in the program rather than an abort
+fn zeroed_cells(cells: usize) -> Result<Vec<i64>, Diagnostic> {
+    let mut memory = Vec::new();
+    memory
+        .try_reserve_exact(cells)
+        .map_err(|_| allocation_failed(cells))?;
+    memory.resize(cells, 0);
+    Ok(memory)
+}
+
+fn check_index(index: i64, len: usize, container: &str) -> Result<usize, Diagnostic> {
+    usize::try_from(index)
+        .ok()
+        .filter(|index| *index < len)
+        .ok_or_else(|| {
+            Diagnostic::new(
+                Code::OutOfBounds,
+                format!(
+                    "Index {} is out of bounds of {} of length {}!",
+                    index, container, len
+                ),
+            )
+        })
+}
+
+impl Instruction {
+    pub fn decode(code: u64) -> Self {
+        unsafe { std::mem::transmute::<u8, Instruction>(code as u8) }
+    }
+
+    // number of arguments following the instruction in the code
+    pub fn operand_count(&self) -> usize {
+        use Instruction::*;
+        match self {
+            PushBool | PushInt | PushStr | Call | Jump | JumpTrue | JumpFalse | Bind | Unbind
+            | PushBind | PushVar | MakeVar | CheckEnum | JumpTable | PushFunction | PushCapture
+            | Pick | Roll | LoadWide | AssignWide | Shuffle | Alloc | Offset | Zero
+            | CheckIndex | VecPush | VecPop | VecGet | VecSet => 1,
+            MakeClosure | ForStart | ForNext | ArrayGet | ArraySet => 2,
+            Field | FieldUpdate => 3,
+            _ => 0,
+        }
+    }
+}
+
+// Disassembles the program, e.g.
+//   function 1 `main` (entry)
+//     0000  PushInt 5                  ; examples/dev.reko:3:5
+//
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (id, function) in self.functions.iter().enumerate() {
+            write!(f, "function {} `{}`", id, function.name)?;
+            if id == self.entry_index {
+                write!(f, " (entry)")?;
+            }
+            writeln!(f)?;
+
+            let mut offset = 0;
+            while offset < function.code.len() {
+                let instruction = Instruction::decode(function.code[offset]);
+                let operands = &function.code[offset + 1..offset + 1 + instruction.operand_count()];
+
+                let mut text = format!("{:?}", instruction);
+                for operand in operands {
+                    write!(text, " {}", *operand as i64)?;
+                }
+                if let (Instruction::PushStr, Some(index)) = (&instruction, operands.first()) {
+                    let string = &self.strings[*index as usize];
+                    write!(
+                        text,
+                        " {:?}",
+                        String::from_utf8_lossy(&string[..string.len() - 1])
+                    )?;
+                }
+                if let (Instruction::JumpTable, Some(index)) = (&instruction, operands.first()) {
+                    let table = &self.jump_tables[*index as usize];
+                    write!(text, " {{")?;
+                    for (value, target) in (table.min..).zip(&table.targets) {
+                        write!(text, "{}: {:04}, ", value, target)?;
+                    }
+                    write!(text, "_: {:04}}}", table.default)?;
+                }
+                if let (Instruction::Shuffle, Some(index)) = (&instruction, operands.first()) {
+                    let shuffle = &self.shuffles[*index as usize];
+                    write!(text, " {{{}: {:?}}}", shuffle.inputs, shuffle.outputs)?;
+                }
+
+                write!(f, "    {:04}  {:<26}", offset, text)?;
+                if let Some(location) = function.location_at(offset) {
+                    write!(f, " ; {}", location)?;
+                }
+                writeln!(f)?;
+
+                offset += 1 + instruction.operand_count();
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
 }
 
+// How many closures can be made before unreachable ones are first collected
+const MIN_CLOSURE_LIMIT: usize = 1 << 16;
+
+// What a call has to restore when the callee returns
+struct Frame {
+    function: usize,
+    ip: usize,
+    bind_base: usize,
+    closure: usize,
+}
+
+// A pointer is a handle made of the id of the memory it points into in its
+// high half and the cell it points at in its low half. Null is id 0, the
+// variables are id 1 and every `alloc` gets the next id after that, so a stale
+// pointer can't point into a later allocation.
+const VARIABLES_ID: usize = 1;
+const FIRST_ALLOCATION_ID: usize = 2;
+
+fn make_pointer(id: usize, cell: i32) -> i64 {
+    ((id as i64) << 32) | (cell as u32 as i64)
+}
+
+fn pointer_parts(ptr: i64) -> (usize, i32) {
+    ((ptr as u64 >> 32) as usize, ptr as i32)
+}
+
+// The elements of a vector are stored back to back, the length is kept
+// separately so that elements without any cells are still counted
+struct Vector {
+    len: usize,
+    cells: Vec<i64>,
+}
+
+// A closure is a handle into `closures`, where its function id is stored
+// followed by its captured values. Plain functions referenced with `&name`
+// get the closure without captures at their function id.
 struct Evaluator {
     program: Program,
 
     current_function: usize,
     ip: usize,
+    instruction_start: usize,
+    // binds are numbered from the start of the current call's binds
+    bind_base: usize,
+    closure: usize,
 
     data_stack: Vec<i64>,
-    return_stack: Vec<usize>,
+    return_stack: Vec<Frame>,
     bind_stack: Vec<i64>,
     variables: Vec<i64>,
+    // collected closures leave an empty slot to be reused
+    closures: Vec<Option<Box<[i64]>>>,
+    free_closures: Vec<usize>,
+    // the number of closures after which unreachable ones are collected
+    closure_limit: usize,
+    // the cells of every `alloc` by id, freed ones are dropped but keep their
+    // slot so that using them is reported
+    allocations: Vec<Option<Box<[i64]>>>,
+    // the cells of every vector, a vector is its index in here
+    vectors: Vec<Vector>,
+    // constants report integer overflow instead of wrapping
+    checked_arithmetic: bool,
+    output: Output,
 }
 
 impl Evaluator {
-    fn new(program: Program) -> Self {
+    fn new(program: Program) -> Result<Self, Diagnostic> {
         let variable_size = program.variable_size;
-        Self {
+        let closures: Vec<_> = (0..program.functions.len() as i64)
+            .map(|function_id| Some(Box::from([function_id])))
+            .collect();
+        let closure_limit = closures.len() + MIN_CLOSURE_LIMIT;
+        Ok(Self {
             program,
             current_function: 0,
             ip: 0,
+            instruction_start: 0,
+            bind_base: 0,
+            closure: 0,
             data_stack: Vec::new(),
             return_stack: Vec::new(),
             bind_stack: Vec::new(),
-            variables: vec![0; variable_size],
-        }
+            variables: zeroed_cells(variable_size)?,
+            closures,
+            free_closures: Vec::new(),
+            closure_limit,
+            allocations: Vec::new(),
+            vectors: Vec::new(),
+            checked_arithmetic: false,
+            output: Output::Stdout,
+        })
     }
 
     fn prepare_for_program_evaluation(&mut self) {
@@ -136,189 +465,48 @@ impl Evaluator {
     }
 }
 
-// pub fn constant_evaluate(code: parser::IRChunk) -> Result<parser::Constant, String> {
-// 	let mut stack = Vec::new();
-// 	let mut block_stack = Vec::new();
-
-// 	let mut ip = 0;
-// 	while ip < code.len() {
-// 		let instruction = &code[ip];
-
-// 		use parser::IRKind::*;
-// 		match &instruction.kind {
-// 			// Literals
-// 			PushBool(value) => stack.push(parser::Constant::Bool(*value)),
-// 			PushInt(value) => stack.push(parser::Constant::Int(*value)),
-// 			PushStr(value) => stack.push(parser::Constant::Str(value.clone())),
-
-// 			// Keywords
-// 			End => todo!(),
-// 			If => todo!(),
-// 			Elif => todo!(),
-// 			Else => todo!(),
-// 			While => block_stack.push(Block::While(ip)),
-// 			Let => todo!(),
-// 			Then => todo!(),
-// 			Do => todo!(),
-// 			In => todo!(),
-// 			Def(name) => todo!(),
-// 			FunctionArgument(ty) => todo!(),
-// 			Var(name) => todo!(),
-// 			Struct(name) => todo!(),
-// 			StructField(ty) => todo!(),
-// 			Include(path) => todo!(),
-// 			DashDash => todo!(),
-
-// 			// Operators
-// 			Dup => match stack.last().ok_or("Stack underflow!".to_string())? {
-// 				parser::Constant::Bool(value) => stack.push(parser::Constant::Bool(*value)),
-// 				parser::Constant::Int(value) => stack.push(parser::Constant::Int(*value)),
-// 				parser::Constant::Str(value) => stack.push(parser::Constant::Str(value.clone())),
-// 			},
-// 			Over => {
-// 				if stack.len() < 2 {
-// 					return Err("Stack underflow!".to_string());
-// 				}
-
-// 				match &stack[stack.len() - 2] {
-// 					parser::Constant::Bool(value) => stack.push(parser::Constant::Bool(*value)),
-// 					parser::Constant::Int(value) => stack.push(parser::Constant::Int(*value)),
-// 					parser::Constant::Str(value) => stack.push(parser::Constant::Str(value.clone())),
-// 				}
-// 			}
-// 			Drop => {
-// 				stack.pop().ok_or("Stack underflow!".to_string())?;
-// 			}
-// 			Print => {
-// 				let value = stack.pop().ok_or("Stack underflow!".to_string())?;
-// 				match value {
-// 					parser::Constant::Bool(value) => println!("{}", value),
-// 					parser::Constant::Int(value) => println!("{}", value),
-// 					parser::Constant::Str(value) => println!("{}", value),
-// 				}
-// 			}
-// 			Add => {
-// 				let a = stack.pop().ok_or("Stack underflow!".to_string())?;
-// 				let b = stack.pop().ok_or("Stack underflow!".to_string())?;
-
-// 				let a = if let parser::Constant::Int(x) = a {
-// 					x
-// 				} else {
-// 					return Err("Addition is only an integer operation!".to_string());
-// 				};
-
-// 				let b = if let parser::Constant::Int(x) = b {
-// 					x
-// 				} else {
-// 					return Err("Addition is only an integer operation!".to_string());
-// 				};
-
-// 				stack.push(parser::Constant::Int(a + b));
-// 			}
-// 			Subtract => {
-// 				let a = stack.pop().ok_or("Stack underflow!".to_string())?;
-// 				let b = stack.pop().ok_or("Stack underflow!".to_string())?;
-
-// 				let a = if let parser::Constant::Int(x) = a {
-// 					x
-// 				} else {
-// 					return Err("Subtraction is only an integer operation!".to_string());
-// 				};
-
-// 				let b = if let parser::Constant::Int(x) = b {
-// 					x
-// 				} else {
-// 					return Err("Subtraction is only an integer operation!".to_string());
-// 				};
-
-// 				stack.push(parser::Constant::Int(a - b));
-// 			}
-// 			Multiply => {
-// 				let a = stack.pop().ok_or("Stack underflow!".to_string())?;
-// 				let b = stack.pop().ok_or("Stack underflow!".to_string())?;
-
-// 				let a = if let parser::Constant::Int(x) = a {
-// 					x
-// 				} else {
-// 					return Err("Multiplication is only an integer operation!".to_string());
-// 				};
-
-// 				let b = if let parser::Constant::Int(x) = b {
-// 					x
-// 				} else {
-// 					return Err("Multiplication is only an integer operation!".to_string());
-// 				};
-
-// 				stack.push(parser::Constant::Int(a * b));
-// 			}
-// 			Divide => {
-// 				let a = stack.pop().ok_or("Stack underflow!".to_string())?;
-// 				let b = stack.pop().ok_or("Stack underflow!".to_string())?;
-
-// 				let a = if let parser::Constant::Int(x) = a {
-// 					x
-// 				} else {
-// 					return Err("Division is only an integer operation!".to_string());
-// 				};
-
-// 				let b = if let parser::Constant::Int(x) = b {
-// 					x
-// 				} else {
-// 					return Err("Division is only an integer operation!".to_string());
-// 				};
-
-// 				stack.push(parser::Constant::Int(a / b));
-// 			}
-// 			Eq => {
-// 				let a = stack.pop().ok_or("Stack underflow!".to_string())?;
-// 				let b = stack.pop().ok_or("Stack underflow!".to_string())?;
-
-// 				match a {
-// 					parser::Constant::Bool(a) => {
-// 						if let parser::Constant::Bool(b) = b {
-// 							stack.push(parser::Constant::Bool(a == b));
-// 						} else {
-// 							return Err(
-// 								"Cannot evaluate equality between values of different types!".to_string(),
-// 							);
-// 						}
-// 					}
-// 					parser::Constant::Int(a) => {
-// 						if let parser::Constant::Int(b) = b {
-// 							stack.push(parser::Constant::Bool(a == b));
-// 						} else {
-// 							return Err(
-// 								"Cannot evaluate equality between values of different types!".to_string(),
-// 							);
-// 						}
-// 					}
-// 					parser::Constant::Str(a) => {
-// 						if let parser::Constant::Str(b) = b {
-// 							stack.push(parser::Constant::Bool(a == b));
-// 						} else {
-// 							return Err(
-// 								"Cannot evaluate equality between values of different types!".to_string(),
-// 							);
-// 						}
-// 					}
-// 				}
-// 			}
-// 			Call(name) => todo!(),
-// 			_ => todo!(),
-// 		}
-// 	}
-
-// 	if stack.len() > 1 {
-// 		return Err("Unhandled data in constant evaluation!".to_string());
-// 	}
-
-// 	stack
-// 		.pop()
-// 		.ok_or("Code does not evaluate to any value!".to_string())
-// }
-
-pub fn evaluate(program: Program) -> Result<(), String> {
-    let mut evaluator = Evaluator::new(program);
+pub fn evaluate_constant(
+    program: Program,
+    ty: &parser::TypeSignature,
+    output: Output,
+) -> Result<parser::Constant, Diagnostic> {
+    let mut evaluator = Evaluator::new(program)?;
+    evaluator.checked_arithmetic = true;
+    evaluator.output = output;
+    evaluator.evaluate_global_function()?;
+
+    let value = evaluator
+        .data_stack
+        .pop()
+        .expect("The typer checks that constants evaluate to a single value");
+
+    use parser::TypeSignature::*;
+    match ty {
+        Bool => Ok(parser::Constant::Bool(value != 0)),
+        Int => Ok(parser::Constant::Int(value)),
+        Float => Ok(parser::Constant::Float(f64::from_bits(value as u64))),
+        Enum(name) => Ok(parser::Constant::Enum(name.clone(), value)),
+        Str => {
+            let ptr = evaluator
+                .data_stack
+                .pop()
+                .expect("A `str` is a pointer below its length");
+            let string = unsafe {
+                string::ptr_to_str(ptr as *const u8, value as usize).map_err(|err| {
+                    Diagnostic::new(
+                        Code::InvalidString,
+                        format!("Failed to read string constant: {err}"),
+                    )
+                })?
+            };
+            Ok(parser::Constant::Str(string.to_string()))
+        }
+        _ => unreachable!("The typer only allows constants of primitive types"),
+    }
+}
+
+pub fn evaluate(program: Program) -> Result<(), Diagnostic> {
+    let mut evaluator = Evaluator::new(program)?;
     evaluator.evaluate_global_function()?;
     evaluator.prepare_for_program_evaluation();
 
@@ -327,7 +515,9 @@ pub fn evaluate(program: Program) -> Result<(), String> {
             .code
             .len()
     {
-        let returning_main = evaluator.evaluate_instruction()?;
+        let returning_main = evaluator
+            .evaluate_instruction()
+            .map_err(|err| evaluator.locate_error(err))?;
         if returning_main {
             break;
         }
@@ -337,35 +527,298 @@ pub fn evaluate(program: Program) -> Result<(), String> {
 }
 
 impl Evaluator {
-    fn evaluate_global_function(&mut self) -> Result<(), String> {
+    fn evaluate_global_function(&mut self) -> Result<(), Diagnostic> {
         while self.ip < self.program.functions[0].code.len() {
-            self.evaluate_instruction()?;
+            self.evaluate_instruction()
+                .map_err(|err| self.locate_error(err))?;
         }
         Ok(())
     }
 
-    fn evaluate_instruction(&mut self) -> Result<bool, String> {
-        let instruction = self.program.functions[self.current_function].code[self.ip] as u8;
-        self.ip += 1;
+    fn locate_error(&self, err: Diagnostic) -> Diagnostic {
+        let function = &self.program.functions[self.current_function];
+        match function.location_at(self.instruction_start) {
+            Some(location) if err.location.is_none() => err.at(location.clone()),
+            _ => err,
+        }
+    }
+
+    // reads the struct width, field offset and field width of a field instruction
+    fn field_operands(&mut self) -> (usize, usize, usize) {
+        let code = &self.program.functions[self.current_function].code;
+        let operands = (
+            code[self.ip] as usize,
+            code[self.ip + 1] as usize,
+            code[self.ip + 2] as usize,
+        );
+        self.ip += 3;
+        operands
+    }
+
+    fn print(&self, value: impl std::fmt::Display) {
+        match self.output {
+            Output::Stdout => println!("{}", value),
+            Output::Stderr => eprintln!("{}", value),
+        }
+    }
+
+    // Integer arithmetic wraps at runtime like it does in a release build,
+    // while a constant that overflows is an error in the program
+    fn integer_operation(
+        &mut self,
+        checked: fn(i64, i64) -> Option<i64>,
+        wrapping: fn(i64, i64) -> i64,
+    ) -> Result<(), Diagnostic> {
+        let b = self.data_stack.pop().ok_or_else(stack_underflow)?;
+        let a = self.data_stack.pop().ok_or_else(stack_underflow)?;
+        let result = self.integer_result(checked(a, b), || wrapping(a, b))?;
+        self.data_stack.push(result);
+        Ok(())
+    }
+
+    fn integer_result(
+        &self,
+        checked: Option<i64>,
+        wrapping: impl FnOnce() -> i64,
+    ) -> Result<i64, Diagnostic> {
+        if self.checked_arithmetic {
+            checked.ok_or_else(|| {
+                Diagnostic::new(
+                    Code::InvalidConstant,
+                    "Integer overflow while evaluating a constant!",
+                )
+            })
+        } else {
+            Ok(wrapping())
+        }
+    }
+
+    // the index of the deepest of the top `n` values
+    fn stack_depth(&self, n: usize) -> Result<usize, Diagnostic> {
+        self.data_stack
+            .len()
+            .checked_sub(n)
+            .ok_or_else(stack_underflow)
+    }
+
+    fn pop_vector(&mut self) -> Result<usize, Diagnostic> {
+        let vec = self.data_stack.pop().ok_or_else(stack_underflow)?;
+        usize::try_from(vec)
+            .ok()
+            .filter(|vec| *vec < self.vectors.len())
+            .ok_or_else(|| {
+                Diagnostic::new(
+                    Code::InvalidPointer,
+                    format!("{} is not a valid vector!", vec),
+                )
+            })
+    }
+
+    fn add_closure(&mut self, closure: Box<[i64]>) -> usize {
+        if self.free_closures.is_empty() && self.closures.len() >= self.closure_limit {
+            self.collect_closures();
+        }
+
+        match self.free_closures.pop() {
+            Some(index) => {
+                self.closures[index] = Some(closure);
+                index
+            }
+            None => {
+                self.closures.push(Some(closure));
+                self.closures.len() - 1
+            }
+        }
+    }
+
+    // Frees the closures no cell refers to. Any cell that holds a closure's
+    // handle keeps it alive, whatever its type, so a closure is only freed
+    // when nothing could call it anymore.
+    fn collect_closures(&mut self) {
+        fn mark(reachable: &mut [bool], pending: &mut Vec<usize>, value: i64) {
+            if let Some(index) = usize::try_from(value)
+                .ok()
+                .filter(|index| *index < reachable.len() && !reachable[*index])
+            {
+                reachable[index] = true;
+                pending.push(index);
+            }
+        }
+
+        let mut reachable = vec![false; self.closures.len()];
+        let mut pending = Vec::new();
+
+        let frames = self.return_stack.iter().map(|frame| frame.closure as i64);
+        let vectors = self.vectors.iter().flat_map(|vector| vector.cells.iter());
+        let allocations = self
+            .allocations
+            .iter()
+            .flatten()
+            .flat_map(|memory| memory.iter());
+        self.data_stack
+            .iter()
+            .chain(&self.bind_stack)
+            .chain(&self.variables)
+            .chain(vectors)
+            .chain(allocations)
+            .copied()
+            .chain(frames)
+            .chain([self.closure as i64])
+            .for_each(|value| mark(&mut reachable, &mut pending, value));
+
+        while let Some(index) = pending.pop() {
+            if let Some(closure) = &self.closures[index] {
+                for value in &closure[1..] {
+                    mark(&mut reachable, &mut pending, *value);
+                }
+            }
+        }
+
+        let functions = self.program.functions.len();
+        for (index, closure) in self.closures.iter_mut().enumerate().skip(functions) {
+            if !reachable[index] && closure.take().is_some() {
+                self.free_closures.push(index);
+            }
+        }
+        self.closure_limit = self
+            .closure_limit
+            .max(2 * (self.closures.len() - self.free_closures.len()));
+    }
+
+    // reads the length and element width of an array instruction
+    fn array_operands(&mut self) -> (usize, usize) {
+        let code = &self.program.functions[self.current_function].code;
+        let operands = (code[self.ip] as usize, code[self.ip + 1] as usize);
+        self.ip += 2;
+        operands
+    }
+
+    // reads the bind id of the index and the jump of a `for` instruction
+    fn for_operands(&mut self) -> (usize, i64) {
+        let code = &self.program.functions[self.current_function].code;
+        let operands = (
+            self.bind_base + code[self.ip] as usize,
+            code[self.ip + 1] as i64,
+        );
+        self.ip += 2;
+        operands
+    }
+
+    // floats are stored bit-cast in the stack cells
+    fn pop_float(&mut self) -> Result<f64, Diagnostic> {
+        let bits = self.data_stack.pop().ok_or_else(stack_underflow)?;
+        Ok(f64::from_bits(bits as u64))
+    }
+
+    fn push_float(&mut self, value: f64) {
+        self.data_stack.push(value.to_bits() as i64);
+    }
+
+    // The cells a pointer points at, anything that isn't in a variable or a
+    // live allocation is null, freed or was moved out of bounds by `offset`
+    fn memory(&mut self, ptr: i64, width: usize) -> Result<&mut [i64], Diagnostic> {
+        let (id, cell) = pointer_parts(ptr);
+        let memory = match id {
+            0 => {
+                return Err(Diagnostic::new(
+                    Code::NullDereference,
+                    "Dereferenced a null pointer!",
+                ))
+            }
+            VARIABLES_ID => &mut self.variables[..],
+            _ => match self.allocations.get_mut(id - FIRST_ALLOCATION_ID) {
+                Some(Some(memory)) => &mut memory[..],
+                Some(None) => {
+                    return Err(Diagnostic::new(
+                        Code::UseAfterFree,
+                        "Used memory after it was freed!",
+                    ))
+                }
+                None => {
+                    return Err(Diagnostic::new(
+                        Code::InvalidPointer,
+                        "Dereferenced a pointer outside of any variable or allocation!",
+                    ))
+                }
+            },
+        };
+
+        let cells = memory.len();
+        usize::try_from(cell)
+            .ok()
+            .filter(|cell| cell + width <= cells)
+            .map(|cell| &mut memory[cell..cell + width])
+            .ok_or_else(|| {
+                Diagnostic::new(
+                    Code::OutOfBounds,
+                    format!(
+                        "Accessed cell {} of an allocation of {} cells!",
+                        cell as i64 + width as i64 - 1,
+                        cells
+                    ),
+                )
+            })
+    }
+
+    // strings are never freed so their bytes outlive the evaluation
+    fn pop_bytes<'a>(&mut self) -> Result<&'a [u8], Diagnostic> {
+        let (ptr, len) = self.pop_str()?;
+        Ok(unsafe { string::ptr_to_bytes(ptr, len) })
+    }
+
+    fn push_bytes(&mut self, bytes: &[u8]) {
+        self.data_stack.push(bytes.as_ptr() as i64);
+        self.data_stack.push(bytes.len() as i64);
+    }
 
-        let instruction = unsafe { std::mem::transmute::<u8, Instruction>(instruction) };
+    // strings made while running join the constants in the string table,
+    // NUL and all
+    fn push_new_str(&mut self, string: String) {
+        let mut bytes = string.into_bytes();
+        bytes.push(0);
+        let bytes = bytes.into_boxed_slice();
+        self.push_bytes(&bytes[..bytes.len() - 1]);
+        self.program.strings.push(bytes);
+    }
+
+    fn pop_str(&mut self) -> Result<(*const u8, usize), Diagnostic> {
+        let len = self.data_stack.pop().ok_or_else(stack_underflow)?;
+        let ptr = self.data_stack.pop().ok_or_else(stack_underflow)?;
+        Ok((ptr as *const u8, len as usize))
+    }
+
+    fn call(&mut self, callee_id: usize, return_ip: usize, closure: usize) {
+        self.return_stack.push(Frame {
+            function: self.current_function,
+            ip: return_ip,
+            bind_base: self.bind_base,
+            closure: self.closure,
+        });
+
+        self.current_function = callee_id;
+        self.ip = 0;
+        self.bind_base = self.bind_stack.len();
+        self.closure = closure;
+    }
+
+    fn evaluate_instruction(&mut self) -> Result<bool, Diagnostic> {
+        self.instruction_start = self.ip;
+        let instruction =
+            Instruction::decode(self.program.functions[self.current_function].code[self.ip]);
+        self.ip += 1;
 
         use Instruction::*;
         match instruction {
             _NoOp => panic!("Hit a no-op during evaluation!"),
 
             PushBool => {
-                let value: i64 = unsafe {
-                    std::mem::transmute(self.program.functions[self.current_function].code[self.ip])
-                };
+                let value = self.program.functions[self.current_function].code[self.ip] as i64;
                 self.ip += 1;
 
                 self.data_stack.push(value);
             }
             PushInt => {
-                let value: i64 = unsafe {
-                    std::mem::transmute(self.program.functions[self.current_function].code[self.ip])
-                };
+                let value = self.program.functions[self.current_function].code[self.ip] as i64;
                 self.ip += 1;
 
                 self.data_stack.push(value);
@@ -374,233 +827,701 @@ impl Evaluator {
                 let idx = self.program.functions[self.current_function].code[self.ip] as usize;
                 self.ip += 1;
 
-                let string = self.program.strings[idx].as_ref().as_ptr();
-                self.data_stack.push(string as i64);
+                // the string table keeps a NUL after each string
+                let string = &self.program.strings[idx];
+                self.data_stack.push(string.as_ptr() as i64);
+                self.data_stack.push(string.len() as i64 - 1);
             }
             Dup => {
-                let top = *self
-                    .data_stack
-                    .last()
-                    .ok_or("Stack underflow!".to_string())?;
+                let top = *self.data_stack.last().ok_or_else(stack_underflow)?;
                 self.data_stack.push(top);
             }
             Over => {
                 if self.data_stack.len() < 2 {
-                    return Err("Stack underflow!".to_string());
+                    return Err(stack_underflow());
                 }
 
                 let over = self.data_stack[self.data_stack.len() - 2];
                 self.data_stack.push(over);
             }
             Drop => {
-                self.data_stack
-                    .pop()
-                    .ok_or("Stack underflow!".to_string())?;
+                self.data_stack.pop().ok_or_else(stack_underflow)?;
             }
             Swap => {
-                let a = self
-                    .data_stack
-                    .pop()
-                    .ok_or("Stack underflow!".to_string())?;
-                let b = self
-                    .data_stack
-                    .pop()
-                    .ok_or("Stack underflow!".to_string())?;
+                let a = self.data_stack.pop().ok_or_else(stack_underflow)?;
+                let b = self.data_stack.pop().ok_or_else(stack_underflow)?;
                 self.data_stack.push(a);
                 self.data_stack.push(b);
             }
+            Rot => {
+                let start = self.stack_depth(3)?;
+                self.data_stack[start..].rotate_left(1);
+            }
+            Unrot => {
+                let start = self.stack_depth(3)?;
+                self.data_stack[start..].rotate_right(1);
+            }
+            Nip => {
+                let start = self.stack_depth(2)?;
+                self.data_stack.remove(start);
+            }
+            Tuck => {
+                let start = self.stack_depth(2)?;
+                let top = self.data_stack[start + 1];
+                self.data_stack.insert(start, top);
+            }
+            TwoDup => {
+                let start = self.stack_depth(2)?;
+                self.data_stack.extend_from_within(start..);
+            }
+            TwoDrop => {
+                let start = self.stack_depth(2)?;
+                self.data_stack.truncate(start);
+            }
+            TwoSwap => {
+                let start = self.stack_depth(4)?;
+                self.data_stack[start..].rotate_left(2);
+            }
+            TwoOver => {
+                let start = self.stack_depth(4)?;
+                self.data_stack.extend_from_within(start..start + 2);
+            }
+            Pick => {
+                let index = self.program.functions[self.current_function].code[self.ip] as usize;
+                self.ip += 1;
+
+                let start = self.stack_depth(index + 1)?;
+                self.data_stack.push(self.data_stack[start]);
+            }
+            Roll => {
+                let index = self.program.functions[self.current_function].code[self.ip] as usize;
+                self.ip += 1;
+
+                let start = self.stack_depth(index + 1)?;
+                self.data_stack[start..].rotate_left(1);
+            }
             PrintBool => {
-                let top = self
-                    .data_stack
-                    .pop()
-                    .ok_or("Stack underflow!".to_string())?
-                    != 0;
-                println!("{}", top);
+                let top = self.data_stack.pop().ok_or_else(stack_underflow)? != 0;
+                self.print(top);
             }
             PrintInt => {
-                let top = self
-                    .data_stack
-                    .pop()
-                    .ok_or("Stack underflow!".to_string())?;
-                println!("{}", top);
+                let top = self.data_stack.pop().ok_or_else(stack_underflow)?;
+                self.print(top);
+            }
+            // pointers print as their handle, null pointers as `null`
+            PrintPtr => {
+                let top = self.data_stack.pop().ok_or_else(stack_underflow)?;
+                if top == 0 {
+                    self.print("null");
+                } else {
+                    self.print(format!("{:#x}", top));
+                }
             }
             PrintStr => {
-                let ptr = self
-                    .data_stack
-                    .pop()
-                    .ok_or("Stack underflow!".to_string())? as *const u8;
+                let (ptr, len) = self.pop_str()?;
                 let string = unsafe {
-                    string::ptr_to_str(ptr)
-                        .map_err(|err| format!("Failed to read string from data stack: {err}"))?
+                    string::ptr_to_str(ptr, len).map_err(|err| {
+                        Diagnostic::new(
+                            Code::InvalidString,
+                            format!("Failed to read string from data stack: {err}"),
+                        )
+                    })?
                 };
-                println!("{}", string);
+                self.print(string);
             }
             Call => {
                 let callee_id =
                     self.program.functions[self.current_function].code[self.ip] as usize;
 
-                self.return_stack.push(self.ip + 1);
-                self.return_stack.push(self.current_function);
+                self.call(callee_id, self.ip + 1, 0);
+            }
+            CallDynamic => {
+                let closure = self.data_stack.pop().ok_or_else(stack_underflow)?;
+                let (closure, callee_id) = match usize::try_from(closure)
+                    .ok()
+                    .and_then(|index| Some((index, self.closures.get(index)?.as_ref()?[0])))
+                {
+                    Some((index, function_id)) => (index, function_id as usize),
+                    None => {
+                        return Err(Diagnostic::new(
+                            Code::InvalidPointer,
+                            format!("{} is not a valid function!", closure),
+                        ))
+                    }
+                };
 
-                self.current_function = callee_id;
-                self.ip = 0;
+                self.call(callee_id, self.ip, closure);
             }
             Return => {
-                if self.return_stack.len() < 2 {
-                    // returning from
-                    return Ok(true);
-                }
+                let frame = match self.return_stack.pop() {
+                    Some(frame) => frame,
+                    // returning from main
+                    None => return Ok(true),
+                };
 
-                self.current_function = self
-                    .return_stack
-                    .pop()
-                    .expect("We just checked its length!");
-                self.ip = self
-                    .return_stack
-                    .pop()
-                    .expect("We just checked its length!");
+                self.bind_stack.truncate(self.bind_base);
+                self.current_function = frame.function;
+                self.ip = frame.ip;
+                self.bind_base = frame.bind_base;
+                self.closure = frame.closure;
             }
-            And => {
-                let b = self
-                    .data_stack
-                    .pop()
-                    .ok_or("Stack underflow!".to_string())?
-                    != 0;
-                let a = self
+            PushFunction => {
+                let function_id =
+                    self.program.functions[self.current_function].code[self.ip] as usize;
+                self.ip += 1;
+
+                // the closure of a plain function is at its function id
+                self.data_stack.push(function_id as i64);
+            }
+            MakeClosure => {
+                let code = &self.program.functions[self.current_function].code;
+                let (function_id, ncaptures) = (code[self.ip] as i64, code[self.ip + 1] as usize);
+                self.ip += 2;
+
+                let captures_idx = self
                     .data_stack
-                    .pop()
-                    .ok_or("Stack underflow!".to_string())?
-                    != 0;
+                    .len()
+                    .checked_sub(ncaptures)
+                    .ok_or_else(stack_underflow)?;
+                let closure = std::iter::once(function_id)
+                    .chain(self.data_stack.drain(captures_idx..))
+                    .collect::<Box<[i64]>>();
+                let closure = self.add_closure(closure);
+                self.data_stack.push(closure as i64);
+            }
+            PushCapture => {
+                let index = self.program.functions[self.current_function].code[self.ip] as usize;
+                self.ip += 1;
+
+                let value = match &self.closures[self.closure] {
+                    Some(closure) => closure[1 + index],
+                    None => unreachable!("the running closure is never collected"),
+                };
+                self.data_stack.push(value);
+            }
+            And => {
+                let b = self.data_stack.pop().ok_or_else(stack_underflow)? != 0;
+                let a = self.data_stack.pop().ok_or_else(stack_underflow)? != 0;
                 self.data_stack.push((a && b) as i64);
             }
             Or => {
-                let b = self
-                    .data_stack
-                    .pop()
-                    .ok_or("Stack underflow!".to_string())?
-                    != 0;
-                let a = self
-                    .data_stack
-                    .pop()
-                    .ok_or("Stack underflow!".to_string())?
-                    != 0;
+                let b = self.data_stack.pop().ok_or_else(stack_underflow)? != 0;
+                let a = self.data_stack.pop().ok_or_else(stack_underflow)? != 0;
                 self.data_stack.push((a || b) as i64);
             }
             Not => {
-                let a = self
-                    .data_stack
-                    .pop()
-                    .ok_or("Stack underflow!".to_string())?
-                    != 0;
+                let a = self.data_stack.pop().ok_or_else(stack_underflow)? != 0;
                 self.data_stack.push((!a) as i64);
             }
-            Add => {
-                let b = self
-                    .data_stack
-                    .pop()
-                    .ok_or("Stack underflow!".to_string())?;
-                let a = self
-                    .data_stack
-                    .pop()
-                    .ok_or("Stack underflow!".to_string())?;
-                self.data_stack.push(a + b);
-            }
-            Subtract => {
-                let b = self
-                    .data_stack
-                    .pop()
-                    .ok_or("Stack underflow!".to_string())?;
-                let a = self
-                    .data_stack
-                    .pop()
-                    .ok_or("Stack underflow!".to_string())?;
-                self.data_stack.push(a - b);
-            }
-            Multiply => {
-                let b = self
-                    .data_stack
-                    .pop()
-                    .ok_or("Stack underflow!".to_string())?;
-                let a = self
-                    .data_stack
-                    .pop()
-                    .ok_or("Stack underflow!".to_string())?;
-                self.data_stack.push(a * b);
-            }
+            Add => self.integer_operation(i64::checked_add, i64::wrapping_add)?,
+            Subtract => self.integer_operation(i64::checked_sub, i64::wrapping_sub)?,
+            Multiply => self.integer_operation(i64::checked_mul, i64::wrapping_mul)?,
             Divide => {
-                let b = self
-                    .data_stack
-                    .pop()
-                    .ok_or("Stack underflow!".to_string())?;
-                let a = self
-                    .data_stack
-                    .pop()
-                    .ok_or("Stack underflow!".to_string())?;
-                self.data_stack.push(a / b);
+                let b = self.data_stack.pop().ok_or_else(stack_underflow)?;
+                let a = self.data_stack.pop().ok_or_else(stack_underflow)?;
+                if b == 0 {
+                    return Err(Diagnostic::new(Code::DivisionByZero, "Division by zero!"));
+                }
+                let quotient = self.integer_result(a.checked_div(b), || a.wrapping_div(b))?;
+                self.data_stack.push(quotient);
             }
             Eq => {
-                let b = self
-                    .data_stack
-                    .pop()
-                    .ok_or("Stack underflow!".to_string())?;
-                let a = self
-                    .data_stack
-                    .pop()
-                    .ok_or("Stack underflow!".to_string())?;
+                let b = self.data_stack.pop().ok_or_else(stack_underflow)?;
+                let a = self.data_stack.pop().ok_or_else(stack_underflow)?;
                 self.data_stack.push((a == b) as i64);
             }
             Neq => {
-                let b = self
-                    .data_stack
-                    .pop()
-                    .ok_or("Stack underflow!".to_string())?;
-                let a = self
-                    .data_stack
-                    .pop()
-                    .ok_or("Stack underflow!".to_string())?;
+                let b = self.data_stack.pop().ok_or_else(stack_underflow)?;
+                let a = self.data_stack.pop().ok_or_else(stack_underflow)?;
                 self.data_stack.push((a != b) as i64);
             }
             Lt => {
-                let b = self
-                    .data_stack
-                    .pop()
-                    .ok_or("Stack underflow!".to_string())?;
-                let a = self
-                    .data_stack
-                    .pop()
-                    .ok_or("Stack underflow!".to_string())?;
+                let b = self.data_stack.pop().ok_or_else(stack_underflow)?;
+                let a = self.data_stack.pop().ok_or_else(stack_underflow)?;
                 self.data_stack.push((a < b) as i64);
             }
             Gt => {
-                let b = self
-                    .data_stack
-                    .pop()
-                    .ok_or("Stack underflow!".to_string())?;
-                let a = self
-                    .data_stack
-                    .pop()
-                    .ok_or("Stack underflow!".to_string())?;
+                let b = self.data_stack.pop().ok_or_else(stack_underflow)?;
+                let a = self.data_stack.pop().ok_or_else(stack_underflow)?;
                 self.data_stack.push((a > b) as i64);
             }
-            Assign => {
-                let ptr = self
-                    .data_stack
-                    .pop()
-                    .ok_or("Stack underflow!".to_string())? as *mut i64;
-                let value = self
-                    .data_stack
-                    .pop()
-                    .ok_or("Stack underflow!".to_string())?;
-                unsafe {
-                    *ptr = value;
+            Modulo => {
+                let b = self.data_stack.pop().ok_or_else(stack_underflow)?;
+                let a = self.data_stack.pop().ok_or_else(stack_underflow)?;
+                if b == 0 {
+                    return Err(Diagnostic::new(Code::DivisionByZero, "Division by zero!"));
+                }
+                let remainder = self.integer_result(a.checked_rem(b), || a.wrapping_rem(b))?;
+                self.data_stack.push(remainder);
+            }
+            Le => {
+                let b = self.data_stack.pop().ok_or_else(stack_underflow)?;
+                let a = self.data_stack.pop().ok_or_else(stack_underflow)?;
+                self.data_stack.push((a <= b) as i64);
+            }
+            Ge => {
+                let b = self.data_stack.pop().ok_or_else(stack_underflow)?;
+                let a = self.data_stack.pop().ok_or_else(stack_underflow)?;
+                self.data_stack.push((a >= b) as i64);
+            }
+            Negate => {
+                let a = self.data_stack.pop().ok_or_else(stack_underflow)?;
+                let negated = self.integer_result(a.checked_neg(), || a.wrapping_neg())?;
+                self.data_stack.push(negated);
+            }
+            BitAnd => {
+                let b = self.data_stack.pop().ok_or_else(stack_underflow)?;
+                let a = self.data_stack.pop().ok_or_else(stack_underflow)?;
+                self.data_stack.push(a & b);
+            }
+            BitOr => {
+                let b = self.data_stack.pop().ok_or_else(stack_underflow)?;
+                let a = self.data_stack.pop().ok_or_else(stack_underflow)?;
+                self.data_stack.push(a | b);
+            }
+            BitXor => {
+                let b = self.data_stack.pop().ok_or_else(stack_underflow)?;
+                let a = self.data_stack.pop().ok_or_else(stack_underflow)?;
+                self.data_stack.push(a ^ b);
+            }
+            BitNot => {
+                let a = self.data_stack.pop().ok_or_else(stack_underflow)?;
+                self.data_stack.push(!a);
+            }
+            // shifting by 64 or more (or by a negative amount) shifts every
+            // bit out, `shr` is arithmetic so it fills with the sign bit
+            Shl => {
+                let b = self.data_stack.pop().ok_or_else(stack_underflow)?;
+                let a = self.data_stack.pop().ok_or_else(stack_underflow)?;
+                let shifted = u32::try_from(b).ok().and_then(|b| a.checked_shl(b));
+                self.data_stack.push(shifted.unwrap_or(0));
+            }
+            Shr => {
+                let b = self.data_stack.pop().ok_or_else(stack_underflow)?;
+                let a = self.data_stack.pop().ok_or_else(stack_underflow)?;
+                let shifted = u32::try_from(b).ok().and_then(|b| a.checked_shr(b));
+                self.data_stack.push(shifted.unwrap_or(a >> 63));
+            }
+            DivMod => {
+                let b = self.data_stack.pop().ok_or_else(stack_underflow)?;
+                let a = self.data_stack.pop().ok_or_else(stack_underflow)?;
+                if b == 0 {
+                    return Err(Diagnostic::new(Code::DivisionByZero, "Division by zero!"));
+                }
+                let quotient = self.integer_result(a.checked_div(b), || a.wrapping_div(b))?;
+                let remainder = self.integer_result(a.checked_rem(b), || a.wrapping_rem(b))?;
+                self.data_stack.push(quotient);
+                self.data_stack.push(remainder);
+            }
+            FloatAdd => {
+                let b = self.pop_float()?;
+                let a = self.pop_float()?;
+                self.push_float(a + b);
+            }
+            FloatSubtract => {
+                let b = self.pop_float()?;
+                let a = self.pop_float()?;
+                self.push_float(a - b);
+            }
+            FloatMultiply => {
+                let b = self.pop_float()?;
+                let a = self.pop_float()?;
+                self.push_float(a * b);
+            }
+            FloatDivide => {
+                let b = self.pop_float()?;
+                let a = self.pop_float()?;
+                self.push_float(a / b);
+            }
+            FloatEq => {
+                let b = self.pop_float()?;
+                let a = self.pop_float()?;
+                self.data_stack.push((a == b) as i64);
+            }
+            FloatNeq => {
+                let b = self.pop_float()?;
+                let a = self.pop_float()?;
+                self.data_stack.push((a != b) as i64);
+            }
+            FloatLt => {
+                let b = self.pop_float()?;
+                let a = self.pop_float()?;
+                self.data_stack.push((a < b) as i64);
+            }
+            FloatGt => {
+                let b = self.pop_float()?;
+                let a = self.pop_float()?;
+                self.data_stack.push((a > b) as i64);
+            }
+            FloatLe => {
+                let b = self.pop_float()?;
+                let a = self.pop_float()?;
+                self.data_stack.push((a <= b) as i64);
+            }
+            FloatGe => {
+                let b = self.pop_float()?;
+                let a = self.pop_float()?;
+                self.data_stack.push((a >= b) as i64);
+            }
+            StrEq => {
+                let (b_ptr, b_len) = self.pop_str()?;
+                let (a_ptr, a_len) = self.pop_str()?;
+                let equal = unsafe {
+                    string::ptr_to_bytes(a_ptr, a_len) == string::ptr_to_bytes(b_ptr, b_len)
+                };
+                self.data_stack.push(equal as i64);
+            }
+            StrNeq => {
+                let (b_ptr, b_len) = self.pop_str()?;
+                let (a_ptr, a_len) = self.pop_str()?;
+                let equal = unsafe {
+                    string::ptr_to_bytes(a_ptr, a_len) == string::ptr_to_bytes(b_ptr, b_len)
+                };
+                self.data_stack.push((!equal) as i64);
+            }
+            StrLen => {
+                let (_, len) = self.pop_str()?;
+                self.data_stack.push(len as i64);
+            }
+            StrConcat => {
+                let b = self.pop_bytes()?;
+                let a = self.pop_bytes()?;
+                let string = String::from_utf8_lossy(&[a, b].concat()).into_owned();
+                self.push_new_str(string);
+            }
+            StrSlice => {
+                let end = self.data_stack.pop().ok_or_else(stack_underflow)?;
+                let start = self.data_stack.pop().ok_or_else(stack_underflow)?;
+                let s = self.pop_bytes()?;
+                let string = str::from_utf8(s).map_err(|err| {
+                    Diagnostic::new(
+                        Code::InvalidString,
+                        format!("Failed to read string from data stack: {err}"),
+                    )
+                })?;
+
+                let range = match (usize::try_from(start), usize::try_from(end)) {
+                    (Ok(start), Ok(end)) if start <= end && end <= s.len() => start..end,
+                    _ => {
+                        return Err(Diagnostic::new(
+                            Code::OutOfBounds,
+                            format!(
+                                "Cannot slice {}..{} out of a string of length {}!",
+                                start,
+                                end,
+                                s.len()
+                            ),
+                        ))
+                    }
+                };
+                match string.get(range.clone()) {
+                    Some(slice) => self.push_bytes(slice.as_bytes()),
+                    None => {
+                        let index = if string.is_char_boundary(range.start) {
+                            range.end
+                        } else {
+                            range.start
+                        };
+                        return Err(Diagnostic::new(
+                            Code::InvalidString,
+                            format!(
+                                "Cannot slice {}..{} out of {:?}: byte {} is inside a character!",
+                                range.start, range.end, string, index
+                            ),
+                        ));
+                    }
                 }
             }
+            StrFind => {
+                let needle = self.pop_bytes()?;
+                let s = self.pop_bytes()?;
+                let index = if needle.is_empty() {
+                    Some(0)
+                } else {
+                    s.windows(needle.len()).position(|window| window == needle)
+                };
+                self.data_stack.push(index.map_or(-1, |index| index as i64));
+            }
+            StrStartsWith => {
+                let prefix = self.pop_bytes()?;
+                let s = self.pop_bytes()?;
+                self.data_stack.push(s.starts_with(prefix) as i64);
+            }
+            StrEndsWith => {
+                let suffix = self.pop_bytes()?;
+                let s = self.pop_bytes()?;
+                self.data_stack.push(s.ends_with(suffix) as i64);
+            }
+            StrCompare => {
+                let b = self.pop_bytes()?;
+                let a = self.pop_bytes()?;
+                self.data_stack.push(a.cmp(b) as i64);
+            }
+            // splits at the first delimiter, a string without one is all
+            // before it
+            StrSplit => {
+                let delimiter = self.pop_bytes()?;
+                let s = self.pop_bytes()?;
+                let index = if delimiter.is_empty() {
+                    Some(0)
+                } else {
+                    s.windows(delimiter.len())
+                        .position(|window| window == delimiter)
+                };
+                match index {
+                    Some(index) => {
+                        self.push_bytes(&s[..index]);
+                        self.push_bytes(&s[index + delimiter.len()..]);
+                    }
+                    None => {
+                        self.push_bytes(s);
+                        self.push_bytes(&s[s.len()..]);
+                    }
+                }
+                self.data_stack.push(index.is_some() as i64);
+            }
+            StrTrim => {
+                let s = self.pop_bytes()?;
+                let string = str::from_utf8(s).map_err(|err| {
+                    Diagnostic::new(
+                        Code::InvalidString,
+                        format!("Failed to read string from data stack: {err}"),
+                    )
+                })?;
+                self.push_bytes(string.trim().as_bytes());
+            }
+            StrToInt => {
+                let s = self.pop_bytes()?;
+                let value = str::from_utf8(s)
+                    .ok()
+                    .and_then(|string| string.parse::<i64>().ok());
+                self.data_stack.push(value.unwrap_or(0));
+                self.data_stack.push(value.is_some() as i64);
+            }
+            IntToStr => {
+                let a = self.data_stack.pop().ok_or_else(stack_underflow)?;
+                self.push_new_str(a.to_string());
+            }
+            LoadWide => {
+                let width = self.program.functions[self.current_function].code[self.ip] as usize;
+                self.ip += 1;
+
+                let ptr = self.data_stack.pop().ok_or_else(stack_underflow)?;
+                let cells = self.memory(ptr, width)?.to_vec();
+                self.data_stack.extend(cells);
+            }
+            AssignWide => {
+                let width = self.program.functions[self.current_function].code[self.ip] as usize;
+                self.ip += 1;
+
+                let ptr = self.data_stack.pop().ok_or_else(stack_underflow)?;
+                let start = self.stack_depth(width)?;
+                let values = self.data_stack.split_off(start);
+                self.memory(ptr, width)?.copy_from_slice(&values);
+            }
+            Shuffle => {
+                let index = self.program.functions[self.current_function].code[self.ip] as usize;
+                self.ip += 1;
+
+                let shuffle = &self.program.shuffles[index];
+                let start = self.stack_depth(shuffle.inputs)?;
+                let cells = self.data_stack.split_off(start);
+                self.data_stack
+                    .extend(shuffle.outputs.iter().map(|index| cells[*index]));
+            }
+            FloatNegate => {
+                let a = self.pop_float()?;
+                self.push_float(-a);
+            }
+            PrintFloat => {
+                let top = self.pop_float()?;
+                self.print(format!("{:?}", top));
+            }
+            IntToFloat => {
+                let a = self.data_stack.pop().ok_or_else(stack_underflow)?;
+                self.push_float(a as f64);
+            }
+            // truncates towards zero, saturating at the ends of `int`
+            FloatToInt => {
+                let a = self.pop_float()?;
+                self.data_stack.push(a as i64);
+            }
+            Assign => {
+                let ptr = self.data_stack.pop().ok_or_else(stack_underflow)?;
+                let value = self.data_stack.pop().ok_or_else(stack_underflow)?;
+                self.memory(ptr, 1)?[0] = value;
+            }
             Load => {
-                let ptr = self
-                    .data_stack
-                    .pop()
-                    .ok_or("Stack underflow!".to_string())? as *const i64;
-                self.data_stack.push(unsafe { *ptr });
+                let ptr = self.data_stack.pop().ok_or_else(stack_underflow)?;
+                let value = self.memory(ptr, 1)?[0];
+                self.data_stack.push(value);
+            }
+            Alloc => {
+                let width = self.program.functions[self.current_function].code[self.ip] as usize;
+                self.ip += 1;
+
+                let count = self.data_stack.pop().ok_or_else(stack_underflow)?;
+                let id = FIRST_ALLOCATION_ID + self.allocations.len();
+                // every cell has to be reachable with the low half of a pointer
+                let cells = usize::try_from(count)
+                    .ok()
+                    .and_then(|count| count.checked_mul(width))
+                    .filter(|cells| *cells <= i32::MAX as usize && id <= i32::MAX as usize)
+                    .ok_or_else(|| {
+                        Diagnostic::new(
+                            Code::AllocationFailed,
+                            format!("Cannot allocate {} elements!", count),
+                        )
+                    })?;
+
+                let memory = zeroed_cells(cells)?.into_boxed_slice();
+                self.allocations.push(Some(memory));
+                self.data_stack.push(make_pointer(id, 0));
+            }
+            // freeing null does nothing
+            Free => {
+                let ptr = self.data_stack.pop().ok_or_else(stack_underflow)?;
+                if ptr != 0 {
+                    let allocation = match pointer_parts(ptr) {
+                        (id, 0) if id >= FIRST_ALLOCATION_ID => {
+                            self.allocations.get_mut(id - FIRST_ALLOCATION_ID)
+                        }
+                        _ => None,
+                    };
+                    match allocation {
+                        Some(memory @ Some(_)) => *memory = None,
+                        Some(None) => {
+                            return Err(Diagnostic::new(
+                                Code::DoubleFree,
+                                "Freed memory that was already freed!",
+                            ))
+                        }
+                        None => {
+                            return Err(Diagnostic::new(
+                                Code::InvalidPointer,
+                                "Cannot free a pointer that didn't come from `alloc`!",
+                            ))
+                        }
+                    }
+                }
+            }
+            Zero => {
+                let width = self.program.functions[self.current_function].code[self.ip] as usize;
+                self.ip += 1;
+
+                self.data_stack
+                    .try_reserve(width)
+                    .map_err(|_| allocation_failed(width))?;
+                self.data_stack.resize(self.data_stack.len() + width, 0);
+            }
+            ArrayGet => {
+                let (len, width) = self.array_operands();
+
+                let index = self.data_stack.pop().ok_or_else(stack_underflow)?;
+                let index = check_index(index, len, "an array")?;
+                let start = self.stack_depth(len * width)? + index * width;
+                self.data_stack.extend_from_within(start..start + width);
+            }
+            ArraySet => {
+                let (len, width) = self.array_operands();
+
+                let index = self.data_stack.pop().ok_or_else(stack_underflow)?;
+                let index = check_index(index, len, "an array")?;
+                let array_start = self.stack_depth(len * width)?;
+                let array = self.data_stack.split_off(array_start);
+                let element_start = self.stack_depth(width)?;
+                self.data_stack.extend_from_slice(&array);
+
+                let start = element_start + width + index * width;
+                self.data_stack
+                    .copy_within(element_start..element_start + width, start);
+                self.data_stack.drain(element_start..element_start + width);
+            }
+            CheckIndex => {
+                let len = self.program.functions[self.current_function].code[self.ip] as usize;
+                self.ip += 1;
+
+                let index = *self.data_stack.last().ok_or_else(stack_underflow)?;
+                check_index(index, len, "an array")?;
+            }
+            VecNew => {
+                self.data_stack.push(self.vectors.len() as i64);
+                self.vectors.push(Vector {
+                    len: 0,
+                    cells: Vec::new(),
+                });
+            }
+            VecPush => {
+                let width = self.program.functions[self.current_function].code[self.ip] as usize;
+                self.ip += 1;
+
+                let vec = self.pop_vector()?;
+                let start = self.stack_depth(width)?;
+                let vector = &mut self.vectors[vec];
+                vector.cells.extend(self.data_stack.drain(start..));
+                vector.len += 1;
+            }
+            VecPop => {
+                let width = self.program.functions[self.current_function].code[self.ip] as usize;
+                self.ip += 1;
+
+                let vec = self.pop_vector()?;
+                let vector = &mut self.vectors[vec];
+                if vector.len == 0 {
+                    return Err(Diagnostic::new(
+                        Code::OutOfBounds,
+                        "Cannot pop from an empty vector!",
+                    ));
+                }
+                vector.len -= 1;
+                self.data_stack
+                    .extend(vector.cells.drain(vector.len * width..));
+            }
+            VecGet => {
+                let width = self.program.functions[self.current_function].code[self.ip] as usize;
+                self.ip += 1;
+
+                let index = self.data_stack.pop().ok_or_else(stack_underflow)?;
+                let vec = self.pop_vector()?;
+                let vector = &self.vectors[vec];
+                let index = check_index(index, vector.len, "a vector")?;
+                self.data_stack
+                    .extend_from_slice(&vector.cells[index * width..(index + 1) * width]);
+            }
+            VecSet => {
+                let width = self.program.functions[self.current_function].code[self.ip] as usize;
+                self.ip += 1;
+
+                let index = self.data_stack.pop().ok_or_else(stack_underflow)?;
+                let vec = self.pop_vector()?;
+                let index = check_index(index, self.vectors[vec].len, "a vector")?;
+                let start = self.stack_depth(width)?;
+                let element = self.data_stack.split_off(start);
+                self.vectors[vec].cells[index * width..(index + 1) * width]
+                    .copy_from_slice(&element);
+            }
+            VecLen => {
+                let vec = self.pop_vector()?;
+                self.data_stack.push(self.vectors[vec].len as i64);
+            }
+            Offset => {
+                let width = self.program.functions[self.current_function].code[self.ip] as usize;
+                self.ip += 1;
+
+                let n = self.data_stack.pop().ok_or_else(stack_underflow)?;
+                let ptr = self.data_stack.pop().ok_or_else(stack_underflow)?;
+                let (id, cell) = pointer_parts(ptr);
+                let cell = n
+                    .checked_mul(width as i64)
+                    .and_then(|cells| cells.checked_add(cell as i64))
+                    .and_then(|cell| i32::try_from(cell).ok())
+                    .ok_or_else(|| {
+                        Diagnostic::new(
+                            Code::OutOfBounds,
+                            format!("Cannot offset a pointer by {} elements!", n),
+                        )
+                    })?;
+                self.data_stack.push(make_pointer(id, cell));
             }
             Jump => {
                 let jump = self.program.functions[self.current_function].code[self.ip] as i64;
@@ -611,11 +1532,7 @@ impl Evaluator {
                 let jump = self.program.functions[self.current_function].code[self.ip] as i64;
                 self.ip += 1;
 
-                let should_jump = self
-                    .data_stack
-                    .pop()
-                    .ok_or("Stack underflow!".to_string())?
-                    != 0;
+                let should_jump = self.data_stack.pop().ok_or_else(stack_underflow)? != 0;
                 if should_jump {
                     self.ip = ((self.ip as i64) + jump) as usize;
                 }
@@ -624,11 +1541,7 @@ impl Evaluator {
                 let jump = self.program.functions[self.current_function].code[self.ip] as i64;
                 self.ip += 1;
 
-                let should_jump = self
-                    .data_stack
-                    .pop()
-                    .ok_or("Stack underflow!".to_string())?
-                    == 0;
+                let should_jump = self.data_stack.pop().ok_or_else(stack_underflow)? == 0;
                 if should_jump {
                     self.ip = ((self.ip as i64) + jump) as usize;
                 }
@@ -651,27 +1564,94 @@ impl Evaluator {
                 let id = self.program.functions[self.current_function].code[self.ip] as usize;
                 self.ip += 1;
 
-                let value = self.bind_stack[id];
+                let value = self.bind_stack[self.bind_base + id];
                 self.data_stack.push(value);
             }
             PushVar => {
                 let index = self.program.functions[self.current_function].code[self.ip] as usize;
                 self.ip += 1;
 
-                let value = (&self.variables[index]) as *const i64;
-                self.data_stack.push(value as i64);
+                self.data_stack
+                    .push(make_pointer(VARIABLES_ID, index as i32));
             }
             MakeVar => {
                 let index = self.program.functions[self.current_function].code[self.ip] as usize;
                 self.ip += 1;
 
-                let value = self
-                    .data_stack
-                    .pop()
-                    .ok_or("Stack underflow!".to_string())?;
+                let value = self.data_stack.pop().ok_or_else(stack_underflow)?;
                 self.variables[index] = value;
             }
-            _ => panic!("Invalid instruction: {:?}", instruction),
+            ForStart => {
+                let (index, jump) = self.for_operands();
+                if self.bind_stack[index] >= self.bind_stack[index + 1] {
+                    self.ip = ((self.ip as i64) + jump) as usize;
+                }
+            }
+            ForNext => {
+                let (index, jump) = self.for_operands();
+                self.bind_stack[index] += 1;
+                if self.bind_stack[index] < self.bind_stack[index + 1] {
+                    self.ip = ((self.ip as i64) + jump) as usize;
+                }
+            }
+            JumpTable => {
+                let index = self.program.functions[self.current_function].code[self.ip] as usize;
+                self.ip += 1;
+
+                let value = self.data_stack.pop().ok_or_else(stack_underflow)?;
+                let table = &self.program.jump_tables[index];
+                self.ip = value
+                    .checked_sub(table.min)
+                    .and_then(|index| usize::try_from(index).ok())
+                    .and_then(|index| table.targets.get(index))
+                    .copied()
+                    .unwrap_or(table.default);
+            }
+            CheckEnum => {
+                let variant_count =
+                    self.program.functions[self.current_function].code[self.ip] as i64;
+                self.ip += 1;
+
+                let value = *self.data_stack.last().ok_or_else(stack_underflow)?;
+                if !(0..variant_count).contains(&value) {
+                    return Err(Diagnostic::new(
+                        Code::InvalidEnum,
+                        format!(
+                            "`{}` is not a variant of an enum with {} variants!",
+                            value, variant_count
+                        ),
+                    ));
+                }
+            }
+            Field => {
+                let (width, offset, field_width) = self.field_operands();
+
+                let start = self
+                    .data_stack
+                    .len()
+                    .checked_sub(width)
+                    .ok_or_else(stack_underflow)?
+                    + offset;
+                self.data_stack
+                    .extend_from_within(start..start + field_width);
+            }
+            FieldUpdate => {
+                let (width, offset, field_width) = self.field_operands();
+
+                let value_idx = self
+                    .data_stack
+                    .len()
+                    .checked_sub(field_width)
+                    .ok_or_else(stack_underflow)?;
+                let value = self.data_stack.split_off(value_idx);
+                let start = self
+                    .data_stack
+                    .len()
+                    .checked_sub(width)
+                    .ok_or_else(stack_underflow)?
+                    + offset;
+                self.data_stack[start..start + field_width].copy_from_slice(&value);
+            }
         }
 
         Ok(false)
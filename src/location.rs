@@ -0,0 +1,99 @@
+use crate::diagnostic::{Code, Diagnostic};
+use std::fmt;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+#[derive(Debug)]
+pub struct SourceFile {
+    pub path: PathBuf,
+    pub text: String,
+}
+
+impl SourceFile {
+    pub fn new(path: PathBuf, text: String) -> Self {
+        Self { path, text }
+    }
+}
+
+#[derive(Clone)]
+pub struct Location {
+    pub file: Rc<SourceFile>,
+    pub line: usize,
+    pub column: usize,
+    pub length: usize,
+}
+
+impl Location {
+    pub fn new(file: Rc<SourceFile>, line: usize, column: usize, length: usize) -> Self {
+        Self {
+            file,
+            line,
+            column,
+            length,
+        }
+    }
+
+    pub fn error(&self, code: Code, message: impl fmt::Display) -> Diagnostic {
+        Diagnostic::new(code, message).at(self.clone())
+    }
+
+    // The source text this location spans, e.g. the keyword of a token
+    pub fn text(&self) -> String {
+        self.source_line()
+            .chars()
+            .skip(self.column - 1)
+            .take(self.length)
+            .collect()
+    }
+
+    pub fn source_line(&self) -> &str {
+        self.file.text.lines().nth(self.line - 1).unwrap_or("")
+    }
+
+    // Renders the line this location is on with a caret underline beneath
+    // the span, e.g.
+    //    |
+    //  3 |     1 "a" +
+    //    |           ^
+    //
+    pub fn underline(&self) -> String {
+        let line = self.source_line();
+        let line_number = self.line.to_string();
+        let gutter = " ".repeat(line_number.len());
+
+        // keep tabs in the padding so the carets line up with the source
+        let padding = line
+            .chars()
+            .take(self.column - 1)
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect::<String>();
+
+        format!(
+            "{} |\n{} | {}\n{} | {}{}",
+            gutter,
+            line_number,
+            line,
+            gutter,
+            padding,
+            "^".repeat(self.length.max(1))
+        )
+    }
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}",
+            self.file.path.display(),
+            self.line,
+            self.column
+        )
+    }
+}
+
+impl fmt::Debug for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self)
+    }
+}
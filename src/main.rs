@@ -1,27 +1,127 @@
 mod compiler;
+mod diagnostic;
+mod emit;
 mod evaluator;
+mod location;
 mod parser;
 mod string;
 mod typer;
 
+use diagnostic::Diagnostic;
+use emit::{Emit, Stage};
+use std::path::{Path, PathBuf};
+
 fn main() {
-    let path = if let Some(path) = std::env::args().skip(1).next() {
+    let mut path = None;
+    let mut search_paths = Vec::new();
+    let mut emits = Vec::new();
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "-I" {
+            if let Some(search_path) = args.next() {
+                search_paths.push(PathBuf::from(search_path));
+            } else {
+                eprintln!("Error: Expected a directory after `-I`!");
+                return;
+            }
+        } else if let Some(search_path) = arg.strip_prefix("-I") {
+            search_paths.push(PathBuf::from(search_path));
+        } else if let Some(value) = arg.strip_prefix("--emit=") {
+            match Emit::parse(value) {
+                Ok(emit) => emits.push(emit),
+                Err(err) => {
+                    eprintln!("Error: {}", err);
+                    return;
+                }
+            }
+        } else if path.is_none() {
+            path = Some(PathBuf::from(arg));
+        } else {
+            eprintln!("Error: Unexpected argument `{}`!", arg);
+            return;
+        }
+    }
+
+    let path = if let Some(path) = path {
         path
     } else {
         eprintln!("Error: Filepath to reko source file not provided!");
         return;
     };
 
-    if let Err(err) = interpret(path) {
-        eprintln!("Error: {}", err);
+    match interpret(&path, &search_paths, &emits) {
+        Ok(()) => {}
+        Err(Failure::Diagnostics(diagnostics)) => {
+            for diagnostic in &diagnostics {
+                eprintln!("{}\n", diagnostic);
+            }
+
+            let errors = diagnostics.iter().filter(|d| d.is_error()).count();
+            if errors > 1 {
+                eprintln!("{} errors reported", errors);
+            }
+        }
+        Err(Failure::Emit(err)) => eprintln!("Error: {}", err),
+    }
+}
+
+enum Failure {
+    Diagnostics(Vec<Diagnostic>),
+    Emit(String),
+}
+
+impl From<Vec<Diagnostic>> for Failure {
+    fn from(diagnostics: Vec<Diagnostic>) -> Self {
+        Failure::Diagnostics(diagnostics)
+    }
+}
+
+impl From<Diagnostic> for Failure {
+    fn from(diagnostic: Diagnostic) -> Self {
+        Failure::Diagnostics(vec![diagnostic])
     }
 }
 
-fn interpret(path: String) -> Result<(), String> {
-    let source = std::fs::read_to_string(path).or_else(|err| Err(format!("{}", err)))?;
-    let code = parser::parse(source.chars().peekable())?;
-    let typechecked = typer::typecheck(code)?;
+// each phase reports every error it finds but later phases only run when
+// the earlier ones succeeded. Emitting any stage stops before the program
+// is run so its output isn't mixed with the program's.
+fn interpret(path: &Path, search_paths: &[PathBuf], emits: &[Emit]) -> Result<(), Failure> {
+    if emit::wants(emits, Stage::Tokens) {
+        let tokens = parser::tokenize(path)?;
+        emit::emit(emits, Stage::Tokens, &emit::format_tokens(&tokens)).map_err(Failure::Emit)?;
+    }
+
+    // constants are evaluated while parsing, their output mustn't end up in
+    // an emitted stage
+    let constant_output = if emit::writes_to_stdout(emits) {
+        evaluator::Output::Stderr
+    } else {
+        evaluator::Output::Stdout
+    };
+    let code = parser::parse(path, search_paths, constant_output)?;
+    if emit::wants(emits, Stage::Ir) {
+        emit::emit(emits, Stage::Ir, &emit::format_ir(&code)).map_err(Failure::Emit)?;
+    }
+
+    let mut warnings = Vec::new();
+    let typechecked = typer::typecheck(code, &mut warnings)?;
+    for warning in &warnings {
+        eprintln!("{}\n", warning);
+    }
+    if emit::wants(emits, Stage::TypedIr) {
+        emit::emit(emits, Stage::TypedIr, &emit::format_typed_ir(&typechecked))
+            .map_err(Failure::Emit)?;
+    }
+
     let program = compiler::compile(typechecked)?;
-    evaluator::evaluate(program)?;
+    if emit::wants(emits, Stage::Bytecode) {
+        emit::emit(emits, Stage::Bytecode, &emit::format_bytecode(&program))
+            .map_err(Failure::Emit)?;
+    }
+
+    if emits.is_empty() {
+        evaluator::evaluate(program)?;
+    }
     Ok(())
 }
@@ -1,240 +1,570 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::iter::Peekable;
-use std::str::Chars;
-
-// use crate::evaluator;
-
-// @TODO:
-// Implement out-of-order compilation.
-// (For now we're simplifying the problem to make early progress and to give
-// us context when we do)
-//
-pub fn parse<'a>(source: Peekable<Chars<'a>>) -> Result<IRChunks, String> {
-    let mut tokenizer = Tokenizer::new(source);
-    let chunks = chunkify(&mut tokenizer)?;
-    println!("{:#?}", chunks);
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::compiler;
+use crate::diagnostic::{self, Code, Diagnostic, Error};
+use crate::evaluator;
+use crate::location::{Location, SourceFile};
+use crate::typer;
+
+pub fn parse(
+    path: &Path,
+    search_paths: &[PathBuf],
+    constant_output: evaluator::Output,
+) -> Result<IRChunks, Vec<Diagnostic>> {
+    let mut parser = Parser::new(search_paths.to_vec(), constant_output);
+    let chunks = match parser.load_file(path.to_path_buf(), None) {
+        Ok(chunks) => chunks,
+        Err(error) => {
+            parser.report(error);
+            return Err(parser.diagnostics);
+        }
+    };
 
-    let mut parser = Parser::new();
-    let mut ir = Vec::new();
+    // Every top-level declaration is bound before any chunk is parsed so
+    // that declarations can be used anywhere in the program regardless of
+    // the order they're declared in.
+    let (chunks, mut ir) = parser.declare_chunks(chunks);
 
+    // a chunk with an error is skipped and parsing carries on with the next
+    // one so that every error in the program gets reported
     for chunk in chunks {
-        let chunk_ir = parser.parse_chunk(chunk)?;
-        if !chunk_ir.is_empty() {
-            ir.push(chunk_ir);
+        match parser.parse_chunk(chunk) {
+            Ok(chunk_ir) => {
+                if !chunk_ir.is_empty() {
+                    ir.push(chunk_ir);
+                }
+            }
+            Err(error) => {
+                parser.report(error);
+                parser.scopes.clear();
+                parser.next_bind_id = 0;
+            }
         }
     }
 
-    println!("{:#?}", parser);
+    ir.append(&mut parser.quotations);
 
-    println!("{:#?}", ir);
+    if parser.diagnostics.is_empty() {
+        Ok(ir)
+    } else {
+        Err(parser.diagnostics)
+    }
+}
 
-    Ok(ir)
+// Tokenizes a single file without following its includes
+pub fn tokenize(path: &Path) -> Result<Vec<(Location, String)>, Vec<Diagnostic>> {
+    let source = std::fs::read_to_string(path).map_err(|err| {
+        vec![Diagnostic::new(
+            Code::IncludeFailed,
+            format!("Failed to read `{}`: {}", path.display(), err),
+        )]
+    })?;
+
+    let mut tokenizer = Tokenizer::new(Rc::new(SourceFile::new(path.to_path_buf(), source)));
+    let mut tokens = Vec::new();
+    while let Some(token) = tokenizer.next() {
+        tokens.push((token.location, format!("{:?}", token.kind)));
+    }
+
+    if tokenizer.diagnostics.is_empty() {
+        Ok(tokens)
+    } else {
+        Err(tokenizer.diagnostics)
+    }
 }
 
 #[derive(Debug)]
-struct Tokenizer<'a> {
-    source: Peekable<Chars<'a>>,
+struct Tokenizer {
+    file: Rc<SourceFile>,
+    offset: usize,
+    line: usize,
+    column: usize,
+    diagnostics: Vec<Diagnostic>,
 }
 
-impl<'a> Tokenizer<'a> {
-    fn new(source: Peekable<Chars<'a>>) -> Self {
-        Self { source }
+impl Tokenizer {
+    fn new(file: Rc<SourceFile>) -> Self {
+        Self {
+            file,
+            offset: 0,
+            line: 1,
+            column: 1,
+            diagnostics: Vec::new(),
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.file.text[self.offset..].chars().next()
+    }
+
+    fn next_char(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.offset += c.len_utf8();
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        Some(c)
+    }
+
+    fn next_char_if(&mut self, predicate: impl FnOnce(char) -> bool) -> Option<char> {
+        match self.peek() {
+            Some(c) if predicate(c) => self.next_char(),
+            _ => None,
+        }
+    }
+
+    fn location(&self) -> Location {
+        Location::new(self.file.clone(), self.line, self.column, 1)
     }
 
     fn next(&mut self) -> Option<Token> {
         self.skip_whitespace();
 
-        if let Some(&c) = self.source.peek() {
-            if c == '"' {
-                self.tokenize_string()
-            } else if c == ';' {
-                self.source.next();
-                Some(Token {
-                    kind: TokenKind::End,
-                })
-            } else if c.is_ascii_digit() {
-                self.tokenize_number()
-            } else {
-                self.tokenize_identifier_or_keyword()
+        let mut location = self.location();
+
+        let c = self.peek()?;
+        let kind = if c == '"' {
+            Ok(self.tokenize_string())
+        } else if c == '\'' {
+            self.tokenize_char()
+        } else if is_delimiter(c) {
+            self.next_char();
+            Ok(match c {
+                '[' => TokenKind::OpenBracket,
+                ']' => TokenKind::CloseBracket,
+                '(' => TokenKind::OpenParen,
+                ')' => TokenKind::CloseParen,
+                _ => TokenKind::End,
+            })
+        } else {
+            // keywords like `2dup` can look like the start of a number
+            let word = self.read_word();
+            match tokenize_identifier_or_keyword(word) {
+                TokenKind::Ident(word) if starts_number(&word) => tokenize_number(&word),
+                kind => Ok(kind),
             }
+        };
+
+        location.length = self.length_since(&location);
+
+        // malformed literals are still tokens so that chunkify keeps going
+        let kind = kind.unwrap_or_else(|(code, message)| {
+            self.diagnostics.push(location.error(code, message));
+            TokenKind::Int(0)
+        });
+
+        Some(Token { kind, location })
+    }
+
+    // tokens spanning multiple lines are only underlined on their first line
+    fn length_since(&self, location: &Location) -> usize {
+        if self.line == location.line {
+            self.column - location.column
         } else {
-            None
+            location.source_line().chars().count() + 1 - location.column
+        }
+    }
+
+    fn read_word(&mut self) -> String {
+        let mut word = String::new();
+        while let Some(c) = self.next_char_if(|c| !c.is_whitespace() && !is_delimiter(c)) {
+            word.push(c);
         }
+        word
     }
 
     fn skip_whitespace(&mut self) {
-        while let Some(&c) = self.source.peek() {
+        while let Some(c) = self.peek() {
             match c {
                 '#' => self.skip_comment(),
                 _ if !c.is_whitespace() => break,
                 _ => {}
             }
 
-            self.source.next();
+            self.next_char();
         }
     }
 
     fn skip_comment(&mut self) {
-        while let Some(_) = self.source.next_if(|&c| c != '\n') {}
+        while self.next_char_if(|c| c != '\n').is_some() {}
     }
 
-    fn tokenize_string(&mut self) -> Option<Token> {
+    fn tokenize_string(&mut self) -> TokenKind {
+        let start = self.location();
         assert_eq!(
             '"',
-            self.source
-                .next()
+            self.next_char()
                 .expect("Tried to tokenize string but encountered EOF!")
         );
 
-        // @TODO:
-        // Handled escape sequences.
-        //
-
         let mut string = String::new();
-        while let Some(c) = self.source.next_if(|&c| c != '"') {
-            string.push(c);
+        loop {
+            let mut escape_location = self.location();
+            match self.next_char() {
+                Some('"') => break,
+                Some('\\') => match self.tokenize_escape() {
+                    Ok(c) => string.push(c),
+                    Err(message) => {
+                        escape_location.length = self.length_since(&escape_location);
+                        self.diagnostics
+                            .push(escape_location.error(Code::InvalidEscape, message));
+                    }
+                },
+                Some(c) => string.push(c),
+                None => {
+                    self.diagnostics.push(
+                        start.error(Code::UnterminatedString, "Unterminated string literal!"),
+                    );
+                    break;
+                }
+            }
         }
 
-        self.source.next(); // skip terminating `"`
-
-        Some(Token {
-            kind: TokenKind::Str(string),
-        })
+        TokenKind::Str(string)
     }
 
-    fn tokenize_number(&mut self) -> Option<Token> {
-        let mut string = String::new();
-        while let Some(c) = self.source.next_if(|&c| c.is_ascii_digit()) {
-            string.push(c);
+    // A character literal like 'a' or '\n' is the integer value of its
+    // character
+    fn tokenize_char(&mut self) -> Result<TokenKind, (Code, String)> {
+        assert_eq!(
+            '\'',
+            self.next_char()
+                .expect("Tried to tokenize character but encountered EOF!")
+        );
+
+        let c = match self.next_char() {
+            Some('\\') => self
+                .tokenize_escape()
+                .map_err(|message| (Code::InvalidEscape, message)),
+            Some('\'') => {
+                return Err((Code::MalformedChar, "Empty character literal!".to_string()))
+            }
+            Some(c) if c != '\n' => Ok(c),
+            _ => {
+                return Err((
+                    Code::MalformedChar,
+                    "Unterminated character literal!".to_string(),
+                ))
+            }
+        };
+
+        if self.next_char_if(|c| c == '\'').is_none() {
+            // skip the rest of what was probably meant to be a string
+            self.read_word();
+            return Err((
+                Code::MalformedChar,
+                "Character literals must contain exactly one character!".to_string(),
+            ));
         }
 
-        Some(Token {
-            kind: TokenKind::Int(string.parse().expect(
-                "This shouldn't fail because of the while loop checking `is_ascii_digit()`",
-            )),
-        })
+        Ok(TokenKind::Int(c? as i64))
     }
 
-    fn tokenize_identifier_or_keyword(&mut self) -> Option<Token> {
-        let mut string = String::new();
-        while let Some(c) = self.source.next_if(|&c| !c.is_whitespace() && c != ';') {
-            string.push(c);
+    // Called after the `\` of an escape sequence
+    fn tokenize_escape(&mut self) -> Result<char, String> {
+        match self.next_char() {
+            Some('n') => Ok('\n'),
+            Some('t') => Ok('\t'),
+            Some('\\') => Ok('\\'),
+            Some('"') => Ok('"'),
+            Some('\'') => Ok('\''),
+            Some('0') => Ok('\0'),
+            Some('x') => {
+                let mut digits = String::new();
+                while digits.len() < 2 {
+                    match self.next_char_if(|c| c.is_ascii_hexdigit()) {
+                        Some(c) => digits.push(c),
+                        None => break,
+                    }
+                }
+
+                match u8::from_str_radix(&digits, 16) {
+                    Ok(value) if digits.len() == 2 && value <= 0x7F => Ok(value as char),
+                    _ => Err(format!(
+                        "Invalid escape sequence `\\x{}`! `\\x` must be followed by two hex digits up to 7F",
+                        digits
+                    )),
+                }
+            }
+            Some('u') => {
+                if self.next_char_if(|c| c == '{').is_none() {
+                    return Err(
+                        "Invalid escape sequence `\\u`! Expected `{` after `\\u`".to_string()
+                    );
+                }
+
+                let mut digits = String::new();
+                while let Some(c) = self.next_char_if(|c| c.is_ascii_hexdigit()) {
+                    digits.push(c);
+                }
+
+                if self.next_char_if(|c| c == '}').is_none() {
+                    return Err(format!(
+                        "Invalid escape sequence `\\u{{{}`! Expected `}}` after the hex digits",
+                        digits
+                    ));
+                }
+
+                if digits.is_empty() || digits.len() > 6 {
+                    return Err(format!(
+                        "Invalid escape sequence `\\u{{{}}}`! `\\u{{...}}` takes 1 to 6 hex digits",
+                        digits
+                    ));
+                }
+
+                u32::from_str_radix(&digits, 16)
+                    .ok()
+                    .and_then(char::from_u32)
+                    .ok_or_else(|| {
+                        format!(
+                            "Invalid escape sequence `\\u{{{}}}`! It isn't a unicode scalar value",
+                            digits
+                        )
+                    })
+            }
+            Some(c) => Err(format!("Invalid escape sequence `\\{}`!", c)),
+            None => Err("Invalid escape sequence at the end of the file!".to_string()),
         }
+    }
+}
 
-        Some(match string.as_str() {
-            "true" => Token {
-                kind: TokenKind::True,
-            },
-            "false" => Token {
-                kind: TokenKind::False,
-            },
-            "end" => Token {
-                kind: TokenKind::End,
-            },
-            "if" => Token {
-                kind: TokenKind::If,
-            },
-            "elif" => Token {
-                kind: TokenKind::Elif,
-            },
-            "else" => Token {
-                kind: TokenKind::Else,
-            },
-            "while" => Token {
-                kind: TokenKind::While,
-            },
-            "let" => Token {
-                kind: TokenKind::Let,
-            },
-            "then" => Token {
-                kind: TokenKind::Then,
-            },
-            "do" => Token {
-                kind: TokenKind::Do,
-            },
-            "in" => Token {
-                kind: TokenKind::In,
-            },
-            "def" => Token {
-                kind: TokenKind::Def,
-            },
-            "var" => Token {
-                kind: TokenKind::Var,
-            },
-            "const" => Token {
-                kind: TokenKind::Const,
-            },
-            "struct" => Token {
-                kind: TokenKind::Struct,
-            },
-            "enum" => Token {
-                kind: TokenKind::Enum,
-            },
-            "include" => Token {
-                kind: TokenKind::Include,
-            },
-            "--" => Token {
-                kind: TokenKind::DashDash,
-            },
-            "dup" => Token {
-                kind: TokenKind::Dup,
-            },
-            "over" => Token {
-                kind: TokenKind::Over,
-            },
-            "drop" => Token {
-                kind: TokenKind::Drop,
-            },
-            "swap" => Token {
-                kind: TokenKind::Swap,
-            },
-            "print" => Token {
-                kind: TokenKind::Print,
-            },
-            "and" => Token {
-                kind: TokenKind::And,
-            },
-            "or" => Token {
-                kind: TokenKind::Or,
-            },
-            "not" => Token {
-                kind: TokenKind::Not,
-            },
-            "+" => Token {
-                kind: TokenKind::Plus,
-            },
-            "-" => Token {
-                kind: TokenKind::Dash,
-            },
-            "*" => Token {
-                kind: TokenKind::Star,
-            },
-            "/" => Token {
-                kind: TokenKind::Slash,
-            },
-            "=" => Token {
-                kind: TokenKind::Eq,
-            },
-            "!=" => Token {
-                kind: TokenKind::Neq,
-            },
-            "<" => Token {
-                kind: TokenKind::Lt,
-            },
-            ">" => Token {
-                kind: TokenKind::Gt,
-            },
-            "<-" => Token {
-                kind: TokenKind::LeftArrow,
-            },
-            "@" => Token {
-                kind: TokenKind::At,
-            },
-            _ => Token {
-                kind: TokenKind::Ident(string),
-            },
+fn starts_number(word: &str) -> bool {
+    let digits = word.strip_prefix('-').unwrap_or(word);
+    digits.starts_with(|c: char| c.is_ascii_digit())
+}
+
+// Integers can be negative, use `_` as a separator and be written in hex,
+// octal or binary with a `0x`, `0o` or `0b` prefix
+fn tokenize_number(word: &str) -> Result<TokenKind, (Code, String)> {
+    let (negative, unsigned) = match word.strip_prefix('-') {
+        Some(unsigned) => (true, unsigned),
+        None => (false, word),
+    };
+
+    let (radix, digits) = match unsigned.get(..2) {
+        Some("0x" | "0X") => (16, &unsigned[2..]),
+        Some("0o" | "0O") => (8, &unsigned[2..]),
+        Some("0b" | "0B") => (2, &unsigned[2..]),
+        _ => (10, unsigned),
+    };
+
+    let digits = digits.replace('_', "");
+    if radix == 10 && digits.contains(['.', 'e', 'E']) {
+        return tokenize_float(word);
+    }
+    if digits.is_empty() || !digits.chars().all(|c| c.is_digit(radix)) {
+        return Err((
+            Code::MalformedNumber,
+            format!("Malformed number literal `{}`!", word),
+        ));
+    }
+
+    // the magnitude is parsed unsigned so that the smallest `int` can be
+    // written
+    let value = u64::from_str_radix(&digits, radix)
+        .ok()
+        .and_then(|magnitude| {
+            if negative {
+                0i64.checked_sub_unsigned(magnitude)
+            } else {
+                i64::try_from(magnitude).ok()
+            }
+        })
+        .ok_or_else(|| {
+            (
+                Code::IntegerOverflow,
+                format!(
+                    "Integer literal `{}` doesn't fit in an `int`! It must be between {} and {}",
+                    word,
+                    i64::MIN,
+                    i64::MAX
+                ),
+            )
+        })?;
+
+    Ok(TokenKind::Int(value))
+}
+
+// Floats are written in decimal with a fraction and/or an exponent, e.g.
+// `1.5`, `-0.25` or `6.02e23`
+fn tokenize_float(word: &str) -> Result<TokenKind, (Code, String)> {
+    word.replace('_', "")
+        .parse::<f64>()
+        .map(TokenKind::Float)
+        .map_err(|_| {
+            (
+                Code::MalformedNumber,
+                format!("Malformed float literal `{}`!", word),
+            )
         })
+}
+
+// Characters that are tokens of their own even without surrounding whitespace
+fn is_delimiter(c: char) -> bool {
+    matches!(c, ';' | '[' | ']' | '(' | ')')
+}
+
+fn tokenize_identifier_or_keyword(string: String) -> TokenKind {
+    match string.as_str() {
+        "true" => TokenKind::True,
+        "false" => TokenKind::False,
+        "end" => TokenKind::End,
+        "if" => TokenKind::If,
+        "elif" => TokenKind::Elif,
+        "else" => TokenKind::Else,
+        "while" => TokenKind::While,
+        "for" => TokenKind::For,
+        "loop" => TokenKind::Loop,
+        "match" => TokenKind::Match,
+        "case" => TokenKind::Case,
+        "let" => TokenKind::Let,
+        "then" => TokenKind::Then,
+        "do" => TokenKind::Do,
+        "in" => TokenKind::In,
+        "break" => TokenKind::Break,
+        "continue" => TokenKind::Continue,
+        "return" => TokenKind::Return,
+        "def" => TokenKind::Def,
+        "var" => TokenKind::Var,
+        "const" => TokenKind::Const,
+        "struct" => TokenKind::Struct,
+        "enum" => TokenKind::Enum,
+        "include" => TokenKind::Include,
+        "--" => TokenKind::DashDash,
+        "dup" => TokenKind::Dup,
+        "over" => TokenKind::Over,
+        "drop" => TokenKind::Drop,
+        "swap" => TokenKind::Swap,
+        "rot" => TokenKind::Rot,
+        "-rot" => TokenKind::Unrot,
+        "nip" => TokenKind::Nip,
+        "tuck" => TokenKind::Tuck,
+        "2dup" => TokenKind::TwoDup,
+        "2drop" => TokenKind::TwoDrop,
+        "2swap" => TokenKind::TwoSwap,
+        "2over" => TokenKind::TwoOver,
+        "pick" => TokenKind::Pick,
+        "roll" => TokenKind::Roll,
+        "print" => TokenKind::Print,
+        "and" => TokenKind::And,
+        "or" => TokenKind::Or,
+        "not" => TokenKind::Not,
+        "+" => TokenKind::Plus,
+        "-" => TokenKind::Dash,
+        "*" => TokenKind::Star,
+        "/" => TokenKind::Slash,
+        "=" => TokenKind::Eq,
+        "!=" => TokenKind::Neq,
+        "<" => TokenKind::Lt,
+        ">" => TokenKind::Gt,
+        "%" => TokenKind::Percent,
+        "<=" => TokenKind::Le,
+        ">=" => TokenKind::Ge,
+        "neg" => TokenKind::Neg,
+        "band" => TokenKind::BitAnd,
+        "bor" => TokenKind::BitOr,
+        "bxor" => TokenKind::BitXor,
+        "bnot" => TokenKind::BitNot,
+        "shl" => TokenKind::Shl,
+        "shr" => TokenKind::Shr,
+        "divmod" => TokenKind::DivMod,
+        "int>float" => TokenKind::IntToFloat,
+        "float>int" => TokenKind::FloatToInt,
+        "len" => TokenKind::Len,
+        "concat" => TokenKind::Concat,
+        "slice" => TokenKind::Slice,
+        "find" => TokenKind::Find,
+        "starts-with" => TokenKind::StartsWith,
+        "ends-with" => TokenKind::EndsWith,
+        "compare" => TokenKind::Compare,
+        "split" => TokenKind::Split,
+        "trim" => TokenKind::Trim,
+        "str>int" => TokenKind::StrToInt,
+        "int>str" => TokenKind::IntToStr,
+        "alloc" => TokenKind::Alloc,
+        "free" => TokenKind::Free,
+        "ptr+" => TokenKind::Offset,
+        "null" => TokenKind::Null,
+        "zero" => TokenKind::Zero,
+        "get" => TokenKind::Get,
+        "set" => TokenKind::Set,
+        "vec.new" => TokenKind::VecNew,
+        "vec.push" => TokenKind::VecPush,
+        "vec.pop" => TokenKind::VecPop,
+        "vec.get" => TokenKind::VecGet,
+        "vec.set" => TokenKind::VecSet,
+        "vec.len" => TokenKind::VecLen,
+        "<-" => TokenKind::LeftArrow,
+        "@" => TokenKind::At,
+        "call" => TokenKind::Call,
+        _ => match string.strip_prefix('&') {
+            Some(name) if !name.is_empty() => TokenKind::Reference(name.to_string()),
+            _ => TokenKind::Ident(string),
+        },
+    }
+}
+
+// Built-in words like `find` are keywords, so using one as a name gets its
+// own error rather than the one for a missing identifier
+fn expected_identifier(token: Option<Token>, location: &Location, message: &str) -> Error {
+    if let Some(token) = token {
+        let word = token.location.text();
+        if is_builtin_word(&tokenize_identifier_or_keyword(word.clone())) {
+            return token
+                .location
+                .error(
+                    Code::ExpectedIdentifier,
+                    format!("`{}` is a built-in word and can't be used as a name!", word),
+                )
+                .into();
+        }
     }
+    location.error(Code::ExpectedIdentifier, message).into()
+}
+
+fn is_builtin_word(kind: &TokenKind) -> bool {
+    use TokenKind::*;
+    !matches!(
+        kind,
+        Ident(_)
+            | Reference(_)
+            | True
+            | False
+            | End
+            | If
+            | Elif
+            | Else
+            | While
+            | For
+            | Loop
+            | Match
+            | Case
+            | Let
+            | Then
+            | Do
+            | In
+            | Break
+            | Continue
+            | Return
+            | Def
+            | Var
+            | Const
+            | Struct
+            | Enum
+            | Include
+            | DashDash
+    )
+}
+
+// Struct fields are declared as `name: type`
+fn field_name(word: &str) -> Option<&str> {
+    word.strip_suffix(':').filter(|name| !name.is_empty())
 }
 
 type Tokens = Peekable<std::vec::IntoIter<Token>>;
@@ -242,6 +572,7 @@ type Tokens = Peekable<std::vec::IntoIter<Token>>;
 #[derive(Debug)]
 struct Token {
     kind: TokenKind,
+    location: Location,
 }
 
 #[derive(Debug)]
@@ -250,7 +581,9 @@ enum TokenKind {
     True,
     False,
     Ident(String),
+    Reference(String),
     Int(i64),
+    Float(f64),
     Str(String),
 
     // Keywords
@@ -259,10 +592,17 @@ enum TokenKind {
     Elif,
     Else,
     While,
+    For,
+    Loop,
+    Match,
+    Case,
     Let,
     Then,
     Do,
     In,
+    Break,
+    Continue,
+    Return,
     Def,
     Var,
     Const,
@@ -276,6 +616,16 @@ enum TokenKind {
     Over,
     Drop,
     Swap,
+    Rot,
+    Unrot,
+    Nip,
+    Tuck,
+    TwoDup,
+    TwoDrop,
+    TwoSwap,
+    TwoOver,
+    Pick,
+    Roll,
     Print,
     And,
     Or,
@@ -288,61 +638,147 @@ enum TokenKind {
     Neq,
     Lt,
     Gt,
+    Percent,
+    Le,
+    Ge,
+    Neg,
+    BitAnd,
+    BitOr,
+    BitXor,
+    BitNot,
+    Shl,
+    Shr,
+    DivMod,
+    IntToFloat,
+    FloatToInt,
+    Len,
+    Concat,
+    Slice,
+    Find,
+    StartsWith,
+    EndsWith,
+    Compare,
+    Split,
+    Trim,
+    StrToInt,
+    IntToStr,
+    Alloc,
+    Free,
+    Offset,
+    Null,
+    Zero,
+    Get,
+    Set,
+    VecNew,
+    VecPush,
+    VecPop,
+    VecGet,
+    VecSet,
+    VecLen,
     LeftArrow,
     At,
+    Call,
+
+    // Delimiters
+    OpenBracket,
+    CloseBracket,
+    OpenParen,
+    CloseParen,
 }
 
 type Chunk = Vec<Token>;
 type Chunks = Vec<Chunk>;
 
-fn chunkify<'a>(t: &mut Tokenizer<'a>) -> Result<Chunks, String> {
+fn starts_declaration(kind: &TokenKind) -> bool {
+    use TokenKind::*;
+    matches!(kind, Def | Var | Const | Struct | Enum | Include)
+}
+
+// Skips to the start of the next top-level declaration so that chunkify can
+// carry on after an error
+fn next_declaration(t: &mut Tokenizer) -> Option<Token> {
+    std::iter::from_fn(|| t.next()).find(|token| starts_declaration(&token.kind))
+}
+
+fn chunkify(t: &mut Tokenizer, diagnostics: &mut Vec<Diagnostic>) -> Chunks {
     let mut chunks = Chunks::new();
 
-    while let Some(token) = t.next() {
+    let mut next = t.next();
+    while let Some(token) = next.take() {
         use TokenKind::*;
-        if !matches!(token.kind, Def | Var | Const | Struct | Enum | Include) {
-            return Err(format!("{:?} cannot be at top level!", token));
+        if !starts_declaration(&token.kind) {
+            diagnostics.push(token.location.error(
+                Code::UnexpectedToken,
+                format!("{:?} cannot be at top level!", token.kind),
+            ));
+            next = next_declaration(t);
+            continue;
         }
 
         let mut chunk = Chunk::new();
 
         if matches!(token.kind, Include) {
-            let include_path = t
-                .next()
-                .ok_or("Expected a file path to include!".to_string())?;
-
-            if !matches!(include_path.kind, Str(_)) {
-                return Err("Expected a file path to include!".to_string());
+            match t.next() {
+                Some(include_path) if matches!(include_path.kind, Str(_)) => {
+                    chunk.push(token);
+                    chunk.push(include_path);
+                }
+                Some(include_path) => {
+                    diagnostics.push(
+                        include_path
+                            .location
+                            .error(Code::UnexpectedToken, "Expected a file path to include!"),
+                    );
+                    next = if starts_declaration(&include_path.kind) {
+                        Some(include_path)
+                    } else {
+                        next_declaration(t)
+                    };
+                    continue;
+                }
+                None => {
+                    diagnostics.push(
+                        token
+                            .location
+                            .error(Code::UnexpectedToken, "Expected a file path to include!"),
+                    );
+                    break;
+                }
             }
-
-            chunk.push(token);
-            chunk.push(include_path);
         } else {
             chunk.push(token);
 
             let mut num_expected_ends = 1;
-            loop {
-                if let Some(token) = t.next() {
-                    match token.kind {
-                        End => num_expected_ends -= 1,
-                        If | While | Def | Var | Const | Let | Struct | Enum => {
-                            num_expected_ends += 1
-                        }
-                        _ => {}
+            while num_expected_ends != 0 {
+                let token = match t.next() {
+                    Some(token) => token,
+                    None => {
+                        diagnostics.push(chunk[0].location.error(
+                            Code::UnexpectedEof,
+                            format!(
+                                "Unexpected EOF! This `{}` is missing its `end`",
+                                chunk[0].location.text()
+                            ),
+                        ));
+                        return chunks;
                     }
-                    chunk.push(token);
-                }
+                };
 
-                if num_expected_ends == 0 {
-                    break;
+                match token.kind {
+                    End => num_expected_ends -= 1,
+                    If | While | For | Loop | Match | Case | Def | Var | Const | Let | Struct
+                    | Enum => num_expected_ends += 1,
+                    _ => {}
                 }
+                chunk.push(token);
             }
         }
 
         chunks.push(chunk);
+        next = t.next();
     }
 
-    Ok(chunks)
+    chunks
 }
 
 #[derive(Debug)]
@@ -350,17 +786,42 @@ struct Parser {
     global: Scope,
     scopes: Vec<Scope>,
     next_bind_id: usize,
+    // the bodies of quotations, lifted out of the chunks they're in
+    quotations: IRChunks,
+
+    search_paths: Vec<PathBuf>,
+    included_files: HashSet<PathBuf>,
+    include_stack: Vec<PathBuf>,
+
+    pending_constants: HashMap<String, Chunk>,
+    resolving_constants: Vec<String>,
+    // where `print` in the body of a constant writes to
+    constant_output: evaluator::Output,
+
+    diagnostics: Vec<Diagnostic>,
 }
 
 impl Parser {
-    fn new() -> Self {
+    fn new(search_paths: Vec<PathBuf>, constant_output: evaluator::Output) -> Self {
         Self {
             global: Scope::new(ScopeKind::Global),
             scopes: Default::default(),
             next_bind_id: 0,
+            quotations: IRChunks::new(),
+            search_paths,
+            included_files: HashSet::new(),
+            include_stack: Vec::new(),
+            pending_constants: HashMap::new(),
+            resolving_constants: Vec::new(),
+            constant_output,
+            diagnostics: Vec::new(),
         }
     }
 
+    fn report(&mut self, error: Error) {
+        diagnostic::report(&mut self.diagnostics, error);
+    }
+
     fn push_scope(&mut self, kind: ScopeKind) {
         self.scopes.push(Scope::new(kind));
     }
@@ -384,25 +845,98 @@ impl Parser {
         }
     }
 
-    fn evaluate_constant(&mut self, tokens: &mut Tokens) -> Result<Constant, String> {
-        // @TODO:
-        // Actually evaluate the constant
-        //
-        // let mut constant_chunk = Chunk::new();
-        // while let Some(t) = tokens.next() {
-        // 	if matches!(t.kind, TokenKind::End) {
-        // 		break;
-        // 	}
-        // 	constant_chunk.push(t);
-        // }
+    // Adds a did-you-mean to `diagnostic` from the bindings visible here
+    fn suggest_binding(
+        &self,
+        diagnostic: Diagnostic,
+        name: &str,
+        accept: impl Fn(&Binding) -> bool,
+    ) -> Diagnostic {
+        let candidates = self
+            .scopes
+            .iter()
+            .chain(std::iter::once(&self.global))
+            .flat_map(|scope| scope.bindings.iter())
+            .filter(|(_, binding)| accept(binding))
+            .map(|(name, _)| name.as_str());
+
+        match diagnostic::did_you_mean(name, candidates) {
+            Some(suggestion) => diagnostic.with_help(format!("Did you mean `{}`?", suggestion)),
+            None => diagnostic,
+        }
+    }
+
+    fn evaluate_constant(
+        &mut self,
+        tokens: &mut Tokens,
+        location: &Location,
+    ) -> Result<Constant, Error> {
+        let mut constant_chunk = Chunk::new();
+        let mut num_expected_ends = 1;
+        for t in tokens.by_ref() {
+            use TokenKind::*;
+            match t.kind {
+                End => num_expected_ends -= 1,
+                If | While | For | Loop | Match | Case | Let => num_expected_ends += 1,
+                Def | Var | Const | Struct | Enum | Include => {
+                    return Err(t
+                        .location
+                        .error(
+                            Code::InvalidConstant,
+                            format!("{:?} is not allowed in the body of a `const`!", t.kind),
+                        )
+                        .into())
+                }
+                _ => {}
+            }
+
+            if num_expected_ends == 0 {
+                break;
+            }
+            constant_chunk.push(t);
+        }
 
-        // let constant_ir = self.parse_chunk(constant_chunk)?;
-        // evaluator::constant_evaluate(constant_ir)
+        if num_expected_ends != 0 {
+            return Err(location
+                .error(Code::UnexpectedEof, "Unexpected EOF while parsing `const`!")
+                .into());
+        }
+
+        // Only constants from enclosing scopes are visible to the body of a
+        // constant so we evaluate it with a fresh set of scopes and binds.
+        let mut visible = Scope::new(ScopeKind::Const);
+        for scope in &self.scopes {
+            for (name, binding) in &scope.bindings {
+                if let Binding::Constant(constant) = binding {
+                    visible
+                        .bindings
+                        .insert(name.clone(), Binding::Constant(constant.clone()));
+                }
+            }
+        }
 
-        Ok(Constant::Int(69420))
+        let enclosing_scopes = std::mem::replace(&mut self.scopes, vec![visible]);
+        let enclosing_bind_id = std::mem::take(&mut self.next_bind_id);
+        let constant_ir = self.parse_chunk(constant_chunk);
+        self.scopes = enclosing_scopes;
+        self.next_bind_id = enclosing_bind_id;
+
+        let (typechecked, ty) = typer::typecheck_constant(constant_ir?, location)?;
+        let program = compiler::compile_constant(typechecked)?;
+        Ok(evaluator::evaluate_constant(
+            program,
+            &ty,
+            self.constant_output,
+        )?)
     }
 
-    fn bind(&mut self, name: String, binding: Binding) -> Result<(), String> {
+    fn in_constant(&self) -> bool {
+        self.scopes
+            .iter()
+            .any(|scope| matches!(scope.kind, ScopeKind::Const))
+    }
+
+    fn bind(&mut self, name: String, binding: Binding, location: &Location) -> Result<(), Error> {
         let scope = if self.scopes.is_empty() {
             &mut self.global
         } else {
@@ -410,7 +944,12 @@ impl Parser {
         };
 
         if scope.bindings.contains_key(&name) {
-            return Err(format!("Redeclared identifier `{}`", name));
+            return Err(location
+                .error(
+                    Code::RedeclaredIdentifier,
+                    format!("Redeclared identifier `{}`", name),
+                )
+                .into());
         }
 
         scope.bindings.insert(name, binding);
@@ -418,97 +957,545 @@ impl Parser {
         Ok(())
     }
 
-    // fn bind_constant(&mut self, name: String, constant: Constant) -> Result<(), String> {
+    // Binds the words reading and updating `field` of the struct `name`
+    fn bind_field(&mut self, name: &str, field: &str, location: &Location) -> Result<(), Error> {
+        self.bind(
+            format!("{}.{}", name, field),
+            Binding::Field(name.to_string(), field.to_string()),
+            location,
+        )?;
+        self.bind(
+            format!("{}.{}<-", name, field),
+            Binding::FieldUpdate(name.to_string(), field.to_string()),
+            location,
+        )
+    }
+
+    // fn bind_constant(&mut self, name: String, constant: Constant) -> Result<(), Error> {
     // 	self.bind(name, Binding::Constant(constant))
     // }
 
-    // fn bind_function(&mut self, name: String) -> Result<(), String> {
+    // fn bind_function(&mut self, name: String) -> Result<(), Error> {
     // 	self.bind(name, Binding::Function)
     // }
 
-    // fn bind_struct(&mut self, name: String) -> Result<(), String> {
+    // fn bind_struct(&mut self, name: String) -> Result<(), Error> {
     // 	self.bind(name, Binding::Struct)
     // }
 
-    // fn bind_enum(&mut self, name: String) -> Result<(), String> {
+    // fn bind_enum(&mut self, name: String) -> Result<(), Error> {
     // 	self.bind(name, Binding::Enum)
     // }
 }
 
 impl Parser {
-    fn parse_chunk(&mut self, chunk: Chunk) -> Result<IRChunk, String> {
+    fn load_file(
+        &mut self,
+        path: PathBuf,
+        included_at: Option<&Location>,
+    ) -> Result<Chunks, Error> {
+        let path = path.canonicalize().unwrap_or(path);
+
+        if self.include_stack.contains(&path) {
+            let message = format!(
+                "Include cycle detected! {} -> {}",
+                self.include_chain(),
+                path.display()
+            );
+            return Err(match included_at {
+                Some(location) => location.error(Code::IncludeFailed, message),
+                None => Diagnostic::new(Code::IncludeFailed, message),
+            }
+            .into());
+        }
+
+        // every file is only included once no matter how many files include it
+        if !self.included_files.insert(path.clone()) {
+            return Ok(Chunks::new());
+        }
+
+        let source = std::fs::read_to_string(&path).map_err(|err| {
+            let message = format!("Failed to read `{}`: {}", path.display(), err);
+            match included_at {
+                Some(location) => location
+                    .error(Code::IncludeFailed, message)
+                    .with_note(format!("Included by {}", self.include_chain())),
+                None => Diagnostic::new(Code::IncludeFailed, message),
+            }
+        })?;
+
+        self.include_stack.push(path.clone());
+
+        // locations are shown relative to the working directory when possible
+        let display_path = std::env::current_dir()
+            .ok()
+            .and_then(|dir| path.strip_prefix(dir).ok())
+            .map(Path::to_path_buf)
+            .unwrap_or_else(|| path.clone());
+
+        let mut tokenizer = Tokenizer::new(Rc::new(SourceFile::new(display_path, source)));
+        let mut chunk_diagnostics = Vec::new();
+        let chunks = chunkify(&mut tokenizer, &mut chunk_diagnostics);
+        self.diagnostics.append(&mut tokenizer.diagnostics);
+        self.diagnostics.append(&mut chunk_diagnostics);
+
+        let mut loaded = Chunks::new();
+        for chunk in chunks {
+            if let Some(Token {
+                kind: TokenKind::Include,
+                ..
+            }) = chunk.first()
+            {
+                let (include_path, location) = match chunk.into_iter().nth(1) {
+                    Some(Token {
+                        kind: TokenKind::Str(include_path),
+                        location,
+                    }) => (include_path, location),
+                    _ => unreachable!("`chunkify` checks that `include` is followed by a path"),
+                };
+
+                // a missing include is reported and the rest of the file is
+                // still loaded
+                match self
+                    .resolve_include(&include_path, &location)
+                    .and_then(|resolved| self.load_file(resolved, Some(&location)))
+                {
+                    Ok(chunks) => loaded.extend(chunks),
+                    Err(error) => self.report(error),
+                }
+            } else {
+                loaded.push(chunk);
+            }
+        }
+
+        self.include_stack.pop();
+
+        Ok(loaded)
+    }
+
+    fn resolve_include(&self, include_path: &str, location: &Location) -> Result<PathBuf, Error> {
+        let include_path = Path::new(include_path);
+        if include_path.is_absolute() {
+            return Ok(include_path.to_path_buf());
+        }
+
+        // files are first looked up relative to the file including them and
+        // then in each of the search paths in order
+        let including_dir = self
+            .include_stack
+            .last()
+            .and_then(|path| path.parent())
+            .map(Path::to_path_buf)
+            .unwrap_or_default();
+
+        let resolved = std::iter::once(&including_dir)
+            .chain(self.search_paths.iter())
+            .map(|dir| dir.join(include_path))
+            .find(|candidate| candidate.is_file())
+            .ok_or_else(|| {
+                location
+                    .error(
+                        Code::IncludeFailed,
+                        format!("Cannot find included file `{}`!", include_path.display()),
+                    )
+                    .with_note(format!("Included by {}", self.include_chain()))
+                    .with_note(format!(
+                        "Searched {}",
+                        std::iter::once(&including_dir)
+                            .chain(self.search_paths.iter())
+                            .map(|dir| format!("`{}`", dir.display()))
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    ))
+            })?;
+
+        Ok(resolved)
+    }
+
+    fn include_chain(&self) -> String {
+        self.include_stack
+            .iter()
+            .map(|path| path.display().to_string())
+            .collect::<Vec<_>>()
+            .join(" -> ")
+    }
+
+    fn declare_chunks(&mut self, chunks: Chunks) -> (Chunks, IRChunks) {
+        let mut remaining = Chunks::new();
+        let mut declared = IRChunks::new();
+        let mut constants = Vec::new();
+
+        for chunk in chunks {
+            let (name, location) = match chunk.get(1) {
+                Some(Token {
+                    kind: TokenKind::Ident(name),
+                    location,
+                }) => (name.clone(), location.clone()),
+                // malformed declarations are reported when the chunk is parsed
+                _ => {
+                    remaining.push(chunk);
+                    continue;
+                }
+            };
+
+            use TokenKind::*;
+            let bound = match chunk[0].kind {
+                Def => self.bind(name, Binding::Function, &location),
+                Var => self.bind(name, Binding::Variable, &location),
+                Struct => {
+                    // field words have to exist before any chunk using them is
+                    // parsed, malformed fields are reported with the struct
+                    for token in &chunk[2..] {
+                        if let Token {
+                            kind: TokenKind::Ident(word),
+                            location,
+                        } = token
+                        {
+                            if let Some(field) = field_name(word) {
+                                if let Err(error) = self.bind_field(&name, field, location) {
+                                    self.report(error);
+                                }
+                            }
+                        }
+                    }
+                    self.bind(name, Binding::Struct, &location)
+                }
+                Enum => {
+                    // enums only declare constants so they're fully handled here
+                    match self.parse_chunk(chunk) {
+                        Ok(chunk_ir) => declared.push(chunk_ir),
+                        Err(error) => self.report(error),
+                    }
+                    continue;
+                }
+                Const => {
+                    match self.bind(name.clone(), Binding::PendingConstant, &location) {
+                        Ok(()) => {
+                            self.pending_constants
+                                .insert(name.clone(), chunk.into_iter().skip(1).collect());
+                            constants.push((name, location));
+                        }
+                        Err(error) => self.report(error),
+                    }
+                    continue;
+                }
+                _ => Ok(()),
+            };
+
+            // a redeclaration is dropped so its body isn't checked twice
+            match bound {
+                Ok(()) => remaining.push(chunk),
+                Err(error) => self.report(error),
+            }
+        }
+
+        // constants are evaluated in declaration order and any constants they
+        // refer to that haven't been evaluated yet are evaluated on demand
+        for (name, location) in constants {
+            if let Err(error) = self.resolve_constant(&name, &location) {
+                self.report(error);
+            }
+        }
+
+        (remaining, declared)
+    }
+
+    fn resolve_constant(&mut self, name: &String, location: &Location) -> Result<(), Error> {
+        if !matches!(
+            self.global.bindings.get(name),
+            Some(Binding::PendingConstant)
+        ) {
+            return Ok(());
+        }
+
+        let mut tokens = match self.pending_constants.remove(name) {
+            Some(tokens) => tokens.into_iter().peekable(),
+            None => {
+                return Err(location
+                    .error(
+                        Code::InvalidConstant,
+                        format!(
+                            "The value of `const {}` depends on itself! {} -> {}",
+                            name,
+                            self.resolving_constants.join(" -> "),
+                            name
+                        ),
+                    )
+                    .into())
+            }
+        };
+        let name_location = tokens
+            .next()
+            .expect("Pending constants start with their name")
+            .location;
+
+        // a constant can be resolved while parsing a block so we evaluate
+        // it as if it were declared at the top level
+        let enclosing_scopes = std::mem::take(&mut self.scopes);
+        self.resolving_constants.push(name.clone());
+        let value = self.evaluate_constant(&mut tokens, &name_location);
+        self.resolving_constants.pop();
+        self.scopes = enclosing_scopes;
+
+        // a constant that fails to evaluate poisons everything using it so
+        // the error is only reported once
+        match value {
+            Ok(value) => {
+                self.global
+                    .bindings
+                    .insert(name.clone(), Binding::Constant(value));
+                Ok(())
+            }
+            Err(error) => {
+                self.global.bindings.insert(name.clone(), Binding::Poisoned);
+                Err(error)
+            }
+        }
+    }
+
+    // The index of `pick` and `roll` is part of the word so it has to be
+    // known while compiling
+    fn take_index(
+        generated: &mut IRChunk,
+        word: &str,
+        location: &Location,
+    ) -> Result<usize, Error> {
+        match generated.last().map(|i| &i.kind) {
+            Some(IRKind::PushInt(index)) if *index >= 0 => {
+                let index = *index as usize;
+                generated.pop();
+                Ok(index)
+            }
+            _ => Err(location
+                .error(
+                    Code::UnexpectedToken,
+                    format!(
+                        "`{}` needs a non-negative integer index right before it!",
+                        word
+                    ),
+                )
+                .with_help(format!(
+                    "write the index as a literal or constant like `2 {}`",
+                    word
+                ))
+                .into()),
+        }
+    }
+
+    fn parse_loop_exit(
+        &self,
+        kind: IRKind,
+        keyword: &str,
+        location: &Location,
+    ) -> Result<IR, Error> {
+        if !self.in_body_of(|kind| matches!(kind, ScopeKind::Loop | ScopeKind::For)) {
+            return Err(location
+                .error(
+                    Code::UnexpectedToken,
+                    format!("`{}` outside of a loop!", keyword),
+                )
+                .into());
+        }
+        Ok(IR {
+            kind,
+            location: location.clone(),
+        })
+    }
+
+    // Whether the innermost enclosing scope of `is_target` is in the same
+    // function, quotation or declaration
+    fn in_body_of(&self, is_target: impl Fn(&ScopeKind) -> bool) -> bool {
+        for scope in self.scopes.iter().rev() {
+            if is_target(&scope.kind) {
+                return true;
+            }
+            if matches!(
+                scope.kind,
+                ScopeKind::Def | ScopeKind::Quotation { .. } | ScopeKind::Var(_) | ScopeKind::Const
+            ) {
+                return false;
+            }
+        }
+        false
+    }
+
+    fn at_top_level(&self) -> bool {
+        self.scopes.is_empty()
+    }
+
+    fn parse_chunk(&mut self, chunk: Chunk) -> Result<IRChunk, Error> {
         let mut generated = IRChunk::new();
 
         let mut iter = chunk.into_iter().peekable();
         while let Some(token) = iter.next() {
+            let Token { kind, location } = token;
+
             use TokenKind::*;
-            match token.kind {
+            match kind {
                 // Literals
                 True => generated.push(IR {
                     kind: IRKind::PushBool(true),
+                    location: location.clone(),
                 }),
                 False => generated.push(IR {
                     kind: IRKind::PushBool(false),
+                    location: location.clone(),
                 }),
                 Ident(ident) => {
-                    match self
-                        .get_binding(&ident)
-                        .ok_or_else(|| format!("Unknown identifier `{}`", ident))?
-                    {
+                    if matches!(self.get_binding(&ident), Some(Binding::PendingConstant)) {
+                        self.resolve_constant(&ident, &location)?;
+                    }
+
+                    match self.get_binding(&ident).ok_or_else(|| {
+                        self.suggest_binding(
+                            location.error(
+                                Code::UnknownIdentifier,
+                                format!("Unknown identifier `{}`", ident),
+                            ),
+                            &ident,
+                            |_| true,
+                        )
+                    })? {
                         Binding::Constant(constant) => match constant {
                             Constant::Bool(value) => generated.push(IR {
                                 kind: IRKind::PushBool(*value),
+                                location: location.clone(),
                             }),
                             Constant::Int(value) => generated.push(IR {
                                 kind: IRKind::PushInt(*value),
+                                location: location.clone(),
+                            }),
+                            Constant::Float(value) => generated.push(IR {
+                                kind: IRKind::PushFloat(*value),
+                                location: location.clone(),
                             }),
                             Constant::Str(value) => generated.push(IR {
                                 kind: IRKind::PushStr(value.clone()),
+                                location: location.clone(),
+                            }),
+                            Constant::Enum(name, value) => generated.push(IR {
+                                kind: IRKind::PushEnum(name.clone(), *value),
+                                location: location.clone(),
                             }),
                         },
+                        Binding::Variable | Binding::Function if self.in_constant() => {
+                            return Err(location
+                                .error(
+                                    Code::InvalidConstant,
+                                    format!("`{}` cannot be used in the body of a `const`!", ident),
+                                )
+                                .into())
+                        }
                         Binding::Variable => generated.push(IR {
                             kind: IRKind::PushVar(ident),
+                            location: location.clone(),
                         }),
-                        Binding::Let(id) => generated.push(IR {
-                            kind: IRKind::PushBind(*id),
-                        }),
+                        Binding::Let(id) => {
+                            let kind = self.capture_binding(&ident, *id);
+                            generated.push(IR {
+                                kind,
+                                location: location.clone(),
+                            })
+                        }
                         Binding::Function => generated.push(IR {
                             kind: IRKind::Call(ident),
+                            location: location.clone(),
                         }),
-                        // Binding::Struct => todo!(),
-                        Binding::Struct => {
-                            return Err(format!("Type name `{}` is not an expression!", ident))
+                        Binding::Field(name, field) => generated.push(IR {
+                            kind: IRKind::Field(name.clone(), field.clone()),
+                            location: location.clone(),
+                        }),
+                        Binding::FieldUpdate(name, field) => generated.push(IR {
+                            kind: IRKind::FieldUpdate(name.clone(), field.clone()),
+                            location: location.clone(),
+                        }),
+                        Binding::EnumToInt(name) => generated.push(IR {
+                            kind: IRKind::EnumToInt(name.clone()),
+                            location: location.clone(),
+                        }),
+                        Binding::IntToEnum(name, variant_count) => generated.push(IR {
+                            kind: IRKind::IntToEnum(name.clone(), *variant_count),
+                            location: location.clone(),
+                        }),
+                        Binding::Struct | Binding::Enum => {
+                            return Err(location
+                                .error(
+                                    Code::UnexpectedToken,
+                                    format!("Type name `{}` is not an expression!", ident),
+                                )
+                                .into())
+                        }
+                        Binding::PendingConstant => {
+                            unreachable!("We resolve pending constants before using them")
                         }
+                        Binding::Poisoned => return Err(Error::Poisoned),
                     }
                 }
                 Int(value) => generated.push(IR {
                     kind: IRKind::PushInt(value),
+                    location: location.clone(),
+                }),
+                Float(value) => generated.push(IR {
+                    kind: IRKind::PushFloat(value),
+                    location: location.clone(),
                 }),
                 Str(value) => generated.push(IR {
                     kind: IRKind::PushStr(value),
+                    location: location.clone(),
                 }),
 
                 // Keywords
                 End => {
-                    let scope = self
-                        .pop_scope()
-                        .ok_or("Unexpected `end` keyword. No blocks to end!")?;
+                    let scope = self.pop_scope().ok_or_else(|| {
+                        location.error(
+                            Code::UnexpectedToken,
+                            "Unexpected `end` keyword. No blocks to end!",
+                        )
+                    })?;
 
                     match scope.kind {
+                        ScopeKind::Quotation { .. } => {
+                            return Err(location
+                                .error(
+                                    Code::UnexpectedToken,
+                                    "Unexpected `end` keyword. A quotation is closed with `]`!",
+                                )
+                                .into())
+                        }
+                        ScopeKind::For => {
+                            self.next_bind_id -= 2;
+                            generated.push(IR {
+                                kind: IRKind::End,
+                                location: location.clone(),
+                            });
+                        }
                         ScopeKind::Let(nbinds) => {
                             self.next_bind_id -= nbinds;
                             generated.push(IR {
                                 kind: IRKind::Unbind(nbinds),
+                                location: location.clone(),
                             });
                         }
                         ScopeKind::Var(name) => {
-                            self.bind(name, Binding::Variable)?;
-                            generated.push(IR { kind: IRKind::End });
+                            // top-level variables are bound before parsing
+                            if !self.at_top_level() {
+                                self.bind(name, Binding::Variable, &location)?;
+                            }
+                            generated.push(IR {
+                                kind: IRKind::End,
+                                location: location.clone(),
+                            });
                         }
-                        _ => generated.push(IR { kind: IRKind::End }),
+                        _ => generated.push(IR {
+                            kind: IRKind::End,
+                            location: location.clone(),
+                        }),
                     }
                 }
-                If => generated.push(IR { kind: IRKind::If }),
+                If => generated.push(IR {
+                    kind: IRKind::If,
+                    location: location.clone(),
+                }),
                 Elif => {
                     let previous = self.pop_scope();
                     if !matches!(
@@ -518,9 +1505,17 @@ impl Parser {
                             bindings: _
                         })
                     ) {
-                        return Err("`elif` block without a parent `if` block!".to_string());
+                        return Err(location
+                            .error(
+                                Code::UnexpectedToken,
+                                "`elif` block without a parent `if` block!",
+                            )
+                            .into());
                     }
-                    generated.push(IR { kind: IRKind::Elif });
+                    generated.push(IR {
+                        kind: IRKind::Elif,
+                        location: location.clone(),
+                    });
                 }
                 Else => {
                     let previous = self.pop_scope();
@@ -531,14 +1526,103 @@ impl Parser {
                             bindings: _
                         })
                     ) {
-                        return Err("`else` block without a parent `if` block!".to_string());
+                        return Err(location
+                            .error(
+                                Code::UnexpectedToken,
+                                "`else` block without a parent `if` block!",
+                            )
+                            .into());
                     }
                     self.push_scope(ScopeKind::Else);
-                    generated.push(IR { kind: IRKind::Else });
+                    generated.push(IR {
+                        kind: IRKind::Else,
+                        location: location.clone(),
+                    });
                 }
                 While => generated.push(IR {
                     kind: IRKind::While,
+                    location: location.clone(),
                 }),
+                For => {
+                    let (ident, ident_location) = match iter.next() {
+                        Some(Token {
+                            kind: TokenKind::Ident(ident),
+                            location,
+                        }) => (ident, location),
+                        token => {
+                            return Err(expected_identifier(
+                                token,
+                                &location,
+                                "Expected the name of the index after `for` keyword!",
+                            ))
+                        }
+                    };
+                    match iter.next() {
+                        Some(Token {
+                            kind: TokenKind::Do,
+                            ..
+                        }) => {}
+                        _ => {
+                            return Err(ident_location
+                                .error(
+                                    Code::UnexpectedToken,
+                                    "Expected `do` after the index of a `for` loop!",
+                                )
+                                .into())
+                        }
+                    }
+
+                    // the index is bound like a `let` name with the end of
+                    // the range bound after it
+                    let id = self.next_bind_id;
+                    self.push_scope(ScopeKind::For);
+                    if ident != "_" {
+                        self.bind(ident, Binding::Let(id), &ident_location)?;
+                    }
+                    self.next_bind_id += 2;
+
+                    generated.push(IR {
+                        kind: IRKind::For(id),
+                        location: location.clone(),
+                    });
+                }
+                Loop => {
+                    self.push_scope(ScopeKind::Loop);
+                    generated.push(IR {
+                        kind: IRKind::Loop,
+                        location: location.clone(),
+                    });
+                }
+                Match => {
+                    self.push_scope(ScopeKind::Match);
+                    generated.push(IR {
+                        kind: IRKind::Match,
+                        location: location.clone(),
+                    });
+                }
+                Case => {
+                    if !matches!(
+                        self.scopes.last(),
+                        Some(Scope {
+                            kind: ScopeKind::Match,
+                            bindings: _
+                        })
+                    ) {
+                        return Err(location
+                            .error(
+                                Code::UnexpectedToken,
+                                "`case` arm without a parent `match` block!",
+                            )
+                            .into());
+                    }
+
+                    let kind = self.parse_case_values(&mut iter, &location)?;
+                    self.push_scope(ScopeKind::Case);
+                    generated.push(IR {
+                        kind,
+                        location: location.clone(),
+                    });
+                }
                 Let => {
                     self.push_scope(ScopeKind::Let(0));
 
@@ -546,21 +1630,38 @@ impl Parser {
                     loop {
                         match iter.next() {
                             None => {
-                                return Err("Unexpected EOF while parsing let block!".to_string())
+                                return Err(location
+                                    .error(
+                                        Code::UnexpectedEof,
+                                        "Unexpected EOF while parsing let block!",
+                                    )
+                                    .into())
                             }
                             Some(Token {
                                 kind: TokenKind::In,
+                                ..
                             }) => break,
                             Some(Token {
                                 kind: TokenKind::Ident(ident),
+                                location: ident_location,
                             }) => {
                                 if ident != "_" {
-                                    self.bind(ident, Binding::Let(self.next_bind_id))?;
+                                    self.bind(
+                                        ident,
+                                        Binding::Let(self.next_bind_id),
+                                        &ident_location,
+                                    )?;
                                 }
                                 self.next_bind_id += 1;
                                 nbinds += 1;
                             }
-                            _ => return Err("Expected identifier in `let` expression!".to_string()),
+                            token => {
+                                return Err(expected_identifier(
+                                    token,
+                                    &location,
+                                    "Expected identifier in `let` expression!",
+                                ))
+                            }
                         }
                     }
 
@@ -571,56 +1672,114 @@ impl Parser {
 
                     generated.push(IR {
                         kind: IRKind::Bind(nbinds),
+                        location: location.clone(),
                     });
                 }
                 Then => {
                     self.push_scope(ScopeKind::If);
-                    generated.push(IR { kind: IRKind::Then });
+                    generated.push(IR {
+                        kind: IRKind::Then,
+                        location: location.clone(),
+                    });
+                }
+                Do => {
+                    self.push_scope(ScopeKind::Loop);
+                    generated.push(IR {
+                        kind: IRKind::Do,
+                        location: location.clone(),
+                    });
+                }
+                Break => generated.push(self.parse_loop_exit(IRKind::Break, "break", &location)?),
+                Continue => {
+                    generated.push(self.parse_loop_exit(IRKind::Continue, "continue", &location)?)
+                }
+                Return => {
+                    if !self.in_body_of(|kind| matches!(kind, ScopeKind::Def)) {
+                        return Err(location
+                            .error(Code::UnexpectedToken, "`return` outside of a function!")
+                            .into());
+                    }
+                    generated.push(IR {
+                        kind: IRKind::Return,
+                        location: location.clone(),
+                    });
                 }
-                Do => {
-                    self.push_scope(ScopeKind::Def);
-                    generated.push(IR { kind: IRKind::Do });
+                In => {
+                    return Err(location
+                        .error(Code::UnexpectedToken, "Unexpected `in` keyword!")
+                        .into())
                 }
-                In => return Err("Unexpected `in` keyword!".to_string()),
                 Def => {
-                    let ident = match iter.next() {
+                    let (ident, ident_location) = match iter.next() {
                         Some(Token {
                             kind: TokenKind::Ident(ident),
-                        }) => ident,
-                        _ => return Err("Expected an identifier after `def` keyword!".to_string()),
+                            location,
+                        }) => (ident, location),
+                        token => {
+                            return Err(expected_identifier(
+                                token,
+                                &location,
+                                "Expected an identifier after `def` keyword!",
+                            ))
+                        }
                     };
 
-                    self.bind(ident.clone(), Binding::Function)?;
+                    if !self.at_top_level() {
+                        self.bind(ident.clone(), Binding::Function, &ident_location)?;
+                    }
 
                     generated.push(IR {
                         kind: IRKind::Def(ident),
+                        location: location.clone(),
                     });
 
+                    let mut parameters = Vec::new();
+                    let mut returns = Vec::new();
+                    let mut parsing_returns = false;
                     loop {
-                        match iter.peek() {
-                            Some(Token {
-                                kind: TokenKind::Do,
-                            }) => {
-                                generated.push(IR { kind: IRKind::Do });
+                        let arg_location = match iter.peek() {
+                            Some(token) => token.location.clone(),
+                            None => {
+                                return Err(ident_location
+                                    .error(
+                                        Code::UnexpectedEof,
+                                        "Unexpected EOF while parsing function!",
+                                    )
+                                    .into())
+                            }
+                        };
+
+                        match iter.peek().map(|t| &t.kind) {
+                            Some(TokenKind::Do) => {
+                                check_type_variables(&parameters, &returns, &ident_location)?;
+                                check_free_variables(&parameters, &returns, &ident_location)?;
+                                generated.push(IR {
+                                    kind: IRKind::Do,
+                                    location: arg_location,
+                                });
                                 self.push_scope(ScopeKind::Def);
                                 iter.next(); // skip the do
                                 break;
                             }
-                            Some(Token {
-                                kind: TokenKind::DashDash,
-                            }) => {
+                            Some(TokenKind::DashDash) => {
                                 generated.push(IR {
                                     kind: IRKind::DashDash,
+                                    location: arg_location,
                                 });
+                                parsing_returns = true;
                                 iter.next(); // skip --
                             }
-                            None => {
-                                return Err("Unexpected EOF while parsing function!".to_string())
-                            }
                             _ => {
-                                let arg_type_signature = self.parse_type_signature(&mut iter)?;
+                                let arg_type_signature =
+                                    self.parse_type_signature(&mut iter, &arg_location)?;
+                                if parsing_returns {
+                                    returns.push(arg_type_signature.clone());
+                                } else {
+                                    parameters.push(arg_type_signature.clone());
+                                }
                                 generated.push(IR {
                                     kind: IRKind::FunctionArgument(arg_type_signature),
+                                    location: arg_location,
                                 });
                             }
                         }
@@ -630,172 +1789,906 @@ impl Parser {
                     let ident = match iter.next() {
                         Some(Token {
                             kind: TokenKind::Ident(ident),
+                            ..
                         }) => ident,
-                        _ => return Err("Expected an identifier after `var` keyword1".to_string()),
+                        token => {
+                            return Err(expected_identifier(
+                                token,
+                                &location,
+                                "Expected an identifier after `var` keyword!",
+                            ))
+                        }
+                    };
+
+                    // a variable can name the struct it holds before its fields
+                    let struct_type = match iter.peek() {
+                        Some(Token {
+                            kind: TokenKind::Ident(name),
+                            ..
+                        }) if matches!(self.get_binding(name), Some(Binding::Struct)) => {
+                            Some(self.parse_type_signature(&mut iter, &location)?)
+                        }
+                        _ => None,
                     };
 
                     self.push_scope(ScopeKind::Var(ident.clone()));
 
                     generated.push(IR {
-                        kind: IRKind::Var(ident),
+                        kind: IRKind::Var(ident, struct_type),
+                        location: location.clone(),
                     });
                 }
                 Const => {
-                    let ident = match iter.next() {
+                    let (ident, ident_location) = match iter.next() {
                         Some(Token {
                             kind: TokenKind::Ident(ident),
-                        }) => ident,
-                        _ => {
-                            return Err("Expected an identifier after `const` keyword!".to_string())
+                            location,
+                        }) => (ident, location),
+                        token => {
+                            return Err(expected_identifier(
+                                token,
+                                &location,
+                                "Expected an identifier after `const` keyword!",
+                            ))
                         }
                     };
-                    let value = self.evaluate_constant(&mut iter)?;
-                    self.bind(ident, Binding::Constant(value))?;
+                    let value = self.evaluate_constant(&mut iter, &ident_location)?;
+                    self.bind(ident, Binding::Constant(value), &ident_location)?;
                 }
                 Struct => {
-                    let ident = match iter.next() {
+                    let (ident, ident_location) = match iter.next() {
                         Some(Token {
                             kind: TokenKind::Ident(ident),
-                        }) => ident,
-                        _ => {
-                            return Err("Expected an identifier after `struct` keyword!".to_string())
+                            location,
+                        }) => (ident, location),
+                        token => {
+                            return Err(expected_identifier(
+                                token,
+                                &location,
+                                "Expected an identifier after `struct` keyword!",
+                            ))
                         }
                     };
 
-                    self.bind(ident.clone(), Binding::Struct)?;
+                    if !self.at_top_level() {
+                        self.bind(ident.clone(), Binding::Struct, &ident_location)?;
+                    }
 
                     generated.push(IR {
-                        kind: IRKind::Struct(ident),
+                        kind: IRKind::Struct(ident.clone()),
+                        location: location.clone(),
                     });
 
                     loop {
+                        let field_location = match iter.peek() {
+                            Some(token) => token.location.clone(),
+                            None => {
+                                return Err(ident_location
+                                    .error(
+                                        Code::UnexpectedEof,
+                                        "Unexpected EOF while parsing struct!",
+                                    )
+                                    .into())
+                            }
+                        };
+
                         if matches!(iter.peek().map(|t| &t.kind), Some(End)) {
                             iter.next();
-                            generated.push(IR { kind: IRKind::End });
+                            generated.push(IR {
+                                kind: IRKind::End,
+                                location: field_location,
+                            });
                             break;
                         }
-                        let field_type = self.parse_type_signature(&mut iter)?;
+                        let field = match iter.next().map(|token| token.kind) {
+                            Some(Ident(name)) if field_name(&name).is_some() => {
+                                field_name(&name).expect("We just checked it").to_string()
+                            }
+                            _ => {
+                                return Err(field_location
+                                    .error(
+                                        Code::ExpectedIdentifier,
+                                        "Expected a field name like `name:` before the type of a struct field!",
+                                    )
+                                    .into())
+                            }
+                        };
+                        if !self.at_top_level() {
+                            self.bind_field(&ident, &field, &field_location)?;
+                        }
+
+                        let type_location = match iter.peek() {
+                            Some(token) => token.location.clone(),
+                            None => field_location.clone(),
+                        };
+                        let field_type = self.parse_type_signature(&mut iter, &type_location)?;
+                        if field_type.has_variables() {
+                            return Err(type_location
+                                .error(
+                                    Code::InvalidTypeSignature,
+                                    format!(
+                                        "The type of the field `{}` can't contain type variables!",
+                                        field
+                                    ),
+                                )
+                                .with_help("type variables can only be used in function signatures")
+                                .into());
+                        }
                         generated.push(IR {
-                            kind: IRKind::StructField(field_type),
+                            kind: IRKind::StructField(field, field_type),
+                            location: field_location,
                         });
                     }
                 }
                 Enum => {
-                    let ident = match iter.next() {
+                    let (ident, ident_location) = match iter.next() {
                         Some(Token {
                             kind: TokenKind::Ident(ident),
-                        }) => ident,
-                        _ => return Err("Expected an identifier after `enum` keyword!".to_string()),
+                            location,
+                        }) => (ident, location),
+                        token => {
+                            return Err(expected_identifier(
+                                token,
+                                &location,
+                                "Expected an identifier after `enum` keyword!",
+                            ))
+                        }
                     };
 
-                    // self.bind(ident.clone(), Binding::Enum)?;
-
-                    // generated.push(IR {
-                    // 	kind: IRKind::Enum(ident),
-                    // });
+                    generated.push(IR {
+                        kind: IRKind::Enum(ident.clone()),
+                        location: location.clone(),
+                    });
 
-                    let mut variant_id = 0;
+                    let mut variants = Vec::new();
                     loop {
                         match iter.next() {
-                            None => return Err("Unexpected EOF while parsing enum!".to_string()),
+                            None => {
+                                return Err(ident_location
+                                    .error(
+                                        Code::UnexpectedEof,
+                                        "Unexpected EOF while parsing enum!",
+                                    )
+                                    .into())
+                            }
                             Some(Token {
                                 kind: TokenKind::End,
-                            }) => break,
+                                location: end_location,
+                            }) => {
+                                generated.push(IR {
+                                    kind: IRKind::End,
+                                    location: end_location,
+                                });
+                                break;
+                            }
                             Some(Token {
                                 kind: TokenKind::Ident(variant),
+                                location: variant_location,
                             }) => {
                                 self.bind(
                                     format!("{}.{}", ident, variant),
-                                    Binding::Constant(Constant::Int(variant_id)),
+                                    Binding::Constant(Constant::Enum(
+                                        ident.clone(),
+                                        variants.len() as i64,
+                                    )),
+                                    &variant_location,
                                 )?;
+                                generated.push(IR {
+                                    kind: IRKind::EnumVariant(variant.clone()),
+                                    location: variant_location,
+                                });
+                                variants.push(variant);
+                            }
+                            token => {
+                                return Err(expected_identifier(
+                                    token,
+                                    &location,
+                                    "Expected identifier of an enum variant!",
+                                ))
                             }
-                            // generated.push(IR {
-                            // 	kind: IRKind::EnumVariant(variant, variant_id),
-                            // }),
-                            _ => return Err("Expected identifier of an enum variant!".to_string()),
                         }
-
-                        variant_id += 1;
                     }
+
+                    // values only convert to and from `int` explicitly
+                    self.bind(
+                        format!("{}>int", ident),
+                        Binding::EnumToInt(ident.clone()),
+                        &ident_location,
+                    )?;
+                    self.bind(
+                        format!("int>{}", ident),
+                        Binding::IntToEnum(ident.clone(), variants.len()),
+                        &ident_location,
+                    )?;
+                    self.bind(ident, Binding::Enum, &ident_location)?;
                 }
-                Include => match iter.next() {
-                    Some(Token {
-                        kind: TokenKind::Str(path),
-                    }) => generated.push(IR {
-                        kind: IRKind::Include(path),
-                    }),
-                    _ => {
-                        return Err(
-                            "Expected a path to include after `include` keyword!".to_string()
+                Include => {
+                    return Err(location
+                        .error(
+                            Code::UnexpectedToken,
+                            "`include` is only allowed at the top level!",
                         )
-                    }
-                },
+                        .into())
+                }
                 DashDash => generated.push(IR {
                     kind: IRKind::DashDash,
+                    location: location.clone(),
                 }),
 
                 // Operators
-                Dup => generated.push(IR { kind: IRKind::Dup }),
-                Over => generated.push(IR { kind: IRKind::Over }),
-                Drop => generated.push(IR { kind: IRKind::Drop }),
-                Swap => generated.push(IR { kind: IRKind::Swap }),
+                Dup => generated.push(IR {
+                    kind: IRKind::Dup,
+                    location: location.clone(),
+                }),
+                Over => generated.push(IR {
+                    kind: IRKind::Over,
+                    location: location.clone(),
+                }),
+                Drop => generated.push(IR {
+                    kind: IRKind::Drop,
+                    location: location.clone(),
+                }),
+                Swap => generated.push(IR {
+                    kind: IRKind::Swap,
+                    location: location.clone(),
+                }),
+                Rot => generated.push(IR {
+                    kind: IRKind::Rot,
+                    location: location.clone(),
+                }),
+                Unrot => generated.push(IR {
+                    kind: IRKind::Unrot,
+                    location: location.clone(),
+                }),
+                Nip => generated.push(IR {
+                    kind: IRKind::Nip,
+                    location: location.clone(),
+                }),
+                Tuck => generated.push(IR {
+                    kind: IRKind::Tuck,
+                    location: location.clone(),
+                }),
+                TwoDup => generated.push(IR {
+                    kind: IRKind::TwoDup,
+                    location: location.clone(),
+                }),
+                TwoDrop => generated.push(IR {
+                    kind: IRKind::TwoDrop,
+                    location: location.clone(),
+                }),
+                TwoSwap => generated.push(IR {
+                    kind: IRKind::TwoSwap,
+                    location: location.clone(),
+                }),
+                TwoOver => generated.push(IR {
+                    kind: IRKind::TwoOver,
+                    location: location.clone(),
+                }),
+                Pick => {
+                    let index = Self::take_index(&mut generated, "pick", &location)?;
+                    generated.push(IR {
+                        kind: IRKind::Pick(index),
+                        location: location.clone(),
+                    })
+                }
+                Roll => {
+                    let index = Self::take_index(&mut generated, "roll", &location)?;
+                    generated.push(IR {
+                        kind: IRKind::Roll(index),
+                        location: location.clone(),
+                    })
+                }
                 Print => generated.push(IR {
                     kind: IRKind::Print,
+                    location: location.clone(),
+                }),
+                And => generated.push(IR {
+                    kind: IRKind::And,
+                    location: location.clone(),
+                }),
+                Or => generated.push(IR {
+                    kind: IRKind::Or,
+                    location: location.clone(),
+                }),
+                Not => generated.push(IR {
+                    kind: IRKind::Not,
+                    location: location.clone(),
+                }),
+                Plus => generated.push(IR {
+                    kind: IRKind::Add,
+                    location: location.clone(),
                 }),
-                And => generated.push(IR { kind: IRKind::And }),
-                Or => generated.push(IR { kind: IRKind::Or }),
-                Not => generated.push(IR { kind: IRKind::Not }),
-                Plus => generated.push(IR { kind: IRKind::Add }),
                 Dash => generated.push(IR {
                     kind: IRKind::Subtract,
+                    location: location.clone(),
                 }),
                 Star => generated.push(IR {
                     kind: IRKind::Multiply,
+                    location: location.clone(),
                 }),
                 Slash => generated.push(IR {
                     kind: IRKind::Divide,
+                    location: location.clone(),
+                }),
+                Eq => generated.push(IR {
+                    kind: IRKind::Eq,
+                    location: location.clone(),
+                }),
+                Neq => generated.push(IR {
+                    kind: IRKind::Neq,
+                    location: location.clone(),
+                }),
+                Lt => generated.push(IR {
+                    kind: IRKind::Lt,
+                    location: location.clone(),
+                }),
+                Gt => generated.push(IR {
+                    kind: IRKind::Gt,
+                    location: location.clone(),
+                }),
+                Percent => generated.push(IR {
+                    kind: IRKind::Modulo,
+                    location: location.clone(),
+                }),
+                Le => generated.push(IR {
+                    kind: IRKind::Le,
+                    location: location.clone(),
+                }),
+                Ge => generated.push(IR {
+                    kind: IRKind::Ge,
+                    location: location.clone(),
+                }),
+                Neg => generated.push(IR {
+                    kind: IRKind::Negate,
+                    location: location.clone(),
+                }),
+                BitAnd => generated.push(IR {
+                    kind: IRKind::BitAnd,
+                    location: location.clone(),
+                }),
+                BitOr => generated.push(IR {
+                    kind: IRKind::BitOr,
+                    location: location.clone(),
+                }),
+                BitXor => generated.push(IR {
+                    kind: IRKind::BitXor,
+                    location: location.clone(),
+                }),
+                BitNot => generated.push(IR {
+                    kind: IRKind::BitNot,
+                    location: location.clone(),
+                }),
+                Shl => generated.push(IR {
+                    kind: IRKind::Shl,
+                    location: location.clone(),
+                }),
+                Shr => generated.push(IR {
+                    kind: IRKind::Shr,
+                    location: location.clone(),
+                }),
+                DivMod => generated.push(IR {
+                    kind: IRKind::DivMod,
+                    location: location.clone(),
+                }),
+                IntToFloat => generated.push(IR {
+                    kind: IRKind::IntToFloat,
+                    location: location.clone(),
+                }),
+                FloatToInt => generated.push(IR {
+                    kind: IRKind::FloatToInt,
+                    location: location.clone(),
+                }),
+                Len => generated.push(IR {
+                    kind: IRKind::Len,
+                    location: location.clone(),
+                }),
+                Concat => generated.push(IR {
+                    kind: IRKind::Concat,
+                    location: location.clone(),
+                }),
+                Slice => generated.push(IR {
+                    kind: IRKind::Slice,
+                    location: location.clone(),
+                }),
+                Find => generated.push(IR {
+                    kind: IRKind::Find,
+                    location: location.clone(),
+                }),
+                StartsWith => generated.push(IR {
+                    kind: IRKind::StartsWith,
+                    location: location.clone(),
+                }),
+                EndsWith => generated.push(IR {
+                    kind: IRKind::EndsWith,
+                    location: location.clone(),
+                }),
+                Compare => generated.push(IR {
+                    kind: IRKind::Compare,
+                    location: location.clone(),
+                }),
+                Split => generated.push(IR {
+                    kind: IRKind::Split,
+                    location: location.clone(),
+                }),
+                Trim => generated.push(IR {
+                    kind: IRKind::Trim,
+                    location: location.clone(),
+                }),
+                StrToInt => generated.push(IR {
+                    kind: IRKind::StrToInt,
+                    location: location.clone(),
+                }),
+                IntToStr => generated.push(IR {
+                    kind: IRKind::IntToStr,
+                    location: location.clone(),
+                }),
+                // `alloc`, `null`, `zero` and `vec.new` are followed by a type
+                Alloc => {
+                    let ty = self.parse_type_signature(&mut iter, &location)?;
+                    generated.push(IR {
+                        kind: IRKind::Alloc(ty),
+                        location: location.clone(),
+                    })
+                }
+                Free => generated.push(IR {
+                    kind: IRKind::Free,
+                    location: location.clone(),
+                }),
+                Offset => generated.push(IR {
+                    kind: IRKind::Offset,
+                    location: location.clone(),
+                }),
+                Null => {
+                    let ty = self.parse_type_signature(&mut iter, &location)?;
+                    generated.push(IR {
+                        kind: IRKind::Null(ty),
+                        location: location.clone(),
+                    })
+                }
+                Zero => {
+                    let ty = self.parse_type_signature(&mut iter, &location)?;
+                    generated.push(IR {
+                        kind: IRKind::Zero(ty),
+                        location: location.clone(),
+                    })
+                }
+                Get => generated.push(IR {
+                    kind: IRKind::Get,
+                    location: location.clone(),
+                }),
+                Set => generated.push(IR {
+                    kind: IRKind::Set,
+                    location: location.clone(),
+                }),
+                VecNew => {
+                    let ty = self.parse_type_signature(&mut iter, &location)?;
+                    generated.push(IR {
+                        kind: IRKind::VecNew(ty),
+                        location: location.clone(),
+                    })
+                }
+                VecPush => generated.push(IR {
+                    kind: IRKind::VecPush,
+                    location: location.clone(),
+                }),
+                VecPop => generated.push(IR {
+                    kind: IRKind::VecPop,
+                    location: location.clone(),
+                }),
+                VecGet => generated.push(IR {
+                    kind: IRKind::VecGet,
+                    location: location.clone(),
+                }),
+                VecSet => generated.push(IR {
+                    kind: IRKind::VecSet,
+                    location: location.clone(),
+                }),
+                VecLen => generated.push(IR {
+                    kind: IRKind::VecLen,
+                    location: location.clone(),
                 }),
-                Eq => generated.push(IR { kind: IRKind::Eq }),
-                Neq => generated.push(IR { kind: IRKind::Neq }),
-                Lt => generated.push(IR { kind: IRKind::Lt }),
-                Gt => generated.push(IR { kind: IRKind::Gt }),
                 LeftArrow => generated.push(IR {
                     kind: IRKind::Assign,
+                    location: location.clone(),
                 }),
-                At => generated.push(IR { kind: IRKind::Load }),
+                At => generated.push(IR {
+                    kind: IRKind::Load,
+                    location: location.clone(),
+                }),
+                Call => generated.push(IR {
+                    kind: IRKind::CallDynamic,
+                    location: location.clone(),
+                }),
+
+                // Delimiters
+                OpenBracket => {
+                    if self.in_constant() {
+                        return Err(location
+                            .error(
+                                Code::InvalidConstant,
+                                "Quotations cannot be used in the body of a `const`!",
+                            )
+                            .into());
+                    }
+
+                    // the body of a quotation is its own function with its own binds
+                    let enclosing_bind_id = std::mem::take(&mut self.next_bind_id);
+                    self.push_scope(ScopeKind::Quotation {
+                        location: location.clone(),
+                        start: generated.len(),
+                        enclosing_bind_id,
+                        captures: Vec::new(),
+                    });
+                }
+                CloseBracket => {
+                    let (open_location, start, enclosing_bind_id, captures) = match self.pop_scope()
+                    {
+                        Some(Scope {
+                            kind:
+                                ScopeKind::Quotation {
+                                    location,
+                                    start,
+                                    enclosing_bind_id,
+                                    captures,
+                                },
+                            ..
+                        }) => (location, start, enclosing_bind_id, captures),
+                        _ => {
+                            return Err(location
+                                .error(
+                                    Code::UnexpectedToken,
+                                    "Unexpected `]` without an open `[` in the same block!",
+                                )
+                                .into())
+                        }
+                    };
+                    self.next_bind_id = enclosing_bind_id;
+
+                    // quotations are lifted into chunks of their own
+                    let id = self.quotations.len();
+                    let body = generated.split_off(start);
+                    let mut chunk = vec![IR {
+                        kind: IRKind::Quotation(id),
+                        location: open_location.clone(),
+                    }];
+                    chunk.extend(body);
+                    chunk.push(IR {
+                        kind: IRKind::End,
+                        location: location.clone(),
+                    });
+                    self.quotations.push(chunk);
+
+                    generated.push(IR {
+                        kind: IRKind::PushQuotation(id, captures),
+                        location: open_location,
+                    });
+                }
+                Reference(name) => match self.get_binding(&name) {
+                    Some(Binding::Function) if self.in_constant() => {
+                        return Err(location
+                            .error(
+                                Code::InvalidConstant,
+                                format!("`&{}` cannot be used in the body of a `const`!", name),
+                            )
+                            .into())
+                    }
+                    Some(Binding::Function) => generated.push(IR {
+                        kind: IRKind::PushFunction(name),
+                        location: location.clone(),
+                    }),
+                    Some(Binding::Poisoned) => return Err(Error::Poisoned),
+                    Some(_) => {
+                        return Err(location
+                            .error(
+                                Code::UnexpectedToken,
+                                format!(
+                                    "Only functions can be referenced but `{}` isn't one!",
+                                    name
+                                ),
+                            )
+                            .into())
+                    }
+                    None => {
+                        return Err(self
+                            .suggest_binding(
+                                location.error(
+                                    Code::UnknownIdentifier,
+                                    format!("Unknown function `{}`", name),
+                                ),
+                                &name,
+                                |binding| matches!(binding, Binding::Function),
+                            )
+                            .into())
+                    }
+                },
+                OpenParen | CloseParen => {
+                    return Err(location
+                        .error(
+                            Code::UnexpectedToken,
+                            format!(
+                                "Unexpected `{}` outside of a type signature!",
+                                location.text()
+                            ),
+                        )
+                        .into())
+                }
             }
         }
 
         Ok(generated)
     }
 
-    fn parse_type_signature(&self, tokens: &mut Tokens) -> Result<TypeSignature, String> {
+    // A `let` binding read from inside quotations is captured by every
+    // quotation between the binding and the read
+    fn capture_binding(&mut self, name: &String, id: usize) -> IRKind {
+        let found = self
+            .scopes
+            .iter()
+            .rposition(|scope| scope.bindings.contains_key(name))
+            .expect("`let` bindings are always in a scope");
+
+        let mut kind = IRKind::PushBind(id);
+        let mut source = Capture::Bind(id);
+        for scope in &mut self.scopes[found + 1..] {
+            if let ScopeKind::Quotation { captures, .. } = &mut scope.kind {
+                let index = match captures.iter().position(|capture| *capture == source) {
+                    Some(index) => index,
+                    None => {
+                        captures.push(source);
+                        captures.len() - 1
+                    }
+                };
+                kind = IRKind::PushCapture(index);
+                source = Capture::Capture(index);
+            }
+        }
+        kind
+    }
+
+    // Parses the values of a `case` arm up to its `do`. A lone `_` matches
+    // every value no other arm does.
+    fn parse_case_values(
+        &mut self,
+        tokens: &mut Tokens,
+        location: &Location,
+    ) -> Result<IRKind, Error> {
+        let mut values = Vec::new();
+        let mut default = false;
+        loop {
+            let token = match tokens.next() {
+                Some(token) => token,
+                None => {
+                    return Err(location
+                        .error(
+                            Code::UnexpectedEof,
+                            "Unexpected EOF while parsing `case` arm!",
+                        )
+                        .into())
+                }
+            };
+
+            match token.kind {
+                TokenKind::Do => break,
+                TokenKind::Int(value) => values.push(Constant::Int(value)),
+                TokenKind::Ident(ident) if ident == "_" => default = true,
+                TokenKind::Ident(ident) => {
+                    if let Some(Binding::PendingConstant) = self.get_binding(&ident) {
+                        self.resolve_constant(&ident, &token.location)?;
+                    }
+
+                    match self.get_binding(&ident) {
+                        Some(Binding::Constant(
+                            constant @ (Constant::Int(_) | Constant::Enum(..)),
+                        )) => values.push(constant.clone()),
+                        Some(Binding::Poisoned) => return Err(Error::Poisoned),
+                        Some(_) => {
+                            return Err(token
+                                .location
+                                .error(
+                                    Code::UnexpectedToken,
+                                    format!(
+                                    "`case` values must be `int` or enum constants but `{}` isn't!",
+                                    ident
+                                ),
+                                )
+                                .into())
+                        }
+                        None => {
+                            return Err(self
+                                .suggest_binding(
+                                    token.location.error(
+                                        Code::UnknownIdentifier,
+                                        format!("Unknown identifier `{}`", ident),
+                                    ),
+                                    &ident,
+                                    |binding| matches!(binding, Binding::Constant(_)),
+                                )
+                                .into())
+                        }
+                    }
+                }
+                _ => {
+                    return Err(token
+                        .location
+                        .error(
+                            Code::UnexpectedToken,
+                            "Expected a value for the `case` arm or `do`!",
+                        )
+                        .into())
+                }
+            }
+        }
+
+        match (default, values.is_empty()) {
+            (true, true) => Ok(IRKind::CaseDefault),
+            (false, false) => Ok(IRKind::Case(values)),
+            (true, false) => Err(location
+                .error(
+                    Code::UnexpectedToken,
+                    "The default `case _` arm can't have any other values!",
+                )
+                .into()),
+            (false, true) => Err(location
+                .error(
+                    Code::UnexpectedToken,
+                    "Expected a value for the `case` arm!",
+                )
+                .with_help("Use `case _ do` for an arm matching every other value")
+                .into()),
+        }
+    }
+
+    fn parse_type_signature(
+        &self,
+        tokens: &mut Tokens,
+        location: &Location,
+    ) -> Result<TypeSignature, Error> {
         match tokens.next() {
             Some(Token {
                 kind: TokenKind::Ident(ident),
+                location,
             }) => {
-                if ident == "bool" {
+                if is_type_variable(&ident) {
+                    Ok(TypeSignature::Var(ident))
+                } else if let Some(name) = ident
+                    .strip_prefix("..")
+                    .filter(|name| is_type_variable(name))
+                {
+                    Ok(TypeSignature::Row(name.to_string()))
+                } else if ident == "bool" {
                     Ok(TypeSignature::Bool)
                 } else if ident == "int" {
                     Ok(TypeSignature::Int)
+                } else if ident == "float" {
+                    Ok(TypeSignature::Float)
                 } else if ident == "str" {
                     Ok(TypeSignature::Str)
+                } else if ident == "vec" {
+                    Ok(TypeSignature::Vector(Box::new(
+                        self.parse_type_signature(tokens, &location)?,
+                    )))
                 } else {
                     match self.get_binding(&ident) {
                         Some(Binding::Struct) => Ok(TypeSignature::Struct(ident)),
-                        // Some(Binding::Enum) => Ok(TypeSignature::Enum(ident)),
-                        None => Err(format!("Undeclared identifier `{}`", ident)),
-                        _ => Err("Invalid type signature!".to_string()),
+                        Some(Binding::Enum) => Ok(TypeSignature::Enum(ident)),
+                        None => Err(self
+                            .suggest_binding(
+                                location.error(
+                                    Code::UnknownIdentifier,
+                                    format!("Undeclared identifier `{}`", ident),
+                                ),
+                                &ident,
+                                |binding| matches!(binding, Binding::Struct | Binding::Enum),
+                            )
+                            .into()),
+                        _ => Err(location
+                            .error(Code::InvalidTypeSignature, "Invalid type signature!")
+                            .into()),
                     }
                 }
             }
             Some(Token {
                 kind: TokenKind::Star,
+                location,
             }) => Ok(TypeSignature::Ptr(Box::new(
-                self.parse_type_signature(tokens)?,
+                self.parse_type_signature(tokens, &location)?,
             ))),
-            None => Err("Unexpected EOF while parsing type signature!".to_string()),
-            _ => Err("Invalid type signature!".to_string()),
+            // arrays are written `[16]int`
+            Some(Token {
+                kind: TokenKind::OpenBracket,
+                location,
+            }) => {
+                let len = match tokens.next() {
+                    Some(Token {
+                        kind: TokenKind::Int(len),
+                        ..
+                    }) if len >= 0 => len as usize,
+                    _ => {
+                        return Err(location
+                            .error(
+                                Code::InvalidTypeSignature,
+                                "Expected the length of the array after `[`!",
+                            )
+                            .into())
+                    }
+                };
+                match tokens.next() {
+                    Some(Token {
+                        kind: TokenKind::CloseBracket,
+                        ..
+                    }) => {}
+                    _ => {
+                        return Err(location
+                            .error(
+                                Code::InvalidTypeSignature,
+                                "Expected `]` after the length of the array!",
+                            )
+                            .into())
+                    }
+                }
+                Ok(TypeSignature::Array(
+                    Box::new(self.parse_type_signature(tokens, &location)?),
+                    len,
+                ))
+            }
+            Some(Token {
+                kind: TokenKind::OpenParen,
+                location,
+            }) => {
+                let mut parameters = Vec::new();
+                let mut returns = Vec::new();
+                let mut parsing_returns = false;
+                loop {
+                    match tokens.peek().map(|token| &token.kind) {
+                        Some(TokenKind::CloseParen) => {
+                            tokens.next();
+                            break;
+                        }
+                        Some(TokenKind::DashDash) if !parsing_returns => {
+                            tokens.next();
+                            parsing_returns = true;
+                        }
+                        None => {
+                            return Err(location
+                                .error(
+                                    Code::UnexpectedEof,
+                                    "Unexpected EOF while parsing function type! Expected `)`",
+                                )
+                                .into())
+                        }
+                        _ => {
+                            let ty = self.parse_type_signature(tokens, &location)?;
+                            if parsing_returns {
+                                returns.push(ty);
+                            } else {
+                                parameters.push(ty);
+                            }
+                        }
+                    }
+                }
+
+                if !parsing_returns {
+                    return Err(location
+                        .error(
+                            Code::InvalidTypeSignature,
+                            "Function type is missing its `--`!",
+                        )
+                        .with_help("write function types like `(int -- int)`")
+                        .into());
+                }
+                check_type_variables(&parameters, &returns, &location)?;
+                Ok(TypeSignature::Function(parameters, returns))
+            }
+            None => Err(location
+                .error(
+                    Code::UnexpectedEof,
+                    "Unexpected EOF while parsing type signature!",
+                )
+                .into()),
+            Some(token) => Err(token
+                .location
+                .error(Code::InvalidTypeSignature, "Invalid type signature!")
+                .into()),
         }
     }
 }
@@ -819,42 +2712,66 @@ impl Scope {
 enum ScopeKind {
     Global,
     Def,
+    // the body of a `while` or `loop`
+    Loop,
+    For,
     If,
     Else,
+    Match,
+    Case,
+    Quotation {
+        location: Location,
+        start: usize,
+        enclosing_bind_id: usize,
+        captures: Vec<Capture>,
+    },
     Let(usize),
     Var(String),
+    Const,
 }
 
 #[derive(Debug)]
 enum Binding {
     Constant(Constant),
+    Poisoned,
     Variable,
     Let(usize),
     Function,
     Struct,
+    Field(String, String),
+    FieldUpdate(String, String),
+    Enum,
+    EnumToInt(String),
+    IntToEnum(String, usize),
+    PendingConstant,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Constant {
     Bool(bool),
     Int(i64),
+    Float(f64),
     Str(String),
+    Enum(String, i64),
 }
 
 pub type IRChunk = Vec<IR>;
 pub type IRChunks = Vec<IRChunk>;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct IR {
     pub kind: IRKind,
+    pub location: Location,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum IRKind {
     // Literals
     PushBool(bool),
     PushInt(i64),
+    PushFloat(f64),
     PushStr(String),
+    PushEnum(String, i64),
 
     // Keywords
     End,
@@ -862,14 +2779,24 @@ pub enum IRKind {
     Elif,
     Else,
     While,
+    // the bind id of the index
+    For(usize),
+    Loop,
+    Match,
+    Case(Vec<Constant>),
+    CaseDefault,
     Then,
     Do,
+    Break,
+    Continue,
+    Return,
     Def(String),
     FunctionArgument(TypeSignature),
-    Var(String),
+    Var(String, Option<TypeSignature>),
     Struct(String),
-    StructField(TypeSignature),
-    Include(String),
+    StructField(String, TypeSignature),
+    Enum(String),
+    EnumVariant(String),
     DashDash,
 
     // Operators
@@ -877,6 +2804,16 @@ pub enum IRKind {
     Over,
     Drop,
     Swap,
+    Rot,
+    Unrot,
+    Nip,
+    Tuck,
+    TwoDup,
+    TwoDrop,
+    TwoSwap,
+    TwoOver,
+    Pick(usize),
+    Roll(usize),
     Print,
     And,
     Or,
@@ -889,22 +2826,178 @@ pub enum IRKind {
     Neq,
     Lt,
     Gt,
+    Modulo,
+    Le,
+    Ge,
+    Negate,
+    BitAnd,
+    BitOr,
+    BitXor,
+    BitNot,
+    Shl,
+    Shr,
+    DivMod,
+    IntToFloat,
+    FloatToInt,
+    Len,
+    Concat,
+    Slice,
+    Find,
+    StartsWith,
+    EndsWith,
+    Compare,
+    Split,
+    Trim,
+    StrToInt,
+    IntToStr,
+    Alloc(TypeSignature),
+    Free,
+    Offset,
+    Null(TypeSignature),
+    Zero(TypeSignature),
+    Get,
+    Set,
+    VecNew(TypeSignature),
+    VecPush,
+    VecPop,
+    VecGet,
+    VecSet,
+    VecLen,
     Assign,
     Load,
     Call(String),
+    CallDynamic,
+    PushFunction(String),
+    Quotation(usize),
+    PushQuotation(usize, Vec<Capture>),
+    PushCapture(usize),
+    Field(String, String),
+    FieldUpdate(String, String),
+    EnumToInt(String),
+    IntToEnum(String, usize),
     Bind(usize),
     Unbind(usize),
     PushBind(usize),
     PushVar(String),
 }
 
+// Where a quotation gets each value it captures from when it's created
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Capture {
+    Bind(usize),
+    Capture(usize),
+}
+
 #[derive(Debug, Clone)]
 pub enum TypeSignature {
     Bool,
     Int,
+    Float,
     Str,
     Ptr(Box<TypeSignature>),
+    Array(Box<TypeSignature>, usize),
+    Vector(Box<TypeSignature>),
     Struct(String),
+    Enum(String),
+    Function(Vec<TypeSignature>, Vec<TypeSignature>),
+    // a quotation whose type is inferred from where it's first used
+    Quotation(usize),
+    // `a` stands for any one type and `..a` for the rest of the stack
+    Var(String),
+    Row(String),
+}
+
+impl TypeSignature {
+    pub fn has_variables(&self) -> bool {
+        use TypeSignature::*;
+        match self {
+            Var(_) | Row(_) => true,
+            Ptr(inner) | Array(inner, _) | Vector(inner) => inner.has_variables(),
+            Function(parameters, returns) => parameters
+                .iter()
+                .chain(returns)
+                .any(TypeSignature::has_variables),
+            _ => false,
+        }
+    }
+
+    // Adds the names of the type and row variables in order of appearance
+    pub fn variables(&self, names: &mut Vec<TypeSignature>) {
+        use TypeSignature::*;
+        match self {
+            Var(_) | Row(_) if !names.contains(self) => names.push(self.clone()),
+            Ptr(inner) | Array(inner, _) | Vector(inner) => inner.variables(names),
+            Function(parameters, returns) => {
+                for ty in parameters.iter().chain(returns) {
+                    ty.variables(names);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+// Type variables are single lowercase letters
+fn is_type_variable(word: &str) -> bool {
+    let mut chars = word.chars();
+    matches!((chars.next(), chars.next()), (Some(c), None) if c.is_ascii_lowercase())
+}
+
+// A row variable stands for everything below the rest of the types so it
+// can only come first
+fn check_type_variables(
+    parameters: &[TypeSignature],
+    returns: &[TypeSignature],
+    location: &Location,
+) -> Result<(), Error> {
+    for types in [parameters, returns] {
+        if let Some(row) = types
+            .iter()
+            .skip(1)
+            .find(|ty| matches!(ty, TypeSignature::Row(_)))
+        {
+            return Err(location
+                .error(
+                    Code::InvalidTypeSignature,
+                    format!(
+                        "The row variable `{}` must come before any other types!",
+                        row
+                    ),
+                )
+                .into());
+        }
+    }
+    Ok(())
+}
+
+// The types a function returns have to be decided by what it's called with
+fn check_free_variables(
+    parameters: &[TypeSignature],
+    returns: &[TypeSignature],
+    location: &Location,
+) -> Result<(), Error> {
+    let mut bound = Vec::new();
+    for ty in parameters {
+        ty.variables(&mut bound);
+    }
+
+    let mut used = Vec::new();
+    for ty in returns {
+        ty.variables(&mut used);
+    }
+
+    match used.iter().find(|ty| !bound.contains(ty)) {
+        Some(ty) => Err(location
+            .error(
+                Code::InvalidTypeSignature,
+                format!(
+                    "The type variable `{}` is returned but never appears in the parameters!",
+                    ty
+                ),
+            )
+            .into()),
+        None => Ok(()),
+    }
 }
 
 impl std::cmp::PartialEq for TypeSignature {
@@ -913,15 +3006,37 @@ impl std::cmp::PartialEq for TypeSignature {
         match self {
             Bool => matches!(other, Bool),
             Int => matches!(other, Int),
+            Float => matches!(other, Float),
             Str => matches!(other, Str),
             Ptr(inner) => match other {
                 Ptr(other_inner) => inner.as_ref() == other_inner.as_ref(),
                 _ => false,
             },
+            Array(inner, len) => match other {
+                Array(other_inner, other_len) => inner == other_inner && len == other_len,
+                _ => false,
+            },
+            Vector(inner) => match other {
+                Vector(other_inner) => inner == other_inner,
+                _ => false,
+            },
             Struct(inner_name) => match other {
                 Struct(other_name) => inner_name == other_name, // @HACK: This works cause we don't allow duplicate identifiers
                 _ => false,
             },
+            Enum(inner_name) => match other {
+                Enum(other_name) => inner_name == other_name,
+                _ => false,
+            },
+            Function(parameters, returns) => match other {
+                Function(other_parameters, other_returns) => {
+                    parameters == other_parameters && returns == other_returns
+                }
+                _ => false,
+            },
+            Quotation(id) => matches!(other, Quotation(other_id) if id == other_id),
+            Var(name) => matches!(other, Var(other_name) if name == other_name),
+            Row(name) => matches!(other, Row(other_name) if name == other_name),
         }
     }
 }
@@ -934,9 +3049,26 @@ impl std::fmt::Display for TypeSignature {
         match self {
             Bool => write!(f, "bool"),
             Int => write!(f, "int"),
+            Float => write!(f, "float"),
             Str => write!(f, "str"),
             Ptr(ptr_to) => write!(f, "* {}", ptr_to.as_ref()),
-            Struct(name) => write!(f, "{}", name),
+            Array(element, len) => write!(f, "[{}]{}", len, element),
+            Vector(element) => write!(f, "vec {}", element),
+            Struct(name) | Enum(name) => write!(f, "{}", name),
+            Function(parameters, returns) => {
+                write!(f, "(")?;
+                for parameter in parameters {
+                    write!(f, "{} ", parameter)?;
+                }
+                write!(f, "--")?;
+                for ret in returns {
+                    write!(f, " {}", ret)?;
+                }
+                write!(f, ")")
+            }
+            Quotation(_) => write!(f, "[quotation]"),
+            Var(name) => write!(f, "{}", name),
+            Row(name) => write!(f, "..{}", name),
         }
     }
 }
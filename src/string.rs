@@ -4,6 +4,8 @@ use std::{
     str::{self, Utf8Error},
 };
 
+// The trailing NUL keeps empty strings from being zero-sized allocations,
+// the length of a string always travels with its pointer
 pub fn make_from_str(s: &str) -> Result<Box<[u8]>, LayoutError> {
     let allocation = unsafe { alloc(Layout::array::<u8>(s.len() + 1)?) };
     let slice = unsafe { slice::from_raw_parts_mut(allocation, s.len() + 1) };
@@ -13,17 +15,14 @@ pub fn make_from_str(s: &str) -> Result<Box<[u8]>, LayoutError> {
     Ok(zs)
 }
 
-pub unsafe fn ptr_to_str<'a>(ptr: *const u8) -> Result<&'a str, Utf8Error> {
-    let len = len_from_ptr(ptr);
-    let slice = unsafe { slice::from_raw_parts(ptr, len) };
-    str::from_utf8(slice)
+pub unsafe fn ptr_to_bytes<'a>(ptr: *const u8, len: usize) -> &'a [u8] {
+    // zeroed strings are empty but don't point anywhere
+    if len == 0 {
+        return &[];
+    }
+    unsafe { slice::from_raw_parts(ptr, len) }
 }
 
-fn len_from_ptr(mut s: *const u8) -> usize {
-    let mut len = 0;
-    while unsafe { *s } != 0 {
-        len += 1;
-        s = unsafe { s.add(1) };
-    }
-    len
+pub unsafe fn ptr_to_str<'a>(ptr: *const u8, len: usize) -> Result<&'a str, Utf8Error> {
+    str::from_utf8(unsafe { ptr_to_bytes(ptr, len) })
 }
@@ -1,28 +1,190 @@
+use crate::diagnostic::{self, Code, Diagnostic, Error};
+use crate::location::Location;
 use crate::parser;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 type IRIter = <parser::IRChunk as IntoIterator>::IntoIter;
 
-pub fn typecheck(ir_chunks: parser::IRChunks) -> Result<TypedChunks, String> {
+// Warnings don't stop the program from being typechecked. They're added to
+// `warnings` on success and reported alongside the errors otherwise.
+pub fn typecheck(ir_chunks: parser::IRChunks, warnings: &mut Vec<Diagnostic>) -> Result<TypedChunks, Vec<Diagnostic>> {
 	let mut typer = Typer::new();
 
+	// Types and function signatures are resolved before any bodies are
+	// checked so that they can be used before they're declared.
+	let mut types = Vec::new();
+	let mut variables = Vec::new();
+	let mut functions = Vec::new();
+	for chunk in ir_chunks {
+		use parser::IRKind::*;
+		match chunk.first().map(|i| &i.kind) {
+			Some(Quotation(id)) => {
+				typer.quotation_bodies.insert(*id, chunk.into_iter().skip(1).collect());
+			}
+			Some(Struct(_) | Enum(_)) => types.push(chunk),
+			Some(Var(..)) => variables.push(chunk),
+			Some(Def(_)) => functions.push(chunk),
+			_ => unreachable!(),
+		}
+	}
+
+	let mut struct_names = Vec::new();
+	for chunk in types {
+		if let Some(parser::IR {
+			kind: parser::IRKind::Struct(name),
+			..
+		}) = chunk.first() {
+			struct_names.push(name.clone());
+		}
+
+		let mut ir = chunk.into_iter();
+		if let Err(error) = typer.typecheck_chunk(&mut ir) {
+			typer.report(error);
+		}
+	}
+	typer.check_struct_recursion(&struct_names);
+
+	for chunk in &functions {
+		if let Some(parser::IR {
+			kind: parser::IRKind::Def(name),
+			..
+		}) = chunk.first() {
+			let signature = chunk[1..].iter().take_while(|i| !matches!(i.kind, parser::IRKind::Do));
+			match typer.resolve_function_type(&chunk[0].location, signature) {
+				Ok(function_type) => {
+					// generic functions are checked again for every set of
+					// types they're called with
+					if function_type.is_generic() {
+						typer.generic_bodies.insert(name.clone(), chunk[1..].to_vec());
+					}
+					typer.functions.insert(name.clone(), function_type);
+				}
+				Err(error) => {
+					typer.report(error);
+					typer.poisoned.insert(name.clone());
+				}
+			}
+		}
+	}
+
+	// variables are initialized in declaration order before `main` so their
+	// types are known before checking any function bodies
 	let mut typechecked = Vec::new();
+	for chunk in variables.into_iter().chain(functions) {
+		let name = match chunk.first().map(|i| &i.kind) {
+			Some(parser::IRKind::Def(name) | parser::IRKind::Var(name, _)) => name.clone(),
+			_ => unreachable!(),
+		};
+
+		// the body of a function whose signature failed can't be checked
+		if typer.poisoned.contains(&name) {
+			continue;
+		}
 
-	for chunk in ir_chunks {
 		let mut ir = chunk.into_iter();
-		let typed = typer.typecheck_chunk(&mut ir)?;
-		if !typed.is_empty() {
-			typechecked.push(typed);
+		match typer.typecheck_chunk(&mut ir) {
+			Ok(typed) => {
+				if !typed.is_empty() {
+					typechecked.push(typed);
+				}
+			}
+			Err(error) => {
+				// a failed chunk can leave stacks behind. A variable that
+				// failed is poisoned as its type is unknown but a function
+				// keeps its declared signature for its callers.
+				typer.report(error);
+				typer.type_stacks.clear();
+				typer.bind_stack.clear();
+				typer.generic_depth = 0;
+				typer.loops.clear();
+				typer.function_names.clear();
+				typer.diverged = None;
+				if !typer.functions.contains_key(&name) {
+					typer.poisoned.insert(name);
+				}
+			}
 		}
 	}
-	
-	println!("{:#?}", typechecked);
 
-	Ok(typechecked)
+	// instances only fail when their generic body does, unless the typer
+	// has a bug, so they're only reported when nothing else is
+	if !typer.diagnostics.iter().any(Diagnostic::is_error) {
+		typer.diagnostics.append(&mut typer.instance_failures);
+	}
+
+	// a quotation that's never called or passed as a function has no type
+	if !typer.diagnostics.iter().any(Diagnostic::is_error) {
+		let mut unresolved = typer.quotations.values().filter(|info| !info.resolved).collect::<Vec<_>>();
+		unresolved.sort_by_key(|info| (info.location.line, info.location.column));
+		for info in unresolved {
+			typer.diagnostics.push(info
+				.location
+				.error(Code::UninferredQuotation, "Cannot infer the type of this quotation!")
+				.with_help("`call` it or pass it where a function type like `(int -- int)` is expected"));
+		}
+	}
+	typechecked.append(&mut typer.lifted);
+
+	if typer.diagnostics.iter().any(Diagnostic::is_error) {
+		Err(typer.diagnostics)
+	} else {
+		warnings.append(&mut typer.diagnostics);
+		Ok(typechecked)
+	}
+}
+
+pub fn typecheck_constant(
+	ir: parser::IRChunk,
+	location: &Location,
+) -> Result<(TypedChunk, parser::TypeSignature), Error> {
+	let mut typer = Typer::new();
+	typer.type_stacks.push(Vec::new());
+
+	let mut generated = TypedChunk::new();
+	let mut ir = ir.into_iter();
+	while let Some(i) = ir.next() {
+		typer.typecheck_expression(&mut generated, i, &mut ir)?;
+	}
+
+	let mut type_stack = typer.type_stacks.pop().expect("We pushed one for the constant");
+	if type_stack.len() != 1 {
+		return Err(location.error(Code::InvalidBody, format!(
+			"Body of `const` expression must evaluate to a single value but evaluates to {}!",
+			parser::DisplayVec(&type_stack),
+		)).into());
+	}
+
+	let ty = type_stack.pop().expect("We just checked its length");
+	use parser::TypeSignature::*;
+	match ty {
+		Bool | Int | Float | Str | Enum(_) => Ok((generated, ty)),
+		_ => Err(location.error(Code::InvalidBody, format!("Constants of type `{}` are not supported!", ty)).into()),
+	}
 }
 
 struct Typer {
 	structs: HashMap<String, StructType>,
+	enums: HashMap<String, Vec<String>>,
+
+	// quotations are checked once their type is known from how they're used
+	quotation_bodies: HashMap<usize, Vec<parser::IR>>,
+	quotations: HashMap<usize, QuotationInfo>,
+	// the types captured by the quotations being checked
+	captures: Vec<Vec<parser::TypeSignature>>,
+	lifted: TypedChunks,
+
+	// bodies of generic functions and the names of the instances made of them
+	generic_bodies: HashMap<String, Vec<parser::IR>>,
+	instances: HashSet<String>,
+	instance_names: Vec<String>,
+	instance_failures: Vec<Diagnostic>,
+	// how many generic bodies are being checked, whose code is thrown away
+	generic_depth: usize,
+
+	loops: Vec<LoopInfo>,
+	function_names: Vec<String>,
+	// where the current block was left by `break`, `continue` or `return`
+	diverged: Option<Location>,
 	functions: HashMap<String, FunctionType>,
 	variables: HashMap<String, VariableInfo>,
 	next_variable_index: usize,
@@ -33,20 +195,44 @@ struct Typer {
 	//
 	type_stacks: Vec<Vec<parser::TypeSignature>>,
 	bind_stack: Vec<parser::TypeSignature>,
+
+	// declarations that failed to check. Anything using them is skipped
+	// rather than reported again.
+	poisoned: HashSet<String>,
+	diagnostics: Vec<Diagnostic>,
 }
 
 impl Typer {
 	fn new() -> Self {
 		Self {
 			structs: HashMap::new(),
+			enums: HashMap::new(),
+			quotation_bodies: HashMap::new(),
+			quotations: HashMap::new(),
+			captures: Vec::new(),
+			lifted: TypedChunks::new(),
+			generic_bodies: HashMap::new(),
+			instances: HashSet::new(),
+			instance_names: Vec::new(),
+			instance_failures: Vec::new(),
+			generic_depth: 0,
+			loops: Vec::new(),
+			function_names: Vec::new(),
+			diverged: None,
 			functions: HashMap::new(),
 			variables: HashMap::new(),
 			next_variable_index: 0,
 			type_stacks: Vec::new(),
 			bind_stack: Vec::new(),
+			poisoned: HashSet::new(),
+			diagnostics: Vec::new(),
 		}
 	}
 
+	fn report(&mut self, error: Error) {
+		diagnostic::report(&mut self.diagnostics, error);
+	}
+
 	fn type_stack(&mut self) -> &mut Vec<parser::TypeSignature> {
 		self
 			.type_stacks
@@ -55,22 +241,24 @@ impl Typer {
 	}
 
 	fn add_variable(&mut self, name: String, ty: parser::TypeSignature) -> usize {
-		self.variables.insert(name, VariableInfo::new(ty, self.next_variable_index));
-		self.next_variable_index += 1;
-		self.next_variable_index - 1
+		let index = self.next_variable_index;
+		self.next_variable_index += self.width(&ty);
+		self.variables.insert(name, VariableInfo::new(ty, index));
+		index
 	}
 }
 
 impl Typer {
-	fn typecheck_chunk(&mut self, ir: &mut IRIter) -> Result<TypedChunk, String> {
+	fn typecheck_chunk(&mut self, ir: &mut IRIter) -> Result<TypedChunk, Error> {
 		let mut generated = TypedChunk::new();
 
 		while let Some(i) = ir.next() {
 			use parser::IRKind::*;
 			match i.kind {
-				Def(name) => self.typecheck_function(&mut generated, name, ir)?,
-				Var(name) => self.typecheck_variable(&mut generated, name, ir)?,
-				Struct(name) => self.typecheck_struct(name, ir)?,
+				Def(name) => self.typecheck_function(&mut generated, name, i.location, ir)?,
+				Var(name, struct_type) => self.typecheck_variable(&mut generated, name, struct_type, i.location, ir)?,
+				Struct(name) => self.typecheck_struct(name, i.location, ir)?,
+				Enum(name) => self.typecheck_enum(name, ir),
 				_ => unreachable!(),
 			}
 		}
@@ -81,45 +269,79 @@ impl Typer {
 	fn typecheck_expression(
 		&mut self,
 		generated: &mut TypedChunk,
-		ir: parser::IRKind,
+		ir: parser::IR,
 		rest: &mut IRIter,
-	) -> Result<(), String> {
+	) -> Result<(), Error> {
+		let parser::IR { kind, location } = ir;
+
+		// nothing but the cleanup of `let` scopes can follow leaving a block
+		if let Some(exit) = &self.diverged {
+			if !matches!(kind, Unbind(_)) {
+				return Err(location
+					.error(Code::Unreachable, "Unreachable code!")
+					.with_note_at(exit.clone(), "control always leaves the block here")
+					.into());
+			}
+		}
+
 		use parser::IRKind::*;
-		match ir {
+		match kind {
 			// Literals
 			PushBool(value) => {
 				generated.push(TypedIR {
 					kind: TypedIRKind::PushBool(value),
+					location: location.clone(),
 				});
 				self.type_stack().push(parser::TypeSignature::Bool);
 			}
 			PushInt(value) => {
 				generated.push(TypedIR {
 					kind: TypedIRKind::PushInt(value),
+					location: location.clone(),
 				});
 				self.type_stack().push(parser::TypeSignature::Int);
 			}
+			PushFloat(value) => {
+				generated.push(TypedIR {
+					kind: TypedIRKind::PushFloat(value),
+					location: location.clone(),
+				});
+				self.type_stack().push(parser::TypeSignature::Float);
+			}
 			PushStr(value) => {
 				generated.push(TypedIR {
-                    kind: TypedIRKind::PushStr(value),
+					kind: TypedIRKind::PushStr(value),
+					location: location.clone(),
 				});
 				self.type_stack().push(parser::TypeSignature::Str);
 			}
+			PushEnum(name, value) => {
+				generated.push(TypedIR {
+					kind: TypedIRKind::PushInt(value),
+					location: location.clone(),
+				});
+				self.type_stack().push(parser::TypeSignature::Enum(name));
+			}
 
 			// Keywords
-			End => return Err("Unexpected `end`!".to_string()),
-			If => self.typecheck_if(generated, rest)?,
-			Elif => return Err("Unexpected `elif`!".to_string()),
-			Else => return Err("Unexpected `else`!".to_string()),
-			While => self.typecheck_while(generated, rest)?,
-			Then => return Err("Unexpected `then`!".to_string()),
-			Do => return Err("Unexpected `do`!".to_string()),
-			Def(name) => self.typecheck_function(generated, name, rest)?,
+			End => return Err(location.error(Code::UnexpectedKeyword, "Unexpected `end`!").into()),
+			If => self.typecheck_if(generated, location, rest)?,
+			Elif => return Err(location.error(Code::UnexpectedKeyword, "Unexpected `elif`!").into()),
+			Else => return Err(location.error(Code::UnexpectedKeyword, "Unexpected `else`!").into()),
+			While => self.typecheck_while(generated, location, rest)?,
+			For(id) => self.typecheck_for(generated, id, location, rest)?,
+			Loop => self.typecheck_infinite_loop(generated, location, rest)?,
+			Then => return Err(location.error(Code::UnexpectedKeyword, "Unexpected `then`!").into()),
+			Do => return Err(location.error(Code::UnexpectedKeyword, "Unexpected `do`!").into()),
+			Def(name) => self.typecheck_function(generated, name, location, rest)?,
 			FunctionArgument(_) => unreachable!(),
-			Var(name) => self.typecheck_variable(generated, name, rest)?,
-			Struct(name) => self.typecheck_struct(name, rest)?,
-			StructField(_) => unreachable!(),
-			Include(_) => unreachable!(), // This'll eventually be handled in the parser
+			Var(name, struct_type) => self.typecheck_variable(generated, name, struct_type, location, rest)?,
+			Struct(name) => self.typecheck_struct(name, location, rest)?,
+			StructField(..) => unreachable!(),
+			Enum(name) => self.typecheck_enum(name, rest),
+			EnumVariant(_) => unreachable!(),
+			Match => self.typecheck_match(generated, location, rest)?,
+			Case(_) | CaseDefault => return Err(location.error(Code::UnexpectedKeyword, "Unexpected `case`!").into()),
 			DashDash => unreachable!(),
 
 			// Operators
@@ -127,399 +349,698 @@ impl Typer {
 				let top = (self
 					.type_stack()
 					.last()
-					.ok_or("Cannot `dup` nonexistant data!".to_string())?)
+					.ok_or_else(|| location.error(Code::StackUnderflow, "Cannot `dup` nonexistant data!"))?)
 				.clone();
+				let kind = self.shuffle_kind(std::slice::from_ref(&top), &[0, 0], TypedIRKind::Dup);
 				self.type_stack().push(top);
 				generated.push(TypedIR {
-					kind: TypedIRKind::Dup,
+					kind,
+					location: location.clone(),
 				});
 			}
 			Over => {
 				if self.type_stack().len() < 2 {
-					return Err(format!(
+					return Err(location.error(Code::StackUnderflow, format!(
 						"`over` expects at least 2 items on the stack but there were {}!",
 						self.type_stack().len()
-					));
+					)).into());
 				}
 
 				let type_stack_len = self.type_stack().len();
+				let shuffled = self.type_stack()[type_stack_len - 2..].to_vec();
+				let kind = self.shuffle_kind(&shuffled, &[0, 1, 0], TypedIRKind::Over);
 				let top = self.type_stack()[type_stack_len - 2].clone();
 				self.type_stack().push(top);
 
 				generated.push(TypedIR {
-					kind: TypedIRKind::Over,
+					kind,
+					location: location.clone(),
 				});
 			}
 			Drop => {
-				self
+				let top = self
 					.type_stack()
 					.pop()
-					.ok_or("Cannot `drop` nonexistant data!".to_string())?;
+					.ok_or_else(|| location.error(Code::StackUnderflow, "Cannot `drop` nonexistant data!"))?;
 				generated.push(TypedIR {
-					kind: TypedIRKind::Drop,
+					kind: self.shuffle_kind(&[top], &[], TypedIRKind::Drop),
+					location: location.clone(),
 				});
 			}
 			Swap => {
 				let a = self
 					.type_stack()
 					.pop()
-					.ok_or("Cannot `swap` nonexistant data!".to_string())?;
+					.ok_or_else(|| location.error(Code::StackUnderflow, "Cannot `swap` nonexistant data!"))?;
 				let b = self
 					.type_stack()
 					.pop()
-					.ok_or("Cannot `swap` nonexistant data!".to_string())?;
+					.ok_or_else(|| location.error(Code::StackUnderflow, "Cannot `swap` nonexistant data!"))?;
+				let kind = self.shuffle_kind(&[b.clone(), a.clone()], &[1, 0], TypedIRKind::Swap);
 				self.type_stack().push(a);
 				self.type_stack().push(b);
 				generated.push(TypedIR {
-					kind: TypedIRKind::Swap,
+					kind,
+					location: location.clone(),
 				});
 			}
+			Rot => self.typecheck_shuffle(generated, location, "rot", 3, &[1, 2, 0], TypedIRKind::Rot)?,
+			Unrot => self.typecheck_shuffle(generated, location, "-rot", 3, &[2, 0, 1], TypedIRKind::Unrot)?,
+			Nip => self.typecheck_shuffle(generated, location, "nip", 2, &[1], TypedIRKind::Nip)?,
+			Tuck => self.typecheck_shuffle(generated, location, "tuck", 2, &[1, 0, 1], TypedIRKind::Tuck)?,
+			TwoDup => self.typecheck_shuffle(generated, location, "2dup", 2, &[0, 1, 0, 1], TypedIRKind::TwoDup)?,
+			TwoDrop => self.typecheck_shuffle(generated, location, "2drop", 2, &[], TypedIRKind::TwoDrop)?,
+			TwoSwap => self.typecheck_shuffle(generated, location, "2swap", 4, &[2, 3, 0, 1], TypedIRKind::TwoSwap)?,
+			TwoOver => self.typecheck_shuffle(generated, location, "2over", 4, &[0, 1, 2, 3, 0, 1], TypedIRKind::TwoOver)?,
+			Pick(index) => {
+				let outputs = (0..=index).chain([0]).collect::<Vec<_>>();
+				self.typecheck_shuffle(generated, location, &format!("{} pick", index), index + 1, &outputs, TypedIRKind::Pick(index))?
+			}
+			Roll(index) => {
+				let outputs = (1..=index).chain([0]).collect::<Vec<_>>();
+				self.typecheck_shuffle(generated, location, &format!("{} roll", index), index + 1, &outputs, TypedIRKind::Roll(index))?
+			}
 			Print => {
 				let top = self
 					.type_stack()
 					.pop()
-					.ok_or("Cannot `print` nonexistant data!".to_string())?;
-				use parser::TypeSignature::*;
-				match top {
-					Bool => generated.push(TypedIR {
-						kind: TypedIRKind::PrintBool,
-					}),
-					Int => generated.push(TypedIR {
-						kind: TypedIRKind::PrintInt,
-					}),
-					Str =>
-						generated.push(TypedIR {
-						kind: TypedIRKind::PrintStr,
-					}),
-					Ptr(_) => generated.push(TypedIR {
-						kind: TypedIRKind::PrintPtr,
-					}),
-					Struct(_) => unreachable!(),
+					.ok_or_else(|| location.error(Code::StackUnderflow, "Cannot `print` nonexistant data!"))?;
+				if let parser::TypeSignature::Struct(_) = top {
+					self.typecheck_struct_print(generated, location, &top)?;
+				} else {
+					let kind = self.print_kind(&top, &location)?;
+					generated.push(TypedIR { kind, location });
 				}
 			}
 			And => {
-				let b = self.type_stack().pop().ok_or("Cannot `and` nonexistant data!".to_string())?;
-				let a = self.type_stack().pop().ok_or("Cannot `and` nonexistant data!".to_string())?;
+				let b = self.type_stack().pop().ok_or_else(|| location.error(Code::StackUnderflow, "Cannot `and` nonexistant data!"))?;
+				let a = self.type_stack().pop().ok_or_else(|| location.error(Code::StackUnderflow, "Cannot `and` nonexistant data!"))?;
 
 				if a != parser::TypeSignature::Bool {
-					return Err(format!("Cannot `and` something of type `{}`!", a));
+					return Err(location.error(Code::TypeMismatch, format!("Cannot `and` something of type `{}`!", a)).into());
 				}
 				if b != parser::TypeSignature::Bool {
-					return Err(format!("Cannot `and` something of type `{}`!", b));
+					return Err(location.error(Code::TypeMismatch, format!("Cannot `and` something of type `{}`!", b)).into());
 				}
 
 				self.type_stack().push(parser::TypeSignature::Bool);
 
 				generated.push(TypedIR {
 					kind: TypedIRKind::And,
+					location: location.clone(),
 				});
 			}
 			Or => {
-				let b = self.type_stack().pop().ok_or("Cannot `or` nonexistant data!".to_string())?;
-				let a = self.type_stack().pop().ok_or("Cannot `or` nonexistant data!".to_string())?;
+				let b = self.type_stack().pop().ok_or_else(|| location.error(Code::StackUnderflow, "Cannot `or` nonexistant data!"))?;
+				let a = self.type_stack().pop().ok_or_else(|| location.error(Code::StackUnderflow, "Cannot `or` nonexistant data!"))?;
 
 				if a != parser::TypeSignature::Bool {
-					return Err(format!("Cannot `or` something of type `{}`!", a));
+					return Err(location.error(Code::TypeMismatch, format!("Cannot `or` something of type `{}`!", a)).into());
 				}
 				if b != parser::TypeSignature::Bool {
-					return Err(format!("Cannot `or` something of type `{}`!", b));
+					return Err(location.error(Code::TypeMismatch, format!("Cannot `or` something of type `{}`!", b)).into());
 				}
 
 				self.type_stack().push(parser::TypeSignature::Bool);
 
 				generated.push(TypedIR {
 					kind: TypedIRKind::Or,
+					location: location.clone(),
 				});
 			}
 			Not => {
-				let a = self.type_stack().pop().ok_or("Cannot `or` nonexistant data!".to_string())?;
+				let a = self.type_stack().pop().ok_or_else(|| location.error(Code::StackUnderflow, "Cannot `or` nonexistant data!"))?;
 
 				if a != parser::TypeSignature::Bool {
-					return Err(format!("Cannot `or` something of type `{}`!", a));
+					return Err(location.error(Code::TypeMismatch, format!("Cannot `or` something of type `{}`!", a)).into());
 				}
 
 				self.type_stack().push(parser::TypeSignature::Bool);
 
 				generated.push(TypedIR {
 					kind: TypedIRKind::Not,
+					location: location.clone(),
 				});
 			}
-			Add => {
+			Add => self.typecheck_numeric_operation(generated, location, "add", 2, TypedIRKind::Add, TypedIRKind::FloatAdd)?,
+			Subtract => self.typecheck_numeric_operation(generated, location, "subtract", 2, TypedIRKind::Subtract, TypedIRKind::FloatSubtract)?,
+			Multiply => self.typecheck_numeric_operation(generated, location, "multiply", 2, TypedIRKind::Multiply, TypedIRKind::FloatMultiply)?,
+			Divide => self.typecheck_numeric_operation(generated, location, "divide", 2, TypedIRKind::Divide, TypedIRKind::FloatDivide)?,
+			Eq if matches!(self.type_stack().last(), Some(parser::TypeSignature::Struct(_))) => self.typecheck_struct_eq(generated, location, false)?,
+			Neq if matches!(self.type_stack().last(), Some(parser::TypeSignature::Struct(_))) => self.typecheck_struct_eq(generated, location, true)?,
+			Eq => {
 				let b = self
 					.type_stack()
 					.pop()
-					.ok_or("Cannot add nonexistant data!".to_string())?;
+					.ok_or_else(|| location.error(Code::StackUnderflow, "Cannot check nonexistant data for equality!"))?;
 				let a = self
 					.type_stack()
 					.pop()
-					.ok_or("Cannot add nonexistant data!".to_string())?;
+					.ok_or_else(|| location.error(Code::StackUnderflow, "Cannot check nonexistant data for equality!"))?;
 
-				if a != parser::TypeSignature::Int {
-					return Err(format!("Cannot add something of type `{}`!", a));
+				if matches!(a, parser::TypeSignature::Array(..) | parser::TypeSignature::Vector(_)) {
+					return Err(location.error(Code::TypeMismatch, format!("Cannot check values of type `{}` for equality!", a)).into());
 				}
-				if b != parser::TypeSignature::Int {
-					return Err(format!("Cannot add something of type `{}`!", b));
+				if a != b {
+					let diagnostic = location.error(Code::TypeMismatch, format!(
+						"Operands of equality operation have different types! `{}` vs. `{}`!",
+						a, b
+					));
+					return Err(with_conversion_help(diagnostic, &a, &b).into());
 				}
 
-				self.type_stack().push(parser::TypeSignature::Int);
+				self.type_stack().push(parser::TypeSignature::Bool);
 
 				generated.push(TypedIR {
-					kind: TypedIRKind::Add,
+					kind: match a {
+						parser::TypeSignature::Float => TypedIRKind::FloatEq,
+						parser::TypeSignature::Str => TypedIRKind::StrEq,
+						_ => TypedIRKind::Eq,
+					},
+					location: location.clone(),
 				});
 			}
-			Subtract => {
+			Neq => {
 				let b = self
 					.type_stack()
 					.pop()
-					.ok_or("Cannot subtract nonexistant data!".to_string())?;
+					.ok_or_else(|| location.error(Code::StackUnderflow, "Cannot check nonexistant data for non-equality!"))?;
 				let a = self
 					.type_stack()
 					.pop()
-					.ok_or("Cannot subtract nonexistant data!".to_string())?;
+					.ok_or_else(|| location.error(Code::StackUnderflow, "Cannot check nonexistant data for non-equality!"))?;
 
-				if a != parser::TypeSignature::Int {
-					return Err(format!("Cannot subtract something of type `{}`!", a));
+				if matches!(a, parser::TypeSignature::Array(..) | parser::TypeSignature::Vector(_)) {
+					return Err(location.error(Code::TypeMismatch, format!("Cannot check values of type `{}` for non-equality!", a)).into());
 				}
-				if b != parser::TypeSignature::Int {
-					return Err(format!("Cannot subtract something of type `{}`!", b));
+				if a != b {
+					let diagnostic = location.error(Code::TypeMismatch, format!(
+						"Operands of non-equality operation have different types! `{}` vs. `{}`!",
+						a, b
+					));
+					return Err(with_conversion_help(diagnostic, &a, &b).into());
 				}
 
-				self.type_stack().push(parser::TypeSignature::Int);
+				self.type_stack().push(parser::TypeSignature::Bool);
 
 				generated.push(TypedIR {
-					kind: TypedIRKind::Subtract,
+					kind: match a {
+						parser::TypeSignature::Float => TypedIRKind::FloatNeq,
+						parser::TypeSignature::Str => TypedIRKind::StrNeq,
+						_ => TypedIRKind::Neq,
+					},
+					location: location.clone(),
 				});
 			}
-			Multiply => {
-				let b = self
-					.type_stack()
-					.pop()
-					.ok_or("Cannot multiply nonexistant data!".to_string())?;
-				let a = self
-					.type_stack()
-					.pop()
-					.ok_or("Cannot multiply nonexistant data!".to_string())?;
-
-				if a != parser::TypeSignature::Int {
-					return Err(format!("Cannot multiply something of type `{}`!", a));
+			Lt => self.typecheck_numeric_operation(generated, location, "compare", 2, TypedIRKind::Lt, TypedIRKind::FloatLt)?,
+			Gt => self.typecheck_numeric_operation(generated, location, "compare", 2, TypedIRKind::Gt, TypedIRKind::FloatGt)?,
+			Modulo => self.typecheck_int_operation(generated, location, "take the remainder of", 2, &[parser::TypeSignature::Int], TypedIRKind::Modulo)?,
+			Le => self.typecheck_numeric_operation(generated, location, "compare", 2, TypedIRKind::Le, TypedIRKind::FloatLe)?,
+			Ge => self.typecheck_numeric_operation(generated, location, "compare", 2, TypedIRKind::Ge, TypedIRKind::FloatGe)?,
+			Negate => self.typecheck_numeric_operation(generated, location, "negate", 1, TypedIRKind::Negate, TypedIRKind::FloatNegate)?,
+			BitAnd => self.typecheck_int_operation(generated, location, "`band`", 2, &[parser::TypeSignature::Int], TypedIRKind::BitAnd)?,
+			BitOr => self.typecheck_int_operation(generated, location, "`bor`", 2, &[parser::TypeSignature::Int], TypedIRKind::BitOr)?,
+			BitXor => self.typecheck_int_operation(generated, location, "`bxor`", 2, &[parser::TypeSignature::Int], TypedIRKind::BitXor)?,
+			BitNot => self.typecheck_int_operation(generated, location, "`bnot`", 1, &[parser::TypeSignature::Int], TypedIRKind::BitNot)?,
+			Shl => self.typecheck_int_operation(generated, location, "shift", 2, &[parser::TypeSignature::Int], TypedIRKind::Shl)?,
+			Shr => self.typecheck_int_operation(generated, location, "shift", 2, &[parser::TypeSignature::Int], TypedIRKind::Shr)?,
+			DivMod => self.typecheck_int_operation(generated, location, "divide", 2, &[parser::TypeSignature::Int, parser::TypeSignature::Int], TypedIRKind::DivMod)?,
+			IntToFloat => self.typecheck_conversion(generated, location, "int>float", parser::TypeSignature::Int, parser::TypeSignature::Float, TypedIRKind::IntToFloat)?,
+			FloatToInt => self.typecheck_conversion(generated, location, "float>int", parser::TypeSignature::Float, parser::TypeSignature::Int, TypedIRKind::FloatToInt)?,
+			Len => match self.type_stack().last() {
+				Some(parser::TypeSignature::Array(_, len)) => {
+					// the length of an array is part of its type
+					let len = *len;
+					let array = self.type_stack().pop().expect("We just looked at it");
+					let kind = self.shuffle_kind(&[array], &[], TypedIRKind::Drop);
+					generated.push(TypedIR {
+						kind,
+						location: location.clone(),
+					});
+					self.type_stack().push(parser::TypeSignature::Int);
+					generated.push(TypedIR {
+						kind: TypedIRKind::PushInt(len as i64),
+						location: location.clone(),
+					});
+				}
+				Some(parser::TypeSignature::Ptr(array)) if matches!(array.as_ref(), parser::TypeSignature::Array(..)) => {
+					let len = match array.as_ref() {
+						parser::TypeSignature::Array(_, len) => *len,
+						_ => unreachable!(),
+					};
+					self.type_stack().pop();
+					self.type_stack().push(parser::TypeSignature::Int);
+					generated.push(TypedIR {
+						kind: TypedIRKind::Drop,
+						location: location.clone(),
+					});
+					generated.push(TypedIR {
+						kind: TypedIRKind::PushInt(len as i64),
+						location: location.clone(),
+					});
 				}
-				if b != parser::TypeSignature::Int {
-					return Err(format!("Cannot multiply something of type `{}`!", b));
+				_ => self.typecheck_builtin(generated, location, "len", &[parser::TypeSignature::Str], &[parser::TypeSignature::Int], TypedIRKind::StrLen)?,
+			},
+			Concat => self.typecheck_builtin(generated, location, "concat", &[parser::TypeSignature::Str, parser::TypeSignature::Str], &[parser::TypeSignature::Str], TypedIRKind::StrConcat)?,
+			Slice => self.typecheck_builtin(generated, location, "slice", &[parser::TypeSignature::Str, parser::TypeSignature::Int, parser::TypeSignature::Int], &[parser::TypeSignature::Str], TypedIRKind::StrSlice)?,
+			Find => self.typecheck_builtin(generated, location, "find", &[parser::TypeSignature::Str, parser::TypeSignature::Str], &[parser::TypeSignature::Int], TypedIRKind::StrFind)?,
+			StartsWith => self.typecheck_builtin(generated, location, "starts-with", &[parser::TypeSignature::Str, parser::TypeSignature::Str], &[parser::TypeSignature::Bool], TypedIRKind::StrStartsWith)?,
+			EndsWith => self.typecheck_builtin(generated, location, "ends-with", &[parser::TypeSignature::Str, parser::TypeSignature::Str], &[parser::TypeSignature::Bool], TypedIRKind::StrEndsWith)?,
+			Compare => self.typecheck_builtin(generated, location, "compare", &[parser::TypeSignature::Str, parser::TypeSignature::Str], &[parser::TypeSignature::Int], TypedIRKind::StrCompare)?,
+			Split => self.typecheck_builtin(generated, location, "split", &[parser::TypeSignature::Str, parser::TypeSignature::Str], &[parser::TypeSignature::Str, parser::TypeSignature::Str, parser::TypeSignature::Bool], TypedIRKind::StrSplit)?,
+			Trim => self.typecheck_builtin(generated, location, "trim", &[parser::TypeSignature::Str], &[parser::TypeSignature::Str], TypedIRKind::StrTrim)?,
+			StrToInt => self.typecheck_builtin(generated, location, "str>int", &[parser::TypeSignature::Str], &[parser::TypeSignature::Int, parser::TypeSignature::Bool], TypedIRKind::StrToInt)?,
+			IntToStr => self.typecheck_builtin(generated, location, "int>str", &[parser::TypeSignature::Int], &[parser::TypeSignature::Str], TypedIRKind::IntToStr)?,
+			Alloc(ty) => {
+				let width = self.pointee_width(&ty, "alloc", &location)?;
+				// allocations start out zeroed
+				if !self.has_zero_value(&ty) {
+					return Err(location.error(Code::TypeMismatch, format!("Cannot `alloc` `{}` since it has no zero value!", ty)).into());
+				}
+				let ptr = parser::TypeSignature::Ptr(Box::new(ty));
+				self.typecheck_builtin(generated, location, "alloc", &[parser::TypeSignature::Int], &[ptr], TypedIRKind::Alloc(width))?
+			}
+			Free => {
+				let top = self.type_stack().pop().ok_or_else(|| location.error(Code::StackUnderflow, "Cannot `free` nonexistant data!"))?;
+				if !matches!(top, parser::TypeSignature::Ptr(_)) {
+					return Err(location.error(Code::TypeMismatch, format!("Cannot `free` something of type `{}`!", top)).into());
 				}
-
-				self.type_stack().push(parser::TypeSignature::Int);
 
 				generated.push(TypedIR {
-					kind: TypedIRKind::Multiply,
+					kind: TypedIRKind::Free,
+					location: location.clone(),
 				});
 			}
-			Divide => {
-				let b = self
-					.type_stack()
-					.pop()
-					.ok_or("Cannot divide nonexistant data!".to_string())?;
-				let a = self
-					.type_stack()
-					.pop()
-					.ok_or("Cannot divide nonexistant data!".to_string())?;
+			Offset => {
+				let type_stack_len = self.type_stack().len();
+				let ty = match self.type_stack().get(type_stack_len.wrapping_sub(2)..) {
+					Some([parser::TypeSignature::Ptr(ty), parser::TypeSignature::Int]) => ty.as_ref().clone(),
+					_ => {
+						let code = if type_stack_len < 2 { Code::StackUnderflow } else { Code::TypeMismatch };
+						return Err(location.error(code, format!(
+							"`ptr+` expects a pointer and an `int` on top of the stack! Stack: {}",
+							parser::DisplayVec(self.type_stack())
+						)).into());
+					}
+				};
 
-				if a != parser::TypeSignature::Int {
-					return Err(format!("Cannot divide something of type `{}`!", a));
+				// pointers move by whole elements
+				let width = self.pointee_width(&ty, "ptr+", &location)?;
+				self.type_stack().pop();
+				generated.push(TypedIR {
+					kind: TypedIRKind::Offset(width),
+					location: location.clone(),
+				});
+			}
+			Zero(ty) => {
+				let width = self.pointee_width(&ty, "zero", &location)?;
+				if !self.has_zero_value(&ty) {
+					return Err(location.error(Code::TypeMismatch, format!("`{}` has no zero value!", ty)).into());
 				}
-				if b != parser::TypeSignature::Int {
-					return Err(format!("Cannot divide something of type `{}`!", b));
+
+				self.type_stack().push(ty);
+				generated.push(TypedIR {
+					kind: TypedIRKind::Zero(width),
+					location: location.clone(),
+				});
+			}
+			Get => self.typecheck_get(generated, location)?,
+			Set => self.typecheck_set(generated, location)?,
+			VecNew(ty) => {
+				self.flatten_type(&ty, &mut Vec::new(), &mut Vec::new(), &location)?;
+				let vec = parser::TypeSignature::Vector(Box::new(ty));
+				self.typecheck_builtin(generated, location, "vec.new", &[], &[vec], TypedIRKind::VecNew)?
+			}
+			VecPush => {
+				let (vec, element, width) = self.vec_element("vec.push", false, &location)?;
+				self.typecheck_builtin(generated, location, "vec.push", &[element, vec], &[], TypedIRKind::VecPush(width))?
+			}
+			VecPop => {
+				let (vec, element, width) = self.vec_element("vec.pop", false, &location)?;
+				self.typecheck_builtin(generated, location, "vec.pop", &[vec], &[element], TypedIRKind::VecPop(width))?
+			}
+			VecGet => {
+				let (vec, element, width) = self.vec_element("vec.get", true, &location)?;
+				self.typecheck_builtin(generated, location, "vec.get", &[vec, parser::TypeSignature::Int], &[element], TypedIRKind::VecGet(width))?
+			}
+			VecSet => {
+				let (vec, element, width) = self.vec_element("vec.set", true, &location)?;
+				self.typecheck_builtin(generated, location, "vec.set", &[element, vec, parser::TypeSignature::Int], &[], TypedIRKind::VecSet(width))?
+			}
+			VecLen => {
+				// the length doesn't depend on the elements so this works on generic vectors too
+				let top = self.type_stack().pop().ok_or_else(|| location.error(Code::StackUnderflow, "Cannot `vec.len` nonexistant data!"))?;
+				if !matches!(top, parser::TypeSignature::Vector(_)) {
+					return Err(location.error(Code::TypeMismatch, format!("Cannot `vec.len` something of type `{}`!", top)).into());
 				}
 
 				self.type_stack().push(parser::TypeSignature::Int);
-
 				generated.push(TypedIR {
-					kind: TypedIRKind::Divide,
+					kind: TypedIRKind::VecLen,
+					location: location.clone(),
 				});
 			}
-			Eq => {
-				let b = self
-					.type_stack()
-					.pop()
-					.ok_or("Cannot check nonexistant data for equality!".to_string())?;
-				let a = self
-					.type_stack()
-					.pop()
-					.ok_or("Cannot check nonexistant data for equality!".to_string())?;
-
-				if a != b {
-					return Err(format!(
-						"Operands of equality operation have different types! `{}` vs. `{}`!",
-						a, b
-					));
+			Null(ty) => {
+				self.type_stack().push(parser::TypeSignature::Ptr(Box::new(ty)));
+				generated.push(TypedIR {
+					kind: TypedIRKind::PushInt(0),
+					location: location.clone(),
+				});
+			}
+			Assign => {
+				let b = self.type_stack().pop().ok_or_else(|| location.error(Code::StackUnderflow, "Cannot assign nonexistant data to a variable!"))?;
+				if self.type_stack().is_empty() {
+					return Err(location.error(Code::StackUnderflow, "Cannot assign to nonexistant data!").into());
 				}
 
-				self.type_stack().push(parser::TypeSignature::Bool);
+				let ptr_to = match b {
+					parser::TypeSignature::Ptr(ptr_to) => *ptr_to,
+					_ => return Err(location.error(Code::TypeMismatch, format!("Cannot assign to something of non-pointer type! Found `{}`!", b)).into()),
+				};
+
+				if !self.stack_ends_with(std::slice::from_ref(&ptr_to))? {
+					let found = self.type_stack().last().expect("We checked the stack isn't empty");
+					return Err(location.error(Code::TypeMismatch, format!(
+						"Cannot assign to mismatched types! Expected `{}` but found `{}`",
+						ptr_to,
+						found
+					)).into());
+				}
 
+				self.type_stack().pop();
 				generated.push(TypedIR {
-					kind: TypedIRKind::Eq,
+					kind: TypedIRKind::Assign(self.width(&ptr_to)),
+					location: location.clone(),
 				});
 			}
-			Neq => {
-				let b = self
-					.type_stack()
-					.pop()
-					.ok_or("Cannot check nonexistant data for non-equality!".to_string())?;
-				let a = self
-					.type_stack()
-					.pop()
-					.ok_or("Cannot check nonexistant data for non-equality!".to_string())?;
-
-				if a != b {
-					return Err(format!(
-						"Operands of non-equality operation have different types! `{}` vs. `{}`!",
-						a, b
-					));
+			Load => {
+				let a = self.type_stack().pop().ok_or_else(|| location.error(Code::StackUnderflow, "Cannot load non-existant data!"))?;
+                if let parser::TypeSignature::Ptr(ptr_to) = a {
+                    let width = self.width(&ptr_to);
+                    self.type_stack().push(*ptr_to);
+                    generated.push(TypedIR {
+					kind: TypedIRKind::Load(width),
+					location: location.clone(),
+				});
+                } else {
+					return Err(location.error(Code::TypeMismatch, format!("Cannot load something of type `{}`!", a)).into());
+                }
+			}
+			Call(name) => {
+				if self.poisoned.contains(&name) {
+					return Err(Error::Poisoned);
 				}
 
-				self.type_stack().push(parser::TypeSignature::Bool);
+				let function_type = self
+					.functions
+					.get(&name)
+					.expect("Unresolved identifiers should be caught during parsing");
+				let parameters = function_type.parameters.clone();
+				let returns = function_type.returns.clone();
+
+				let (consumed, returns, name) = if function_type.is_generic() {
+					let substitution = match self.unify_stack(&parameters)? {
+						Some(substitution) => substitution,
+						None => return Err(self.call_mismatch(&name, &location)),
+					};
+
+					// a row variable takes everything below the other parameters
+					let consumed = match parameters.first() {
+						Some(parser::TypeSignature::Row(_)) => self.type_stack().len(),
+						_ => parameters.len(),
+					};
+					let instance = if self.generic_depth > 0 {
+						name
+					} else {
+						self.instantiate(&name, &substitution, &location)?
+					};
+					(consumed, substitution.apply_all(&returns), instance)
+				} else {
+					if !self.stack_ends_with(&parameters)? {
+						return Err(self.call_mismatch(&name, &location));
+					}
+					(parameters.len(), returns, name)
+				};
+
+				let type_stack_len = self.type_stack().len();
+				self.type_stack().truncate(type_stack_len - consumed);
+				self.type_stack().extend(returns);
 
 				generated.push(TypedIR {
-					kind: TypedIRKind::Neq,
+					kind: TypedIRKind::Call(name),
+					location: location.clone(),
 				});
 			}
-			Lt => {
-				let b = self
-					.type_stack()
-					.pop()
-					.ok_or("Cannot compare nonexistant data!".to_string())?;
-				let a = self
-					.type_stack()
-					.pop()
-					.ok_or("Cannot compare nonexistant data!".to_string())?;
-
-				if a != parser::TypeSignature::Int {
-					return Err(format!("Cannot compare something of type `{}`!", a));
+			Break | Continue => {
+				let current_loop = self.loops.last().expect("The parser only allows `break` and `continue` in loops");
+				let type_stack_before_loop = current_loop.type_stack.clone();
+				let binds_before_loop = current_loop.binds;
+				let keyword = if matches!(kind, Break) { "break" } else { "continue" };
+				if !self.stack_matches(&type_stack_before_loop)? {
+					return Err(location.error(Code::LoopAltersStack, format!(
+						"`{}` leaves the loop with altered type stack! Expected: {} vs. Actual: {}",
+						keyword,
+						parser::DisplayVec(&type_stack_before_loop),
+						parser::DisplayVec(self.type_stack()),
+					)).into());
 				}
-				if b != parser::TypeSignature::Int {
-					return Err(format!("Cannot compare something of type `{}`!", b));
+				if matches!(kind, Break) {
+					self.loops.last_mut().expect("We just read it").broken = true;
 				}
 
-				self.type_stack().push(parser::TypeSignature::Bool);
-
+				// the `let` scopes inside the loop are left too
+				let nbinds = self.cells(&self.bind_stack[binds_before_loop..]);
 				generated.push(TypedIR {
-					kind: TypedIRKind::Lt,
+					kind: if matches!(kind, Break) { TypedIRKind::Break(nbinds) } else { TypedIRKind::Continue(nbinds) },
+					location: location.clone(),
 				});
+				self.diverged = Some(location);
 			}
-			Gt => {
-				let b = self
-					.type_stack()
-					.pop()
-					.ok_or("Cannot compare nonexistant data!".to_string())?;
-				let a = self
-					.type_stack()
-					.pop()
-					.ok_or("Cannot compare nonexistant data!".to_string())?;
-
-				if a != parser::TypeSignature::Int {
-					return Err(format!("Cannot compare something of type `{}`!", a));
-				}
-				if b != parser::TypeSignature::Int {
-					return Err(format!("Cannot compare something of type `{}`!", b));
+			Return => {
+				let name = self.function_names.last().expect("The parser only allows `return` in functions").clone();
+				let function_type = self.functions.get(&name).expect("We inserted it before checking the body");
+				let returns = function_type.returns.clone();
+				// checking the end of the stack first takes any fields there as structs
+				if !self.stack_ends_with(&returns)? || self.type_stack().len() != returns.len() {
+					let function_type = self.functions.get(&name).expect("We inserted it before checking the body");
+					return Err(location
+						.error(Code::ReturnMismatch, format!(
+							"`return` doesn't match the return types of `{}`! Expected: {} vs. Actual {}",
+							name,
+							parser::DisplayVec(&function_type.returns),
+							parser::DisplayVec(self.type_stacks.last().expect("We should have a type stack")),
+						))
+						.with_note_at(function_type.location.clone(), format!("function `{}` declared here", name)).into());
 				}
 
-				self.type_stack().push(parser::TypeSignature::Bool);
-
+				// returning drops the binds of the whole call
 				generated.push(TypedIR {
-					kind: TypedIRKind::Gt,
+					kind: TypedIRKind::Return,
+					location: location.clone(),
 				});
+				self.diverged = Some(location);
 			}
-			Assign => {
-				// @TODO:
-				// handle strings
-				//
-				let b = self.type_stack().pop().ok_or("Cannot assign nonexistant data to a variable!".to_string())?;
-				let a = self.type_stack().pop().ok_or("Cannot assign to nonexistant data!".to_string())?;
-
-				if let parser::TypeSignature::Ptr(ptr_to) = b {
-					if a != *ptr_to {
-						return Err(format!("Cannot assign to mismatched types! Expected `{}` but found `{}`", ptr_to, a));
-					}
-				} else {
-					return Err(format!("Cannot assign to something of non-pointer type! Found `{}`!", b));
+			CallDynamic => {
+				let top = self.type_stack().pop().ok_or_else(|| location.error(Code::StackUnderflow, "Cannot `call` nonexistant data!"))?;
+				let (parameters, returns) = match top {
+					parser::TypeSignature::Function(parameters, returns) => (parameters, returns),
+					parser::TypeSignature::Quotation(id) => self.infer_quotation(id)?,
+					_ => return Err(location.error(Code::TypeMismatch, format!("Cannot `call` something of type `{}`!", top)).into()),
+				};
+
+				if !self.stack_ends_with(&parameters)? {
+					return Err(location.error(Code::TypeMismatch, format!(
+						"Incorrect types for `call`! Stack: {}. Parameters: {}",
+						parser::DisplayVec(self.type_stack()),
+						parser::DisplayVec(&parameters)
+					)).into());
 				}
 
+				let type_stack_len = self.type_stack().len();
+				self.type_stack().truncate(type_stack_len - parameters.len());
+				self.type_stack().extend(returns);
 				generated.push(TypedIR {
-					kind: TypedIRKind::Assign,
+					kind: TypedIRKind::CallDynamic,
+					location: location.clone(),
 				});
 			}
-			Load => {
-				let a = self.type_stack().pop().ok_or("Cannot load non-existant data!".to_string())?;
-                if let parser::TypeSignature::Ptr(ptr_to) = a {
-                    self.type_stack().push(*ptr_to);
-                    generated.push(TypedIR {
-                        kind: TypedIRKind::Load,
-                    });
-                } else {
-					return Err(format!("Cannot load something of type `{}`!", a));
-                }
-			}
-			Call(name) => {
+			PushFunction(name) => {
+				if self.poisoned.contains(&name) {
+					return Err(Error::Poisoned);
+				}
+
 				let function_type = self
 					.functions
 					.get(&name)
 					.expect("Unresolved identifiers should be caught during parsing");
+				if function_type.is_generic() {
+					return Err(location
+						.error(Code::TypeMismatch, format!("Cannot take a reference to the generic function `{}`!", name))
+						.with_help(format!("wrap it in a quotation like `[ {} ]` so its types can be inferred", name))
+						.into());
+				}
+				let ty = parser::TypeSignature::Function(function_type.parameters.clone(), function_type.returns.clone());
+				self.type_stack().push(ty);
+				generated.push(TypedIR {
+					kind: TypedIRKind::PushFunction(name),
+					location: location.clone(),
+				});
+			}
+			Quotation(_) => unreachable!(),
+			PushQuotation(id, captures) => {
+				let mut capture_types = Vec::new();
+				for capture in captures {
+					let (ty, kind) = match capture {
+						parser::Capture::Bind(index) => {
+							let ty = self.bind_stack[index].clone();
+							let kind = TypedIRKind::PushBind(self.cells(&self.bind_stack[..index]), self.width(&ty));
+							(ty, kind)
+						}
+						parser::Capture::Capture(index) => {
+							let captures = self.captures.last().expect("Only quotations capture from quotations");
+							let ty = captures[index].clone();
+							let kind = TypedIRKind::PushCapture(self.cells(&captures[..index]), self.width(&ty));
+							(ty, kind)
+						}
+					};
+					capture_types.push(ty);
+					generated.push(TypedIR {
+						kind,
+						location: location.clone(),
+					});
+				}
 
-				if !self
-					.type_stacks
-					.last()
-					.expect("We should have a type stack")
-					.ends_with(function_type.parameters.as_slice())
-				{
-					return Err(format!(
-						"Incorrect types for call to `{}`! Stack: {}. Parameters: {}",
-						name, 
-						parser::DisplayVec(self.type_stacks.last().expect("We should have a type stack")), 
-						parser::DisplayVec(&function_type.parameters)
-					));
+				generated.push(TypedIR {
+					kind: TypedIRKind::MakeClosure(self.quotation_name(&location), self.cells(&capture_types)),
+					location: location.clone(),
+				});
+				self.quotations.insert(id, QuotationInfo {
+					location,
+					captures: capture_types,
+					resolved: false,
+				});
+				self.type_stack().push(parser::TypeSignature::Quotation(id));
+			}
+			PushCapture(index) => {
+				let captures = self.captures.last().expect("Only quotations capture values");
+				let ty = captures[index].clone();
+				let kind = TypedIRKind::PushCapture(self.cells(&captures[..index]), self.width(&ty));
+				self.type_stack().push(ty);
+				generated.push(TypedIR {
+					kind,
+					location: location.clone(),
+				});
+			}
+			EnumToInt(name) => {
+				let top = self.type_stack().pop().ok_or_else(|| location.error(Code::StackUnderflow, format!("Cannot convert nonexistant data with `{}>int`!", name)))?;
+				if top != parser::TypeSignature::Enum(name.clone()) {
+					let diagnostic = location.error(Code::TypeMismatch, format!("`{}>int` expects a `{}` but found `{}`!", name, name, top));
+					return Err(diagnostic.into());
 				}
 
-				let type_stack_len = self
-					.type_stacks
-					.last()
-					.expect("We should have a type stack")
-					.len();
-				self
-					.type_stacks
-					.last_mut()
-					.expect("We should have a type stack")
-					.truncate(type_stack_len - function_type.parameters.len());
-				self
-					.type_stacks
-					.last_mut()
-					.expect("We should have a type stack")
-					.extend_from_slice(function_type.returns.as_slice());
+				// the value of an enum already is its variant's index
+				self.type_stack().push(parser::TypeSignature::Int);
+			}
+			IntToEnum(name, variant_count) => {
+				let top = self.type_stack().pop().ok_or_else(|| location.error(Code::StackUnderflow, format!("Cannot convert nonexistant data with `int>{}`!", name)))?;
+				if top != parser::TypeSignature::Int {
+					return Err(location.error(Code::TypeMismatch, format!("`int>{}` expects an `int` but found `{}`!", name, top)).into());
+				}
 
+				self.type_stack().push(parser::TypeSignature::Enum(name));
 				generated.push(TypedIR {
-					kind: TypedIRKind::Call(name),
+					kind: TypedIRKind::CheckEnum(variant_count),
+					location: location.clone(),
+				});
+			}
+			Field(name, field) => {
+				let (layout, field_type) = self.field_layout(&name, &field, &location)?;
+				let struct_type = parser::TypeSignature::Struct(name.clone());
+				if !self.stack_ends_with(&[struct_type])? {
+					let code = if self.type_stack().is_empty() { Code::StackUnderflow } else { Code::TypeMismatch };
+					return Err(location.error(code, format!(
+						"`{}.{}` expects a `{}` on top of the stack! Stack: {}",
+						name,
+						field,
+						name,
+						parser::DisplayVec(self.type_stack())
+					)).into());
+				}
+
+				self.type_stack().push(field_type);
+				generated.push(TypedIR {
+					kind: TypedIRKind::Field(layout),
+					location: location.clone(),
+				});
+			}
+			FieldUpdate(name, field) => {
+				let (layout, field_type) = self.field_layout(&name, &field, &location)?;
+				let expected = [parser::TypeSignature::Struct(name.clone()), field_type];
+				if !self.stack_ends_with(&expected)? {
+					let code = if self.type_stack().len() < expected.len() { Code::StackUnderflow } else { Code::TypeMismatch };
+					return Err(location.error(code, format!(
+						"`{}.{}<-` expects a `{}` and the new value of `{}` on top of the stack! Stack: {}. Expected: {}",
+						name,
+						field,
+						name,
+						field,
+						parser::DisplayVec(self.type_stack()),
+						parser::DisplayVec(&expected)
+					)).into());
+				}
+
+				self.type_stack().pop();
+				generated.push(TypedIR {
+					kind: TypedIRKind::FieldUpdate(layout),
+					location: location.clone(),
 				});
 			}
 			Bind(nbinds) => {
+				if self.type_stack().len() < nbinds {
+					return Err(location.error(Code::StackUnderflow, format!(
+						"`let` expects at least {} items on the stack but there were {}!",
+						nbinds,
+						self.type_stack().len()
+					)).into());
+				}
+
 				let split_idx = self.type_stack().len() - nbinds;
-				let drain = self.type_stacks.last_mut().expect("We should have a type stack").drain(split_idx..);
-				self.bind_stack.extend(drain);
+				let bound = self.type_stack().split_off(split_idx);
+				let ncells = self.cells(&bound);
+				self.bind_stack.extend(bound);
 
 				generated.push(TypedIR {
-					kind: TypedIRKind::Bind(nbinds),
+					kind: TypedIRKind::Bind(ncells),
+					location: location.clone(),
 				});
 			}
 			Unbind(nbinds) => {
-				self.bind_stack.truncate(self.bind_stack.len() - nbinds);
+				let split_idx = self.bind_stack.len() - nbinds;
+				let ncells = self.cells(&self.bind_stack[split_idx..]);
+				self.bind_stack.truncate(split_idx);
 				generated.push(TypedIR {
-					kind: TypedIRKind::Unbind(nbinds),
+					kind: TypedIRKind::Unbind(ncells),
+					location: location.clone(),
 				});
 			}
 			PushBind(id) => {
 				let ty = self.bind_stack[id].clone();
+				let kind = TypedIRKind::PushBind(self.cells(&self.bind_stack[..id]), self.width(&ty));
 				self.type_stack().push(ty);
-				generated.push(TypedIR { kind: TypedIRKind::PushBind(id) });
+				generated.push(TypedIR {
+					kind,
+					location: location.clone(),
+				});
 			}
 			PushVar(name) => {
-				let var = self.variables.get(&name).expect("Unknown identifiers should be handled during parsing");
+				if self.poisoned.contains(&name) {
+					return Err(Error::Poisoned);
+				}
+
+				let var = self
+					.variables
+					.get(&name)
+					.ok_or_else(|| location.error(Code::UninitializedVariable, format!("Variable `{}` is used before it is initialized!", name)))?;
 				self
 				.type_stacks
 				.last_mut()
@@ -527,125 +1048,899 @@ impl Typer {
 				.push(parser::TypeSignature::Ptr(
 					Box::new(var.ty.clone()))
 				);
-				generated.push(TypedIR { kind: TypedIRKind::PushVar(var.index) });
+				generated.push(TypedIR {
+					kind: TypedIRKind::PushVar(var.index),
+					location: location.clone(),
+				});
 			}
 		}
 		Ok(())
 	}
 
-	fn typecheck_function(
+	// Replaces the top `inputs` types with the ones at the indices of `outputs`,
+	// counted from the deepest input
+	fn typecheck_shuffle(
 		&mut self,
 		generated: &mut TypedChunk,
-		name: String,
-		ir: &mut IRIter,
-	) -> Result<(), String> {
+		location: Location,
+		word: &str,
+		inputs: usize,
+		outputs: &[usize],
+		kind: TypedIRKind,
+	) -> Result<(), Error> {
+		if self.type_stack().len() < inputs {
+			return Err(location.error(Code::StackUnderflow, format!(
+				"`{}` expects at least {} items on the stack but there were {}!",
+				word,
+				inputs,
+				self.type_stack().len()
+			)).into());
+		}
+
+		let split_idx = self.type_stack().len() - inputs;
+		let shuffled = self.type_stack().split_off(split_idx);
+		self.type_stack().extend(outputs.iter().map(|index| shuffled[*index].clone()));
 		generated.push(TypedIR {
-			kind: TypedIRKind::Def(name.clone()),
+			kind: self.shuffle_kind(&shuffled, outputs, kind),
+			location,
 		});
+		Ok(())
+	}
 
-		let mut function_type = FunctionType::new();
+	// Pops `inputs` integers and pushes the `outputs` of an arithmetic,
+	// bitwise or comparison operation
+	fn typecheck_int_operation(
+		&mut self,
+		generated: &mut TypedChunk,
+		location: Location,
+		verb: &str,
+		inputs: usize,
+		outputs: &[parser::TypeSignature],
+		kind: TypedIRKind,
+	) -> Result<(), Error> {
+		if self.type_stack().len() < inputs {
+			return Err(location.error(Code::StackUnderflow, format!("Cannot {} nonexistant data!", verb)).into());
+		}
 
-		// parse parameter and return types for function
-		{
-			let mut parsing_return_types = false;
-			while let Some(i) = ir.next() {
-				use parser::IRKind::*;
-				match i.kind {
-					Do => break,
-					FunctionArgument(type_signature) => {
-						let types = if parsing_return_types {
-							&mut function_type.returns
-						} else {
-							&mut function_type.parameters
-						};
+		let split_idx = self.type_stack().len() - inputs;
+		let operands = self.type_stack().split_off(split_idx);
+		if let Some(operand) = operands.iter().find(|operand| **operand != parser::TypeSignature::Int) {
+			return Err(location.error(Code::TypeMismatch, format!("Cannot {} something of type `{}`!", verb, operand)).into());
+		}
 
-						match type_signature {
-							parser::TypeSignature::Struct(name) => {
-								let struct_type = self
-									.structs
-									.get(&name)
-									.expect("Unresolved identifiers should be caught during parsing");
-								types.extend_from_slice(struct_type.field_types.as_slice());
-							}
-							_ => types.push(type_signature),
-						}
-					}
-					DashDash => parsing_return_types = true,
-					_ => unreachable!(),
-				}
-			}
+		self.type_stack().extend(outputs.iter().cloned());
+		generated.push(TypedIR { kind, location });
+		Ok(())
+	}
+
+	// Arithmetic and comparisons work on either `int`s or `float`s but never
+	// a mix of both
+	fn typecheck_numeric_operation(
+		&mut self,
+		generated: &mut TypedChunk,
+		location: Location,
+		verb: &str,
+		inputs: usize,
+		int_kind: TypedIRKind,
+		float_kind: TypedIRKind,
+	) -> Result<(), Error> {
+		if self.type_stack().len() < inputs {
+			return Err(location.error(Code::StackUnderflow, format!("Cannot {} nonexistant data!", verb)).into());
+		}
+
+		let split_idx = self.type_stack().len() - inputs;
+		let operands = self.type_stack().split_off(split_idx);
+		if let Some(operand) = operands.iter().find(|operand| !matches!(operand, parser::TypeSignature::Int | parser::TypeSignature::Float)) {
+			return Err(location.error(Code::TypeMismatch, format!("Cannot {} something of type `{}`!", verb, operand)).into());
+		}
+
+		let ty = operands[0].clone();
+		if let Some(operand) = operands.iter().find(|operand| **operand != ty) {
+			let diagnostic = location.error(Code::TypeMismatch, format!(
+				"Cannot {} `{}` and `{}`!",
+				verb, ty, operand
+			));
+			return Err(with_conversion_help(diagnostic, &ty, operand).into());
+		}
+
+		let comparison = matches!(int_kind, TypedIRKind::Lt | TypedIRKind::Gt | TypedIRKind::Le | TypedIRKind::Ge);
+		let kind = if ty == parser::TypeSignature::Float { float_kind } else { int_kind };
+		self.type_stack().push(if comparison { parser::TypeSignature::Bool } else { ty });
+		generated.push(TypedIR { kind, location });
+		Ok(())
+	}
+
+	// Words with a fixed signature like the string built-ins
+	fn typecheck_builtin(
+		&mut self,
+		generated: &mut TypedChunk,
+		location: Location,
+		word: &str,
+		inputs: &[parser::TypeSignature],
+		outputs: &[parser::TypeSignature],
+		kind: TypedIRKind,
+	) -> Result<(), Error> {
+		if !self.stack_ends_with(inputs)? {
+			let code = if self.type_stack().len() < inputs.len() { Code::StackUnderflow } else { Code::TypeMismatch };
+			return Err(location.error(code, format!(
+				"`{}` expects {} on top of the stack! Stack: {}",
+				word,
+				parser::DisplayVec(inputs),
+				parser::DisplayVec(self.type_stack())
+			)).into());
+		}
+
+		let type_stack_len = self.type_stack().len();
+		self.type_stack().truncate(type_stack_len - inputs.len());
+		self.type_stack().extend(outputs.iter().cloned());
+		generated.push(TypedIR { kind, location });
+		Ok(())
+	}
+
+	fn typecheck_conversion(
+		&mut self,
+		generated: &mut TypedChunk,
+		location: Location,
+		word: &str,
+		from: parser::TypeSignature,
+		to: parser::TypeSignature,
+		kind: TypedIRKind,
+	) -> Result<(), Error> {
+		let top = self
+			.type_stack()
+			.pop()
+			.ok_or_else(|| location.error(Code::StackUnderflow, format!("Cannot `{}` nonexistant data!", word)))?;
+		if top != from {
+			return Err(location.error(Code::TypeMismatch, format!(
+				"`{}` expects a `{}` but found `{}`!",
+				word, from, top
+			)).into());
+		}
+
+		self.type_stack().push(to);
+		generated.push(TypedIR { kind, location });
+		Ok(())
+	}
+
+	fn typecheck_function(
+		&mut self,
+		generated: &mut TypedChunk,
+		name: String,
+		location: Location,
+		ir: &mut IRIter,
+	) -> Result<(), Error> {
+		let start = generated.len();
+		let definition = ir.clone();
+		generated.push(TypedIR {
+			kind: TypedIRKind::Def(name.clone()),
+			location: location.clone(),
+		});
+
+		// functions declared at the top level have their types resolved before
+		// any bodies are checked so we only need to resolve nested functions
+		let signature = ir
+			.by_ref()
+			.take_while(|i| !matches!(i.kind, parser::IRKind::Do))
+			.collect::<Vec<_>>();
+		let function_type = match self.functions.remove(&name) {
+			Some(function_type) => function_type,
+			None => self.resolve_function_type(&location, signature.iter())?,
+		};
+
+		// a generic body is checked with its type variables standing for
+		// types it knows nothing about
+		let generic = function_type.is_generic();
+		if generic {
+			self.generic_depth += 1;
 		}
 
 		self.type_stacks.push(function_type.parameters.clone());
 		self.functions.insert(name.clone(), function_type);
+		self.function_names.push(name.clone());
 
+		let mut end_location = location;
 		while let Some(i) = ir.next() {
 			use parser::IRKind::*;
 			match i.kind {
-				End => break,
-				_ => self.typecheck_expression(generated, i.kind, ir)?,
+				End => {
+					end_location = i.location;
+					break;
+				}
+				_ => self.typecheck_expression(generated, i, ir)?,
 			}
 		}
 
-		if *self
-			.type_stacks
-			.last()
-			.expect("We should have a type stack")
-			!= self
-				.functions
-				.get(&name)
-				.expect("We inserted it before checking the body")
-				.returns
-		{
-			return Err(format!(
-				"The function `{}` doesn't match its return types! Expected: {} vs. Actual {}",
-				name,
-				parser::DisplayVec(&self.functions.get(&name).expect("We inserted it before checking the body").returns),
-				parser::DisplayVec(self.type_stacks.last().expect("We should have a type stack")),
-			));
+		// a body that always returns early has been checked at each `return`
+		let returns = self.functions.get(&name).expect("We inserted it before checking the body").returns.clone();
+		if self.diverged.take().is_none() && (!self.stack_ends_with(&returns)? || self.type_stack().len() != returns.len()) {
+			let function_type = self.functions.get(&name).expect("We inserted it before checking the body");
+			return Err(end_location
+				.error(Code::ReturnMismatch, format!(
+					"The function `{}` doesn't match its return types! Expected: {} vs. Actual {}",
+					name,
+					parser::DisplayVec(&function_type.returns),
+					parser::DisplayVec(self.type_stacks.last().expect("We should have a type stack")),
+				))
+				.with_note_at(function_type.location.clone(), format!("function `{}` declared here", name)).into());
 		}
 
 		self
 			.type_stacks
 			.pop()
 			.expect("We pushed one before typechecking the body so it should be here");
+		self.function_names.pop();
+
+		if generic {
+			self.generic_depth -= 1;
+			generated.truncate(start);
+			let length = definition.len() - ir.len();
+			self.generic_bodies.entry(name).or_insert_with(|| definition.take(length).collect());
+		}
 
 		Ok(())
 	}
 
-	fn typecheck_variable(&mut self, generated: &mut TypedChunk, name: String, ir: &mut IRIter) -> Result<(), String> {
+	// Checks the body of a generic function with the types it's called with
+	// and names the resulting function after them
+	fn instantiate(&mut self, name: &str, substitution: &Substitution, location: &Location) -> Result<String, Error> {
+		let function_type = self.functions.get(name).expect("Only declared functions are instantiated");
+		let mut variables = Vec::new();
+		for ty in &function_type.parameters {
+			ty.variables(&mut variables);
+		}
+		let arguments = variables
+			.iter()
+			.map(|variable| match variable {
+				parser::TypeSignature::Row(_) => parser::DisplayVec(&substitution.apply_all(std::slice::from_ref(variable))).to_string(),
+				_ => substitution.apply(variable).to_string(),
+			})
+			.collect::<Vec<_>>();
+		let instance = format!("{}<{}>", name, arguments.join(", "));
+		if self.instances.contains(&instance) {
+			return Ok(instance);
+		}
+
+		if self.instance_names.len() >= MAX_INSTANCE_DEPTH {
+			return Err(location
+				.error(Code::InvalidBody, format!("Instantiating `{}` requires more than {} nested instances!", instance, MAX_INSTANCE_DEPTH))
+				.into());
+		}
+
+		let body = match self.generic_bodies.get(name) {
+			Some(body) => body.clone(),
+			None => {
+				return Err(location
+					.error(Code::InvalidBody, format!("The generic function `{}` is called before its body is checked!", name))
+					.into())
+			}
+		};
+
+		let instance_type = FunctionType {
+			parameters: substitution.apply_all(&function_type.parameters),
+			returns: substitution.apply_all(&function_type.returns),
+			location: function_type.location.clone(),
+		};
+		let definition_location = instance_type.location.clone();
+		self.instances.insert(instance.clone());
+		self.functions.insert(instance.clone(), instance_type);
+
+		let enclosing_stacks = std::mem::take(&mut self.type_stacks);
+		let enclosing_binds = std::mem::take(&mut self.bind_stack);
+		let enclosing_captures = std::mem::take(&mut self.captures);
+		let enclosing_depth = std::mem::take(&mut self.generic_depth);
+		let enclosing_loops = std::mem::take(&mut self.loops);
+		let enclosing_names = std::mem::take(&mut self.function_names);
+		self.instance_names.push(instance.clone());
+
+		let mut generated = TypedChunk::new();
+		let result = self.typecheck_function(&mut generated, instance.clone(), definition_location, &mut body.into_iter());
+
+		self.instance_names.pop();
+		self.type_stacks = enclosing_stacks;
+		self.bind_stack = enclosing_binds;
+		self.captures = enclosing_captures;
+		self.generic_depth = enclosing_depth;
+		self.loops = enclosing_loops;
+		self.function_names = enclosing_names;
+		self.diverged = None;
+
+		match result {
+			Ok(()) => {
+				self.lifted.push(generated);
+				Ok(instance)
+			}
+			Err(error) => {
+				if let Error::Diagnostic(diagnostic) = error {
+					self.instance_failures.push(diagnostic.with_note_at(location.clone(), format!("while checking `{}` called here", instance)));
+				}
+				Err(Error::Poisoned)
+			}
+		}
+	}
+
+	fn call_mismatch(&self, name: &str, location: &Location) -> Error {
+		let function_type = self.functions.get(name).expect("Only declared functions are called");
+		location
+			.error(Code::TypeMismatch, format!(
+				"Incorrect types for call to `{}`! Stack: {}. Parameters: {}",
+				name,
+				parser::DisplayVec(self.type_stacks.last().expect("We should have a type stack")),
+				parser::DisplayVec(&function_type.parameters)
+			))
+			.with_note_at(function_type.location.clone(), format!("function `{}` declared here", name))
+			.into()
+	}
+
+	// Quotations are lifted into functions named after where they're written
+	fn quotation_name(&self, location: &Location) -> String {
+		match self.instance_names.last() {
+			Some(instance) => format!("{}[{}]", instance, location),
+			None => format!("[{}]", location),
+		}
+	}
+
+	// Matches the parameters of a generic function against the top of the stack
+	fn unify_stack(&mut self, parameters: &[parser::TypeSignature]) -> Result<Option<Substitution>, Error> {
+		self.pack_stack(parameters)?;
+		let stack = self.type_stack().clone();
+		let mut substitution = Substitution::default();
+		let unified = self.unify_list(parameters, &stack, &mut substitution, true)?;
+		Ok(unified.then_some(substitution))
+	}
+
+	// With `suffix` the pattern only has to match the end of `actual`
+	fn unify_list(
+		&mut self,
+		pattern: &[parser::TypeSignature],
+		actual: &[parser::TypeSignature],
+		substitution: &mut Substitution,
+		suffix: bool,
+	) -> Result<bool, Error> {
+		let (row, pattern) = match pattern.split_first() {
+			Some((parser::TypeSignature::Row(name), rest)) => (Some(name), rest),
+			_ => (None, pattern),
+		};
+		if actual.len() < pattern.len() || (!suffix && row.is_none() && actual.len() != pattern.len()) {
+			return Ok(false);
+		}
+
+		let split = actual.len() - pattern.len();
+		if let Some(name) = row {
+			let below = actual[..split].to_vec();
+			match substitution.rows.get(name) {
+				Some(bound) if *bound != below => return Ok(false),
+				Some(_) => {}
+				None => {
+					substitution.rows.insert(name.clone(), below);
+				}
+			}
+		}
+
+		for (pattern, actual) in pattern.iter().zip(&actual[split..]) {
+			if !self.unify(pattern, actual, substitution)? {
+				return Ok(false);
+			}
+		}
+		Ok(true)
+	}
+
+	fn unify(&mut self, pattern: &parser::TypeSignature, actual: &parser::TypeSignature, substitution: &mut Substitution) -> Result<bool, Error> {
+		use parser::TypeSignature::*;
+		match (pattern, actual) {
+			(Var(name), _) => match substitution.types.get(name).cloned() {
+				Some(Function(parameters, returns)) => match actual {
+					Quotation(id) => Ok(self.resolve_quotation(*id, parameters)? == returns),
+					_ => Ok(Function(parameters, returns) == *actual),
+				},
+				Some(bound) => Ok(bound == *actual),
+				None => {
+					if let Quotation(id) = actual {
+						let location = self.quotations[id].location.clone();
+						return Err(location
+							.error(Code::UninferredQuotation, format!("Cannot infer the type of this quotation when it's passed as `{}`!", pattern))
+							.with_help("pass it where a function type like `(int -- int)` is expected")
+							.into());
+					}
+					substitution.types.insert(name.clone(), actual.clone());
+					Ok(true)
+				}
+			},
+			(Ptr(pattern), Ptr(actual)) => self.unify(pattern, actual, substitution),
+			(Array(pattern, pattern_len), Array(actual, len)) => Ok(pattern_len == len && self.unify(pattern, actual, substitution)?),
+			(Vector(pattern), Vector(actual)) => self.unify(pattern, actual, substitution),
+			(Function(pattern_parameters, pattern_returns), Function(parameters, returns)) => {
+				Ok(self.unify_list(pattern_parameters, parameters, substitution, false)?
+					&& self.unify_list(pattern_returns, returns, substitution, false)?)
+			}
+			(Function(pattern_parameters, pattern_returns), Quotation(id)) => {
+				// the quotation is checked with the parameters it'll be given
+				let mut variables = Vec::new();
+				for ty in pattern_parameters {
+					ty.variables(&mut variables);
+				}
+				let unbound = variables.iter().find(|variable| match variable {
+					Var(name) => !substitution.types.contains_key(name),
+					Row(name) => !substitution.rows.contains_key(name),
+					_ => unreachable!(),
+				});
+				if let Some(variable) = unbound {
+					let location = self.quotations[id].location.clone();
+					return Err(location
+						.error(Code::UninferredQuotation, format!("Cannot infer the type of this quotation as `{}` is unknown!", variable))
+						.with_help("pass the values it takes before the quotation")
+						.into());
+				}
+
+				let parameters = substitution.apply_all(pattern_parameters);
+				let returns = self.resolve_quotation(*id, parameters)?;
+				self.unify_list(pattern_returns, &returns, substitution, false)
+			}
+			_ => Ok(pattern == actual),
+		}
+	}
+
+	fn resolve_function_type<'a>(
+		&self,
+		location: &Location,
+		signature: impl Iterator<Item = &'a parser::IR>,
+	) -> Result<FunctionType, Error> {
+		let mut function_type = FunctionType::new(location.clone());
+		let mut parsing_return_types = false;
+
+		for i in signature {
+			use parser::IRKind::*;
+			match &i.kind {
+				FunctionArgument(type_signature) => {
+					let types = if parsing_return_types {
+						&mut function_type.returns
+					} else {
+						&mut function_type.parameters
+					};
+					// structs are only flattened to check they exist
+					self.flatten_type(type_signature, &mut Vec::new(), &mut Vec::new(), &i.location)?;
+					types.push(type_signature.clone());
+				}
+				DashDash => parsing_return_types = true,
+				_ => unreachable!(),
+			}
+		}
+
+		Ok(function_type)
+	}
+
+	fn flatten_type(
+		&self,
+		ty: &parser::TypeSignature,
+		types: &mut Vec<parser::TypeSignature>,
+		visiting: &mut Vec<String>,
+		location: &Location,
+	) -> Result<(), Error> {
+		match ty {
+			parser::TypeSignature::Struct(name) => {
+				if self.poisoned.contains(name) {
+					return Err(Error::Poisoned);
+				}
+
+				if visiting.contains(name) {
+					return Err(location.error(Code::RecursiveStruct, format!("The struct `{}` contains itself!", name)).into());
+				}
+
+				let struct_type = self
+					.structs
+					.get(name)
+					.ok_or_else(|| location.error(Code::UnknownType, format!("Unknown struct `{}`!", name)))?;
+
+				visiting.push(name.clone());
+				for (_, field_type) in &struct_type.fields {
+					self.flatten_type(field_type, types, visiting, location)?;
+				}
+				visiting.pop();
+			}
+			parser::TypeSignature::Array(element, _) | parser::TypeSignature::Vector(element) => {
+				// the element is only flattened to catch unknown and recursive structs
+				self.flatten_type(element, &mut Vec::new(), visiting, location)?;
+				if self.width(ty) > MAX_ARRAY_CELLS {
+					return Err(location.error(Code::InvalidTypeSignature, format!(
+						"`{}` is too large! Arrays can't be larger than {} cells",
+						ty,
+						MAX_ARRAY_CELLS
+					)).into());
+				}
+				types.push(ty.clone());
+			}
+			_ => types.push(ty.clone()),
+		}
+		Ok(())
+	}
+
+	// Finds where `field` lives in the flattened cells of the struct `name`
+	fn field_layout(
+		&self,
+		name: &str,
+		field: &str,
+		location: &Location,
+	) -> Result<(FieldLayout, parser::TypeSignature), Error> {
+		let mut struct_types = Vec::new();
+		self.flatten_type(&parser::TypeSignature::Struct(name.to_string()), &mut struct_types, &mut Vec::new(), location)?;
+
+		let struct_type = self.structs.get(name).expect("Flattening checked that the struct exists");
+		let mut offset = 0;
+		for (field_name, field_type) in &struct_type.fields {
+			let mut field_types = Vec::new();
+			self.flatten_type(field_type, &mut field_types, &mut Vec::new(), location)?;
+			if field_name == field {
+				let layout = FieldLayout {
+					width: self.cells(&struct_types),
+					offset,
+					field_width: self.cells(&field_types),
+				};
+				return Ok((layout, field_type.clone()));
+			}
+			offset += self.cells(&field_types);
+		}
+
+		unreachable!("Field words are only bound for declared fields")
+	}
+
+	// The number of stack cells a value of `ty` takes up
+	fn width(&self, ty: &parser::TypeSignature) -> usize {
+		use parser::TypeSignature::*;
+		match ty {
+			Str => 2,
+			Array(element, len) => len.saturating_mul(self.width(element)),
+			Struct(name) => self.structs.get(name).map_or(1, |struct_type| {
+				struct_type.fields.iter().fold(0, |width, (_, field_type)| width.saturating_add(self.width(field_type)))
+			}),
+			_ => 1,
+		}
+	}
+
+	fn cells(&self, types: &[parser::TypeSignature]) -> usize {
+		types.iter().map(|ty| self.width(ty)).sum()
+	}
+
+	// Values that are all a single cell wide are shuffled by their own
+	// instruction, anything wider moves its cells with a general shuffle
+	fn shuffle_kind(&self, shuffled: &[parser::TypeSignature], outputs: &[usize], kind: TypedIRKind) -> TypedIRKind {
+		let widths = shuffled.iter().map(|ty| self.width(ty)).collect::<Vec<_>>();
+		if widths.iter().all(|width| *width == 1) {
+			return kind;
+		}
+
+		let mut starts = Vec::new();
+		let mut ncells = 0;
+		for width in &widths {
+			starts.push(ncells);
+			ncells += width;
+		}
+		let cell_outputs = outputs
+			.iter()
+			.flat_map(|index| starts[*index]..starts[*index] + widths[*index])
+			.collect();
+		TypedIRKind::Shuffle(ncells, cell_outputs)
+	}
+
+	fn print_kind(&self, ty: &parser::TypeSignature, location: &Location) -> Result<TypedIRKind, Error> {
+		use parser::TypeSignature::*;
+		match ty {
+			Bool => Ok(TypedIRKind::PrintBool),
+			Int | Enum(_) => Ok(TypedIRKind::PrintInt),
+			Float => Ok(TypedIRKind::PrintFloat),
+			Str => Ok(TypedIRKind::PrintStr),
+			Ptr(_) => Ok(TypedIRKind::PrintPtr),
+			Function(..) | Quotation(_) | Var(_) | Row(_) | Array(..) | Vector(_) => {
+				Err(location.error(Code::TypeMismatch, format!("Cannot `print` something of type `{}`!", ty)).into())
+			}
+			Struct(_) => unreachable!("Structs are printed field by field"),
+		}
+	}
+
+	// Structs are printed one field after the other in the order they're
+	// declared, each field is copied to the top of the stack and printed
+	fn typecheck_struct_print(&mut self, generated: &mut TypedChunk, location: Location, ty: &parser::TypeSignature) -> Result<(), Error> {
+		let mut fields = Vec::new();
+		self.flatten_type(ty, &mut fields, &mut Vec::new(), &location)?;
+		let ncells = self.cells(&fields);
+
+		let mut offset = 0;
+		for field in &fields {
+			let width = self.width(field);
+			let kind = self.print_kind(field, &location)?;
+			let cells = (0..ncells).chain(offset..offset + width).collect();
+			generated.push(TypedIR {
+				kind: TypedIRKind::Shuffle(ncells, cells),
+				location: location.clone(),
+			});
+			generated.push(TypedIR {
+				kind,
+				location: location.clone(),
+			});
+			offset += width;
+		}
+
+		generated.push(TypedIR {
+			kind: TypedIRKind::Shuffle(ncells, Vec::new()),
+			location,
+		});
+		Ok(())
+	}
+
+	// Two structs are equal when all of their fields are
+	fn typecheck_struct_eq(&mut self, generated: &mut TypedChunk, location: Location, negate: bool) -> Result<(), Error> {
+		let b = self.type_stack().pop().expect("The struct on top was matched before");
+		let mut fields = Vec::new();
+		self.flatten_type(&b, &mut fields, &mut Vec::new(), &location)?;
+
+		let verb = if negate { "non-equality" } else { "equality" };
+		self.pack_stack(std::slice::from_ref(&b))?;
+		if self.type_stack().last() == Some(&b) {
+			self.type_stack().pop();
+		} else {
+			let a = match self.type_stack().last() {
+				Some(a) => a.clone(),
+				None => return Err(location.error(Code::StackUnderflow, format!("Cannot check nonexistant data for {}!", verb)).into()),
+			};
+			return Err(location.error(Code::TypeMismatch, format!(
+				"Operands of {} operation have different types! `{}` vs. `{}`!",
+				verb, a, b
+			)).into());
+		}
+
+		// the fields are compared pairwise and and-ed into a flag above both structs
+		let ncells = self.cells(&fields);
+		let total = 2 * ncells + 1;
+		let mut kinds = vec![TypedIRKind::PushBool(true)];
+		let mut offset = 0;
+		for field in &fields {
+			let width = self.width(field);
+			let eq = match field {
+				parser::TypeSignature::Array(..) | parser::TypeSignature::Vector(_) => {
+					return Err(location.error(Code::TypeMismatch, format!("Cannot check values of type `{}` for {}!", field, verb)).into());
+				}
+				parser::TypeSignature::Float => TypedIRKind::FloatEq,
+				parser::TypeSignature::Str => TypedIRKind::StrEq,
+				_ => TypedIRKind::Eq,
+			};
+			let cells = (0..total).chain(offset..offset + width).chain(ncells + offset..ncells + offset + width).collect();
+			kinds.extend([TypedIRKind::Shuffle(total, cells), eq, TypedIRKind::And]);
+			offset += width;
+		}
+		kinds.push(TypedIRKind::Shuffle(total, vec![2 * ncells]));
+		if negate {
+			kinds.push(TypedIRKind::Not);
+		}
+
+		for kind in kinds {
+			generated.push(TypedIR {
+				kind,
+				location: location.clone(),
+			});
+		}
+		self.type_stack().push(parser::TypeSignature::Bool);
+		Ok(())
+	}
+
+	// The number of cells an element of `ty` takes up behind a pointer
+	fn pointee_width(&self, ty: &parser::TypeSignature, word: &str, location: &Location) -> Result<usize, Error> {
+		if ty.has_variables() {
+			return Err(location.error(Code::TypeMismatch, format!("Cannot `{}` with the generic type `{}`!", word, ty)).into());
+		}
+
+		let mut types = Vec::new();
+		self.flatten_type(ty, &mut types, &mut Vec::new(), location)?;
+		Ok(self.cells(&types))
+	}
+
+	// Function values always point at some code so there's no zero for them
+	fn has_zero_value(&self, ty: &parser::TypeSignature) -> bool {
+		use parser::TypeSignature::*;
+		match ty {
+			// vectors are handles to storage made by `vec.new`
+			Function(..) | Quotation(_) | Vector(_) => false,
+			Array(element, _) => self.has_zero_value(element),
+			Struct(name) => self.structs.get(name).is_some_and(|struct_type| {
+				struct_type.fields.iter().all(|(_, field_type)| self.has_zero_value(field_type))
+			}),
+			_ => true,
+		}
+	}
+
+	// `get` takes an array and leaves it below the element or a pointer to an
+	// array and loads the element through it
+	fn typecheck_get(&mut self, generated: &mut TypedChunk, location: Location) -> Result<(), Error> {
+		let type_stack_len = self.type_stack().len();
+		let (element, len, through_ptr) = match self.type_stack().get(type_stack_len.wrapping_sub(2)..) {
+			Some([parser::TypeSignature::Array(element, len), parser::TypeSignature::Int]) => (element.as_ref().clone(), *len, false),
+			Some([parser::TypeSignature::Ptr(array), parser::TypeSignature::Int]) => match array.as_ref() {
+				parser::TypeSignature::Array(element, len) => (element.as_ref().clone(), *len, true),
+				_ => return Err(self.array_mismatch("get", "an array or a pointer to one and an `int` index", location)),
+			},
+			_ => return Err(self.array_mismatch("get", "an array or a pointer to one and an `int` index", location)),
+		};
+
+		let width = self.width(&element);
+		self.type_stack().pop();
+		if through_ptr {
+			self.type_stack().pop();
+			for kind in [TypedIRKind::CheckIndex(len), TypedIRKind::Offset(width), TypedIRKind::Load(width)] {
+				generated.push(TypedIR {
+					kind,
+					location: location.clone(),
+				});
+			}
+		} else {
+			generated.push(TypedIR {
+				kind: TypedIRKind::ArrayGet(len, width),
+				location,
+			});
+		}
+		self.type_stack().push(element);
+		Ok(())
+	}
+
+	// `set` takes the new element below the array or pointer to one and its
+	// index, like `<-` takes the value below the pointer
+	fn typecheck_set(&mut self, generated: &mut TypedChunk, location: Location) -> Result<(), Error> {
+		let type_stack_len = self.type_stack().len();
+		let (array, through_ptr) = match self.type_stack().get(type_stack_len.wrapping_sub(2)..) {
+			Some([array @ parser::TypeSignature::Array(..), parser::TypeSignature::Int]) => (array.clone(), false),
+			Some([parser::TypeSignature::Ptr(array), parser::TypeSignature::Int]) if matches!(array.as_ref(), parser::TypeSignature::Array(..)) => {
+				(array.as_ref().clone(), true)
+			}
+			_ => return Err(self.array_mismatch("set", "an element, an array or a pointer to one and an `int` index", location)),
+		};
+		let (element, len) = match &array {
+			parser::TypeSignature::Array(element, len) => (element.as_ref().clone(), *len),
+			_ => unreachable!(),
+		};
+
+		let width = self.width(&element);
+		let container = self.type_stack().split_off(type_stack_len - 2);
+		if !self.stack_ends_with(std::slice::from_ref(&element))? {
+			self.type_stack().extend(container);
+			return Err(location.error(Code::TypeMismatch, format!(
+				"`set` expects a `{}` below the `{}`! Stack: {}",
+				element,
+				array,
+				parser::DisplayVec(self.type_stack())
+			)).into());
+		}
+
+		self.type_stack().pop();
+		if through_ptr {
+			// the element is stored through a pointer to it
+			for kind in [TypedIRKind::CheckIndex(len), TypedIRKind::Offset(width), TypedIRKind::Assign(width)] {
+				generated.push(TypedIR {
+					kind,
+					location: location.clone(),
+				});
+			}
+		} else {
+			self.type_stack().push(array);
+			generated.push(TypedIR {
+				kind: TypedIRKind::ArraySet(len, width),
+				location,
+			});
+		}
+		Ok(())
+	}
+
+	// Finds the vector below the index for indexed words or on top otherwise,
+	// the rest of the stack is checked against the element type after
+	fn vec_element(
+		&mut self,
+		word: &str,
+		indexed: bool,
+		location: &Location,
+	) -> Result<(parser::TypeSignature, parser::TypeSignature, usize), Error> {
+		let type_stack_len = self.type_stack().len();
+		let vec = match (indexed, self.type_stack().get(type_stack_len.wrapping_sub(2)..)) {
+			(true, Some([vec @ parser::TypeSignature::Vector(_), parser::TypeSignature::Int])) => vec.clone(),
+			(false, _) => match self.type_stack().last() {
+				Some(vec @ parser::TypeSignature::Vector(_)) => vec.clone(),
+				_ => return Err(self.array_mismatch(word, "a vector", location.clone())),
+			},
+			_ => return Err(self.array_mismatch(word, "a vector and an `int` index", location.clone())),
+		};
+		let element = match &vec {
+			parser::TypeSignature::Vector(element) => element.as_ref().clone(),
+			_ => unreachable!(),
+		};
+
+		let width = self.pointee_width(&element, word, location)?;
+		Ok((vec, element, width))
+	}
+
+	fn array_mismatch(&mut self, word: &str, expected: &str, location: Location) -> Error {
+		let code = if self.type_stack().len() < 2 { Code::StackUnderflow } else { Code::TypeMismatch };
+		location.error(code, format!(
+			"`{}` expects {} on top of the stack! Stack: {}",
+			word,
+			expected,
+			parser::DisplayVec(self.type_stack())
+		)).into()
+	}
+
+	fn check_struct_recursion(&mut self, names: &[String]) {
+		for name in names {
+			let struct_type = match self.structs.get(name) {
+				Some(struct_type) => struct_type,
+				None => continue,
+			};
+
+			let result = self.flatten_type(
+				&parser::TypeSignature::Struct(name.clone()),
+				&mut Vec::new(),
+				&mut Vec::new(),
+				&struct_type.location,
+			);
+			if let Err(error) = result {
+				self.report(error);
+				self.poisoned.insert(name.clone());
+			}
+		}
+	}
+
+	fn typecheck_variable(
+		&mut self,
+		generated: &mut TypedChunk,
+		name: String,
+		struct_type: Option<parser::TypeSignature>,
+		location: Location,
+		ir: &mut IRIter,
+	) -> Result<(), Error> {
 		self.type_stacks.push(Vec::new());
 
-		generated.push(TypedIR { kind: TypedIRKind::Var });
+		generated.push(TypedIR {
+			kind: TypedIRKind::Var,
+			location: location.clone(),
+		});
 
 		while let Some(i) = ir.next() {
 			use parser::IRKind::*;
 			match i.kind {
 				End => break,
-				_ => self.typecheck_expression(generated, i.kind, ir)?,
+				_ => self.typecheck_expression(generated, i, ir)?,
 			}
 		}
 
-		if self.type_stack().len() != 1 {
-			return Err("Body of `var` expression does not evaluate to a single value!".to_string());
+		let var_type = match struct_type {
+			Some(struct_type) => struct_type,
+			None => match self.type_stack().as_slice() {
+				[var_type] => var_type.clone(),
+				_ => self.infer_var_struct(&location)?,
+			},
+		};
+		self.pack_stack(std::slice::from_ref(&var_type))?;
+		if *self.type_stack() != [var_type.clone()] {
+			return Err(location.error(Code::InvalidBody, format!(
+				"Body of `var` expression does not evaluate to a `{}`! Found: {}",
+				var_type,
+				parser::DisplayVec(self.type_stack())
+			)).into());
 		}
-
-		let var_type = self.type_stack().pop().expect("We just checked its length");
+		let width = self.width(&var_type);
 		let var_index = self.add_variable(name, var_type);
 
-		generated.push(TypedIR { kind: TypedIRKind::MakeVar(var_index) });
+		generated.push(TypedIR {
+			kind: TypedIRKind::MakeVar(var_index, width),
+			location,
+		});
 
 		self.type_stacks.pop().expect("We push a new stack for the var");
 
 		Ok(())
 	}
 
-	fn typecheck_if(&mut self, generated: &mut TypedChunk, ir: &mut IRIter) -> Result<(), String> {
+	fn typecheck_if(&mut self, generated: &mut TypedChunk, location: Location, ir: &mut IRIter) -> Result<(), Error> {
 		let type_stack_before_if = self.type_stack().clone();
 		let mut type_stack_before_branch = None::<Vec<parser::TypeSignature>>;
+		let mut type_stack_after_condition = type_stack_before_if.clone();
+		let mut has_else = false;
 
 		generated.push(TypedIR {
 			kind: TypedIRKind::If,
+			location: location.clone(),
 		});
 
 		while let Some(i) = ir.next() {
@@ -655,140 +1950,704 @@ impl Typer {
 					let top = self
 						.type_stack()
 						.pop()
-						.ok_or("No value on stack for condition of `if` expression!".to_string())?;
+						.ok_or_else(|| i.location.error(Code::StackUnderflow, "No value on stack for condition of `if` expression!"))?;
 					if top != parser::TypeSignature::Bool {
-						return Err(format!(
+						return Err(i.location.error(Code::TypeMismatch, format!(
 							"Type on stack for condition of `if` expression should be {} but found {}",
 							parser::TypeSignature::Bool,
 							top,
-						));
+						)).into());
 					}
+					type_stack_after_condition = self.type_stack().clone();
 					generated.push(TypedIR {
 						kind: TypedIRKind::Then,
+						location: i.location.clone(),
 					});
 				}
 				Elif => {
-					if let Some(type_stack_before_branch) = &type_stack_before_branch {
-						if self.type_stack() != type_stack_before_branch {
-							return Err(format!(
-								"A branch of `if` expression returns different types to other branches! Expected: {} vs. Actual: {}", 
-								parser::DisplayVec(type_stack_before_branch), 
-								parser::DisplayVec(self.type_stack()),
-							));
-						}
-					} else {
-						type_stack_before_branch = Some(self.type_stack().clone());
+					// a branch that leaves the block doesn't decide the types
+					if self.diverged.take().is_none() {
+						self.check_branch(&mut type_stack_before_branch, &type_stack_before_if, &location, &i.location)?;
 					}
 
+					// a failed condition leaves the stack as it was after that
+					// condition so the next branch starts from there
+					*self.type_stack() = type_stack_after_condition.clone();
+
 					generated.push(TypedIR {
 						kind: TypedIRKind::Elif,
+						location: i.location.clone(),
 					});
 				}
 				Else => {
-					if let Some(type_stack_before_branch) = &type_stack_before_branch {
-						if self.type_stack() != type_stack_before_branch {
-							return Err(format!(
-								"A branch of `if` expression returns different types to other branches! Expected: {} vs. Actual: {}", 
-								parser::DisplayVec(type_stack_before_branch),
-								parser::DisplayVec(self.type_stack()),
-							));
-						}
-					} else {
-						type_stack_before_branch = Some(self.type_stack().clone());
+					// a branch that leaves the block doesn't decide the types
+					if self.diverged.take().is_none() {
+						self.check_branch(&mut type_stack_before_branch, &type_stack_before_if, &location, &i.location)?;
 					}
 
+					// a failed condition leaves the stack as it was after that
+					// condition so the next branch starts from there
+					*self.type_stack() = type_stack_after_condition.clone();
+
+					has_else = true;
 					generated.push(TypedIR {
 						kind: TypedIRKind::Else,
+						location: i.location.clone(),
 					});
 				}
 				End => {
-					if let Some(type_stack_before_branch) = &type_stack_before_branch {
-						if self.type_stack() != type_stack_before_branch {
-							return Err(format!(
-								"A branch of `if` expression returns different types to other branches! Expected: {} vs. Actual: {}", 
-								parser::DisplayVec(type_stack_before_branch),
-								parser::DisplayVec(self.type_stack()),
-							));
+					if let Some(exit) = self.diverged.take() {
+						match &type_stack_before_branch {
+							Some(type_stack_before_branch) => *self.type_stack() = type_stack_before_branch.clone(),
+							// every branch leaves the block
+							None if has_else => self.diverged = Some(exit),
+							None => *self.type_stack() = type_stack_after_condition.clone(),
 						}
+					} else if type_stack_before_branch.is_some() || has_else {
+						self.check_branch(&mut type_stack_before_branch, &type_stack_before_if, &location, &i.location)?;
 					} else {
-						if *self.type_stack() != type_stack_before_if {
-							return Err(format!(
-								"`if` expression ends with altered type stack! Before: {} vs. After: {}",
-								parser::DisplayVec(&type_stack_before_if),
-								parser::DisplayVec(self.type_stack()),
-							));
+						if !self.stack_matches(&type_stack_after_condition)? {
+							return Err(i
+								.location
+								.error(Code::BranchMismatch, format!(
+									"`if` expression ends with altered type stack! Before: {} vs. After: {}",
+									parser::DisplayVec(&type_stack_after_condition),
+									parser::DisplayVec(self.type_stack()),
+								))
+								.with_help("add an `else` branch that leaves the stack the same way").into());
 						}
 					}
 
 					generated.push(TypedIR {
 						kind: TypedIRKind::End,
+						location: i.location.clone(),
 					});
 
 					break;
 				}
-				_ => self.typecheck_expression(generated, i.kind, ir)?,
+				_ => self.typecheck_expression(generated, i, ir)?,
 			}
 		}
 
 		Ok(())
 	}
 
-	fn typecheck_while(&mut self, generated: &mut TypedChunk, ir: &mut IRIter) -> Result<(), String> {
+	// Checks a branch of an `if` leaves the same types as the branches before it
+	fn check_branch(
+		&mut self,
+		type_stack_before_branch: &mut Option<Vec<parser::TypeSignature>>,
+		type_stack_before_if: &[parser::TypeSignature],
+		location: &Location,
+		branch_end: &Location,
+	) -> Result<(), Error> {
+		match type_stack_before_branch {
+			Some(type_stack_before_branch) => {
+				if !self.merge_stacks(type_stack_before_branch)? {
+					return Err(branch_end
+						.error(Code::BranchMismatch, format!(
+							"A branch of `if` expression returns different types to other branches! Expected: {} vs. Actual: {}",
+							parser::DisplayVec(type_stack_before_branch),
+							parser::DisplayVec(self.type_stack()),
+						))
+						.with_note_at(location.clone(), format!(
+							"stack before branch was {}",
+							parser::DisplayVec(type_stack_before_if),
+						)).into());
+				}
+			}
+			None => *type_stack_before_branch = Some(self.type_stack().clone()),
+		}
+		Ok(())
+	}
+
+	fn typecheck_while(&mut self, generated: &mut TypedChunk, location: Location, ir: &mut IRIter) -> Result<(), Error> {
 		let type_stack_before_loop = self.type_stack().clone();
+		self.loops.push(LoopInfo::new(type_stack_before_loop.clone(), self.bind_stack.len()));
 
 		generated.push(TypedIR {
 			kind: TypedIRKind::While,
+			location: location.clone(),
 		});
 
+		let mut end_location = location;
+
 		while let Some(i) = ir.next() {
 			use parser::IRKind::*;
 			match i.kind {
 				End => {
 					generated.push(TypedIR {
 						kind: TypedIRKind::End,
+						location: i.location.clone(),
 					});
+					end_location = i.location;
 					break;
 				}
 				Do => {
 					let condition = self
 						.type_stack()
 						.pop()
-						.ok_or("`while` loop requires a condition but no data is present!".to_string())?;
+						.ok_or_else(|| i.location.error(Code::StackUnderflow, "`while` loop requires a condition but no data is present!"))?;
 					if condition != parser::TypeSignature::Bool {
-						return Err(format!(
+						return Err(i.location.error(Code::TypeMismatch, format!(
 							"`while` loop requires its condition value to be `bool` but found {}",
 							condition
-						));
+						)).into());
 					}
 
 					generated.push(TypedIR {
 						kind: TypedIRKind::Do,
+						location: i.location.clone(),
 					});
 				}
-				_ => self.typecheck_expression(generated, i.kind, ir)?,
+				_ => self.typecheck_expression(generated, i, ir)?,
 			}
 		}
 
-		if type_stack_before_loop != *self.type_stack() {
-			return Err(format!(
-				"`while` loop ends with altered type stack! Expected: {} vs. Actual: {}", 
+		// `break` and `continue` check the stack where they leave the body
+		self.loops.pop();
+		if self.diverged.take().is_some() {
+			*self.type_stack() = type_stack_before_loop.clone();
+		}
+
+		if !self.stack_matches(&type_stack_before_loop)? {
+			return Err(end_location.error(Code::LoopAltersStack, format!(
+				"`while` loop ends with altered type stack! Expected: {} vs. Actual: {}",
 				parser::DisplayVec(&type_stack_before_loop),
 				parser::DisplayVec(self.type_stack()),
-			));
+			)).into());
+		}
+
+		Ok(())
+	}
+
+	fn typecheck_for(&mut self, generated: &mut TypedChunk, id: usize, location: Location, ir: &mut IRIter) -> Result<(), Error> {
+		let range = [parser::TypeSignature::Int, parser::TypeSignature::Int];
+		if !self.type_stack().ends_with(&range) {
+			return Err(location.error(Code::TypeMismatch, format!(
+				"`for` loop requires the start and end of its range as {} but found {}",
+				parser::DisplayVec(&range),
+				parser::DisplayVec(self.type_stack()),
+			)).into());
+		}
+
+		let type_stack_len = self.type_stack().len();
+		self.type_stack().truncate(type_stack_len - range.len());
+		debug_assert_eq!(id, self.bind_stack.len(), "The parser binds the index at the top of the bind stack");
+		self.bind_stack.extend(range);
+
+		let type_stack_before_loop = self.type_stack().clone();
+		self.loops.push(LoopInfo::new(type_stack_before_loop.clone(), self.bind_stack.len()));
+		generated.push(TypedIR {
+			kind: TypedIRKind::For(self.cells(&self.bind_stack[..id])),
+			location: location.clone(),
+		});
+
+		let end_location = self.typecheck_loop_body(generated, location, ir)?;
+		self.loops.pop();
+		self.bind_stack.truncate(id);
+		if self.diverged.take().is_some() {
+			*self.type_stack() = type_stack_before_loop.clone();
+		}
+
+		if !self.stack_matches(&type_stack_before_loop)? {
+			return Err(end_location.error(Code::LoopAltersStack, format!(
+				"`for` loop ends with altered type stack! Expected: {} vs. Actual: {}",
+				parser::DisplayVec(&type_stack_before_loop),
+				parser::DisplayVec(self.type_stack()),
+			)).into());
+		}
+
+		Ok(())
+	}
+
+	// A `loop` can only be left with `break` or `return`
+	fn typecheck_infinite_loop(&mut self, generated: &mut TypedChunk, location: Location, ir: &mut IRIter) -> Result<(), Error> {
+		let type_stack_before_loop = self.type_stack().clone();
+		self.loops.push(LoopInfo::new(type_stack_before_loop.clone(), self.bind_stack.len()));
+		generated.push(TypedIR {
+			kind: TypedIRKind::Loop,
+			location: location.clone(),
+		});
+
+		let end_location = self.typecheck_loop_body(generated, location, ir)?;
+		let current_loop = self.loops.pop().expect("We pushed it before the body");
+		if self.diverged.take().is_some() {
+			*self.type_stack() = type_stack_before_loop.clone();
+		}
+
+		if !self.stack_matches(&type_stack_before_loop)? {
+			return Err(end_location.error(Code::LoopAltersStack, format!(
+				"`loop` ends with altered type stack! Expected: {} vs. Actual: {}",
+				parser::DisplayVec(&type_stack_before_loop),
+				parser::DisplayVec(self.type_stack()),
+			)).into());
+		}
+
+		// without a `break` nothing after the loop is ever reached
+		if !current_loop.broken {
+			self.diverged = Some(end_location);
+		}
+
+		Ok(())
+	}
+
+	// Checks the body of a `for` or `loop` up to its `end`, returning where it ends
+	fn typecheck_loop_body(&mut self, generated: &mut TypedChunk, location: Location, ir: &mut IRIter) -> Result<Location, Error> {
+		while let Some(i) = ir.next() {
+			match i.kind {
+				parser::IRKind::End => {
+					generated.push(TypedIR {
+						kind: TypedIRKind::End,
+						location: i.location.clone(),
+					});
+					return Ok(i.location);
+				}
+				_ => self.typecheck_expression(generated, i, ir)?,
+			}
+		}
+		Ok(location)
+	}
+
+	fn typecheck_match(&mut self, generated: &mut TypedChunk, location: Location, ir: &mut IRIter) -> Result<(), Error> {
+		// the arms are only known once they've all been checked
+		let match_index = generated.len();
+		generated.push(TypedIR {
+			kind: TypedIRKind::Match(Vec::new()),
+			location: location.clone(),
+		});
+
+		let mut next = loop {
+			let i = ir.next().expect("The parser closes every `match` with an `end`");
+			match i.kind {
+				parser::IRKind::Case(_) | parser::IRKind::CaseDefault | parser::IRKind::End => break i,
+				_ => self.typecheck_expression(generated, i, ir)?,
+			}
+		};
+
+		let matched = self
+			.type_stack()
+			.pop()
+			.ok_or_else(|| location.error(Code::StackUnderflow, "No value on stack for `match` to match on!"))?;
+		if !matches!(matched, parser::TypeSignature::Int | parser::TypeSignature::Enum(_)) {
+			return Err(location.error(Code::TypeMismatch, format!(
+				"Cannot `match` on something of type `{}`! Only `int` and enum values can be matched on",
+				matched
+			)).into());
+		}
+
+		let type_stack_before_arms = self.type_stack().clone();
+		let mut type_stack_after_arms = None::<Vec<parser::TypeSignature>>;
+		let mut arms = Vec::new();
+		let mut seen = HashMap::<i64, Location>::new();
+		let mut default = None::<Location>;
+		let mut exits = Vec::new();
+
+		loop {
+			match next.kind {
+				parser::IRKind::Case(constants) => {
+					let mut values = Vec::new();
+					for constant in constants {
+						let (ty, value) = match constant {
+							parser::Constant::Int(value) => (parser::TypeSignature::Int, value),
+							parser::Constant::Enum(name, value) => (parser::TypeSignature::Enum(name), value),
+							_ => unreachable!("The parser only allows `int` and enum values in a `case`"),
+						};
+						if ty != matched {
+							let diagnostic = next.location.error(Code::TypeMismatch, format!(
+								"`case` value of type `{}` can't match a value of type `{}`!",
+								ty, matched
+							));
+							return Err(with_conversion_help(diagnostic, &ty, &matched).into());
+						}
+						if let Some(previous) = seen.get(&value) {
+							return Err(next
+								.location
+								.error(Code::DuplicateCase, "Value is already matched by an earlier `case` arm!")
+								.with_note_at(previous.clone(), "first matched here")
+								.into());
+						}
+
+						seen.insert(value, next.location.clone());
+						values.push(value);
+					}
+
+					arms.push(values);
+					generated.push(TypedIR {
+						kind: TypedIRKind::Case,
+						location: next.location.clone(),
+					});
+				}
+				parser::IRKind::CaseDefault => {
+					if let Some(previous) = &default {
+						return Err(next
+							.location
+							.error(Code::DuplicateCase, "`match` has more than one `case _` arm!")
+							.with_note_at(previous.clone(), "first `case _` arm is here")
+							.into());
+					}
+
+					default = Some(next.location.clone());
+					generated.push(TypedIR {
+						kind: TypedIRKind::CaseDefault,
+						location: next.location.clone(),
+					});
+				}
+				parser::IRKind::End => break,
+				_ => return Err(next.location.error(Code::UnexpectedKeyword, "Expected a `case` arm or the `end` of the `match`!").into()),
+			}
+
+			// every arm starts from the stack as it was after the matched value
+			*self.type_stack() = type_stack_before_arms.clone();
+			loop {
+				let i = ir.next().expect("The parser closes every `case` with an `end`");
+				match i.kind {
+					parser::IRKind::End => {
+						if let Some(exit) = self.diverged.take() {
+							exits.push(exit);
+						} else if let Some(type_stack_after_arms) = &mut type_stack_after_arms {
+							if !self.merge_stacks(type_stack_after_arms)? {
+								return Err(i
+									.location
+									.error(Code::BranchMismatch, format!(
+										"A `case` arm of `match` returns different types to other arms! Expected: {} vs. Actual: {}",
+										parser::DisplayVec(type_stack_after_arms),
+										parser::DisplayVec(self.type_stack()),
+									))
+									.with_note_at(location.clone(), format!(
+										"stack before the arms was {}",
+										parser::DisplayVec(&type_stack_before_arms),
+									)).into());
+							}
+						} else {
+							type_stack_after_arms = Some(self.type_stack().clone());
+						}
+
+						generated.push(TypedIR {
+							kind: TypedIRKind::End,
+							location: i.location,
+						});
+						break;
+					}
+					_ => self.typecheck_expression(generated, i, ir)?,
+				}
+			}
+
+			next = ir.next().expect("The parser closes every `match` with an `end`");
+		}
+
+		let missing = match &matched {
+			parser::TypeSignature::Enum(name) if default.is_none() => self
+				.enums
+				.get(name)
+				.map(|variants| {
+					variants
+						.iter()
+						.enumerate()
+						.filter(|(value, _)| !seen.contains_key(&(*value as i64)))
+						.map(|(_, variant)| format!("`{}.{}`", name, variant))
+						.collect::<Vec<_>>()
+				})
+				.unwrap_or_default(),
+			_ => Vec::new(),
+		};
+		if !missing.is_empty() {
+			self.diagnostics.push(location
+				.error(Code::NonExhaustiveMatch, format!("`match` doesn't handle {}", missing.join(", ")))
+				.with_help("add `case` arms for them or a `case _` arm"));
+		}
+
+		// a value no arm matches skips the `match` entirely
+		let exhaustive = default.is_some() || (matches!(matched, parser::TypeSignature::Enum(_)) && missing.is_empty());
+		*self.type_stack() = type_stack_after_arms.unwrap_or_else(|| type_stack_before_arms.clone());
+		if !exhaustive && !self.stack_matches(&type_stack_before_arms)? {
+			return Err(next
+				.location
+				.error(Code::BranchMismatch, format!(
+					"`match` without a `case _` arm ends with altered type stack! Before: {} vs. After: {}",
+					parser::DisplayVec(&type_stack_before_arms),
+					parser::DisplayVec(self.type_stack()),
+				))
+				.with_help("add a `case _` arm that leaves the stack the same way").into());
+		}
+		if exhaustive && exits.len() == arms.len() + default.is_some() as usize {
+			self.diverged = exits.pop();
+		}
+
+		generated[match_index].kind = TypedIRKind::Match(arms);
+		generated.push(TypedIR {
+			kind: TypedIRKind::End,
+			location: next.location,
+		});
+
+		Ok(())
+	}
+
+	// Checks the top of the stack against `expected`, inferring the types of
+	// quotations passed where a function is expected
+	fn stack_ends_with(&mut self, expected: &[parser::TypeSignature]) -> Result<bool, Error> {
+		self.pack_stack(expected)?;
+		let stack = self.type_stack().clone();
+		if stack.len() < expected.len() {
+			return Ok(false);
+		}
+
+		for (actual, expected) in stack[stack.len() - expected.len()..].iter().zip(expected) {
+			match actual {
+				parser::TypeSignature::Quotation(id) if matches!(expected, parser::TypeSignature::Function(..)) => {
+					self.check_quotation(*id, expected)?;
+				}
+				_ if actual != expected => return Ok(false),
+				_ => {}
+			}
 		}
 
+		Ok(true)
+	}
+
+	fn check_quotation(&mut self, id: usize, expected: &parser::TypeSignature) -> Result<(), Error> {
+		let (parameters, returns) = match expected {
+			parser::TypeSignature::Function(parameters, returns) => (parameters, returns),
+			_ => unreachable!("Quotations are only checked against function types"),
+		};
+
+		let actual_returns = self.resolve_quotation(id, parameters.clone())?;
+		if actual_returns != *returns {
+			let location = self.quotations[&id].location.clone();
+			return Err(location.error(Code::TypeMismatch, format!(
+				"Quotation leaves {} on the stack where `{}` is expected!",
+				parser::DisplayVec(&actual_returns),
+				expected
+			)).into());
+		}
 		Ok(())
 	}
 
-	fn typecheck_struct(&mut self, name: String, ir: &mut IRIter) -> Result<(), String> {
-		let mut struct_type = StructType::new();
+	fn stack_matches(&mut self, expected: &[parser::TypeSignature]) -> Result<bool, Error> {
+		self.merge_stacks(&mut expected.to_vec())
+	}
+
+	// Two stacks agree when one has a struct where the other has its fields,
+	// which are then taken as the struct on either side
+	fn merge_stacks(&mut self, other: &mut Vec<parser::TypeSignature>) -> Result<bool, Error> {
+		self.pack_stack(other)?;
+		let stack = self.type_stack().clone();
+		let quotations = self.pack_structs(other, &stack);
+		for (id, expected) in quotations {
+			self.check_quotation(id, &expected)?;
+		}
+		Ok(*self.type_stack() == *other)
+	}
+
+	fn pack_stack(&mut self, expected: &[parser::TypeSignature]) -> Result<(), Error> {
+		let mut stack = std::mem::take(self.type_stack());
+		let quotations = self.pack_structs(&mut stack, expected);
+		*self.type_stack() = stack;
+		for (id, expected) in quotations {
+			self.check_quotation(id, &expected)?;
+		}
+		Ok(())
+	}
+
+	// A struct is made by pushing its fields, so fields on the stack where
+	// `expected` has a struct are taken as that struct. Only the types change
+	// since the fields' cells are already laid out like the struct's. Returns
+	// the quotations given for function fields, which still have to be checked.
+	fn pack_structs(&self, stack: &mut Vec<parser::TypeSignature>, expected: &[parser::TypeSignature]) -> Vec<(usize, parser::TypeSignature)> {
+		let mut quotations = Vec::new();
+		let mut packed = Vec::new();
+		let mut end = stack.len();
+		for ty in expected.iter().rev() {
+			match ty {
+				parser::TypeSignature::Struct(_) if stack[..end].last() != Some(ty) => {
+					end = match self.fields_start(stack, end, ty, &mut quotations) {
+						Some(start) => start,
+						// the caller reports the mismatch
+						None => return Vec::new(),
+					};
+					packed.push(ty.clone());
+				}
+				parser::TypeSignature::Row(_) => break,
+				_ if end == 0 => break,
+				// anything but a struct is checked by the caller
+				_ => {
+					end -= 1;
+					packed.push(stack[end].clone());
+				}
+			}
+		}
+
+		stack.truncate(end);
+		stack.extend(packed.into_iter().rev());
+		quotations
+	}
+
+	// Where a value of `ty` ending at `end` starts on the stack, the value can
+	// be whole or made of its fields
+	fn fields_start(
+		&self,
+		stack: &[parser::TypeSignature],
+		end: usize,
+		ty: &parser::TypeSignature,
+		quotations: &mut Vec<(usize, parser::TypeSignature)>,
+	) -> Option<usize> {
+		match (stack[..end].last(), ty) {
+			(Some(actual), _) if actual == ty => Some(end - 1),
+			(Some(parser::TypeSignature::Quotation(id)), parser::TypeSignature::Function(..)) => {
+				quotations.push((*id, ty.clone()));
+				Some(end - 1)
+			}
+			(_, parser::TypeSignature::Struct(name)) if !self.poisoned.contains(name) => {
+				let struct_type = self.structs.get(name)?;
+				struct_type
+					.fields
+					.iter()
+					.rev()
+					.try_fold(end, |end, (_, field_type)| self.fields_start(stack, end, field_type, quotations))
+			}
+			_ => None,
+		}
+	}
+
+	// A `var` body that leaves the fields of a struct holds that struct, as
+	// long as they aren't the fields of another struct too
+	fn infer_var_struct(&self, location: &Location) -> Result<parser::TypeSignature, Error> {
+		let stack = self.type_stacks.last().expect("We push a new stack for the var");
+		let mut names = self.structs.keys().filter(|name| !self.poisoned.contains(*name)).collect::<Vec<_>>();
+		names.sort();
+		let matching = names
+			.into_iter()
+			.map(|name| parser::TypeSignature::Struct(name.clone()))
+			.filter(|ty| {
+				let mut packed = stack.clone();
+				self.pack_structs(&mut packed, std::slice::from_ref(ty));
+				packed == [ty.clone()]
+			})
+			.collect::<Vec<_>>();
+
+		match matching.as_slice() {
+			[ty] => Ok(ty.clone()),
+			[] => Err(location
+				.error(Code::InvalidBody, "Body of `var` expression does not evaluate to a single value!")
+				.with_help("a `var` can hold the fields of a struct, like `var p \"Tanjiro\" \"Kamado\";`")
+				.into()),
+			[first, ..] => Err(location
+				.error(Code::InvalidBody, format!(
+					"Body of `var` expression evaluates to the fields of several structs: {}!",
+					matching.iter().map(|ty| format!("`{}`", ty)).collect::<Vec<_>>().join(", ")
+				))
+				.with_help(format!("name the struct before the body, like `var p {} ...;`", first))
+				.into()),
+		}
+	}
+
+	// A quotation called directly takes as many values as its body needs from
+	// the stack it's called on
+	fn infer_quotation(&mut self, id: usize) -> Result<(Vec<parser::TypeSignature>, Vec<parser::TypeSignature>), Error> {
+		let stack = self.type_stack().clone();
+		for depth in 0..=stack.len() {
+			let quotations = self.quotations.clone();
+			let instances = self.instances.clone();
+			let lifted = self.lifted.len();
+			let diagnostics = self.diagnostics.len();
+			let instance_failures = self.instance_failures.len();
+
+			let parameters = stack[stack.len() - depth..].to_vec();
+			match self.resolve_quotation(id, parameters.clone()) {
+				Ok(returns) => return Ok((parameters, returns)),
+				Err(Error::Diagnostic(diagnostic)) if diagnostic.code == Code::StackUnderflow && depth < stack.len() => {
+					self.quotations = quotations;
+					self.instances = instances;
+					self.lifted.truncate(lifted);
+					self.diagnostics.truncate(diagnostics);
+					self.instance_failures.truncate(instance_failures);
+				}
+				Err(error) => return Err(error),
+			}
+		}
+
+		unreachable!("The last attempt returns its error")
+	}
 
+	// Checks the body of a quotation as a function taking `parameters` and
+	// lifts it out so it can be compiled
+	fn resolve_quotation(&mut self, id: usize, parameters: Vec<parser::TypeSignature>) -> Result<Vec<parser::TypeSignature>, Error> {
+		let info = self.quotations.get(&id).expect("Quotations are created before they're used").clone();
+		let body = self.quotation_bodies.get(&id).expect("Every quotation has a body").clone();
+
+		let enclosing_stacks = std::mem::replace(&mut self.type_stacks, vec![parameters.clone()]);
+		let enclosing_binds = std::mem::take(&mut self.bind_stack);
+		let enclosing_loops = std::mem::take(&mut self.loops);
+		self.captures.push(info.captures);
+
+		let mut generated = vec![TypedIR {
+			kind: TypedIRKind::Def(self.quotation_name(&info.location)),
+			location: info.location,
+		}];
+		let mut ir = body.into_iter();
+		let mut result = Ok(());
 		while let Some(i) = ir.next() {
+			if let parser::IRKind::End = i.kind {
+				break;
+			}
+			result = self.typecheck_expression(&mut generated, i, &mut ir);
+			if result.is_err() {
+				break;
+			}
+		}
+
+		let returns = self.type_stacks.pop().expect("We pushed one for the quotation");
+		self.type_stacks = enclosing_stacks;
+		self.bind_stack = enclosing_binds;
+		self.loops = enclosing_loops;
+		self.captures.pop();
+		self.diverged = None;
+		result?;
+
+		self.quotations.get_mut(&id).expect("We just read it").resolved = true;
+		if self.generic_depth == 0 {
+			self.lifted.push(generated);
+		}
+
+		let ty = parser::TypeSignature::Function(parameters, returns.clone());
+		let quotation = parser::TypeSignature::Quotation(id);
+		let types = self
+			.type_stacks
+			.iter_mut()
+			.chain(std::iter::once(&mut self.bind_stack))
+			.chain(self.captures.iter_mut())
+			.flatten()
+			.chain(self.variables.values_mut().map(|variable| &mut variable.ty));
+		for t in types {
+			if *t == quotation {
+				*t = ty.clone();
+			}
+		}
+
+		Ok(returns)
+	}
+
+	fn typecheck_enum(&mut self, name: String, ir: &mut IRIter) {
+		let mut variants = Vec::new();
+		for i in ir.by_ref() {
+			match i.kind {
+				parser::IRKind::End => break,
+				parser::IRKind::EnumVariant(variant) => variants.push(variant),
+				_ => unreachable!(),
+			}
+		}
+
+		self.enums.insert(name, variants);
+	}
+
+	fn typecheck_struct(&mut self, name: String, location: Location, ir: &mut IRIter) -> Result<(), Error> {
+		let mut struct_type = StructType::new(location);
+
+		for i in ir.by_ref() {
 			use parser::IRKind::*;
 			match i.kind {
 				End => break,
-				StructField(ty) => {
-					struct_type.field_types.push(ty);
+				StructField(field, ty) => {
+					struct_type.fields.push((field, ty));
 				}
 				_ => unreachable!(),
 			}
@@ -800,14 +2659,93 @@ impl Typer {
 	}
 }
 
+// Enums and ints share a representation but never mix implicitly
+fn with_conversion_help(diagnostic: Diagnostic, a: &parser::TypeSignature, b: &parser::TypeSignature) -> Diagnostic {
+	use parser::TypeSignature::*;
+	match (a, b) {
+		(Enum(name), Int) | (Int, Enum(name)) => diagnostic.with_help(format!(
+			"Convert explicitly with `{}>int` or `int>{}`",
+			name, name
+		)),
+		(Float, Int) | (Int, Float) => diagnostic.with_help("Convert explicitly with `int>float` or `float>int`"),
+		_ => diagnostic,
+	}
+}
+
+#[derive(Clone)]
+struct QuotationInfo {
+	location: Location,
+	captures: Vec<parser::TypeSignature>,
+	resolved: bool,
+}
+
 struct StructType {
-	field_types: Vec<parser::TypeSignature>,
+	fields: Vec<(String, parser::TypeSignature)>,
+	location: Location,
 }
 
 impl StructType {
-	fn new() -> Self {
+	fn new(location: Location) -> Self {
 		Self {
-			field_types: Vec::new(),
+			fields: Vec::new(),
+			location,
+		}
+	}
+}
+
+// Arrays live on the stack and in variables, which are allocated whole, so
+// their size is limited to keep a typo from taking all of the memory
+const MAX_ARRAY_CELLS: usize = 1 << 24;
+
+// Instantiating a generic function can instantiate others, which stops
+// programs like a function calling itself with ever bigger types
+const MAX_INSTANCE_DEPTH: usize = 64;
+
+// What the type variables of a generic function stand for at a call
+#[derive(Default)]
+struct Substitution {
+	types: HashMap<String, parser::TypeSignature>,
+	rows: HashMap<String, Vec<parser::TypeSignature>>,
+}
+
+impl Substitution {
+	fn apply(&self, ty: &parser::TypeSignature) -> parser::TypeSignature {
+		use parser::TypeSignature::*;
+		match ty {
+			Var(name) => self.types.get(name).cloned().unwrap_or_else(|| ty.clone()),
+			Ptr(inner) => Ptr(Box::new(self.apply(inner))),
+			Array(element, len) => Array(Box::new(self.apply(element)), *len),
+			Vector(element) => Vector(Box::new(self.apply(element))),
+			Function(parameters, returns) => Function(self.apply_all(parameters), self.apply_all(returns)),
+			_ => ty.clone(),
+		}
+	}
+
+	fn apply_all(&self, types: &[parser::TypeSignature]) -> Vec<parser::TypeSignature> {
+		let mut applied = Vec::new();
+		for ty in types {
+			match ty {
+				parser::TypeSignature::Row(name) if self.rows.contains_key(name) => applied.extend(self.rows[name].iter().cloned()),
+				_ => applied.push(self.apply(ty)),
+			}
+		}
+		applied
+	}
+}
+
+// The type stack and number of binds at the start of the body of a loop
+struct LoopInfo {
+	type_stack: Vec<parser::TypeSignature>,
+	binds: usize,
+	broken: bool,
+}
+
+impl LoopInfo {
+	fn new(type_stack: Vec<parser::TypeSignature>, binds: usize) -> Self {
+		Self {
+			type_stack,
+			binds,
+			broken: false,
 		}
 	}
 }
@@ -815,15 +2753,21 @@ impl StructType {
 struct FunctionType {
 	parameters: Vec<parser::TypeSignature>,
 	returns: Vec<parser::TypeSignature>,
+	location: Location,
 }
 
 impl FunctionType {
-	fn new() -> Self {
+	fn new(location: Location) -> Self {
 		Self {
 			parameters: Vec::new(),
 			returns: Vec::new(),
+			location,
 		}
 	}
+
+	fn is_generic(&self) -> bool {
+		self.parameters.iter().chain(&self.returns).any(parser::TypeSignature::has_variables)
+	}
 }
 
 struct VariableInfo {
@@ -840,6 +2784,7 @@ impl VariableInfo {
 #[derive(Debug)]
 pub struct TypedIR {
 	pub kind: TypedIRKind,
+	pub location: Location,
 }
 
 #[derive(Debug)]
@@ -847,6 +2792,7 @@ pub enum TypedIRKind {
 	// Literals
 	PushBool(bool),
 	PushInt(i64),
+	PushFloat(f64),
 	PushStr(String),
 
 	// Keywords
@@ -855,8 +2801,13 @@ pub enum TypedIRKind {
 	Elif,
 	Else,
 	While,
+	Match(Vec<Vec<i64>>),
+	Case,
+	CaseDefault,
 	Then,
 	Do,
+	For(usize),
+	Loop,
 	Def(String),
 	Var,
 
@@ -865,8 +2816,19 @@ pub enum TypedIRKind {
 	Over,
 	Drop,
 	Swap,
+	Rot,
+	Unrot,
+	Nip,
+	Tuck,
+	TwoDup,
+	TwoDrop,
+	TwoSwap,
+	TwoOver,
+	Pick(usize),
+	Roll(usize),
 	PrintBool,
 	PrintInt,
+	PrintFloat,
 	PrintStr,
 	PrintPtr,
 	And,
@@ -878,16 +2840,86 @@ pub enum TypedIRKind {
 	Divide,
 	Eq,
 	Neq,
+	StrEq,
+	StrNeq,
 	Lt,
 	Gt,
-	Assign,
-	Load,
+	Modulo,
+	Le,
+	Ge,
+	Negate,
+	BitAnd,
+	BitOr,
+	BitXor,
+	BitNot,
+	Shl,
+	Shr,
+	DivMod,
+	FloatAdd,
+	FloatSubtract,
+	FloatMultiply,
+	FloatDivide,
+	FloatEq,
+	FloatNeq,
+	FloatLt,
+	FloatGt,
+	FloatLe,
+	FloatGe,
+	FloatNegate,
+	IntToFloat,
+	FloatToInt,
+	StrLen,
+	StrConcat,
+	StrSlice,
+	StrFind,
+	StrStartsWith,
+	StrEndsWith,
+	StrCompare,
+	StrSplit,
+	StrTrim,
+	StrToInt,
+	IntToStr,
+	Alloc(usize),
+	Free,
+	Offset(usize),
+	Zero(usize),
+	ArrayGet(usize, usize),
+	ArraySet(usize, usize),
+	CheckIndex(usize),
+	VecNew,
+	VecPush(usize),
+	VecPop(usize),
+	VecGet(usize),
+	VecSet(usize),
+	VecLen,
+	Assign(usize),
+	Load(usize),
 	Call(String),
+	Break(usize),
+	Continue(usize),
+	Return,
+	CallDynamic,
+	PushFunction(String),
+	MakeClosure(String, usize),
+	PushCapture(usize, usize),
+	Field(FieldLayout),
+	FieldUpdate(FieldLayout),
+	CheckEnum(usize),
 	Bind(usize),
 	Unbind(usize),
-	PushBind(usize),
+	PushBind(usize, usize),
 	PushVar(usize),
-	MakeVar(usize)
+	MakeVar(usize, usize),
+	Shuffle(usize, Vec<usize>),
+}
+
+// A field spans `field_width` cells starting `offset` cells into a struct
+// that is `width` cells wide on the stack
+#[derive(Debug, Clone, Copy)]
+pub struct FieldLayout {
+	pub width: usize,
+	pub offset: usize,
+	pub field_width: usize,
 }
 
 pub type TypedChunk = Vec<TypedIR>;